@@ -7,12 +7,19 @@ const CMD_RESIZE = 0x01;
 
 const CMD_OUTPUT = 0x00;
 const CMD_SESSION_ID = 0x10;
+const CMD_SESSION_KEYS = 0x11;
 const CMD_SHELL_EXIT = 0x12;
 const CMD_WINDOW_SIZE = 0x13;
 const CMD_REPLAY_END = 0x14;
+const CMD_IDLE_WARNING = 0x15;
+const CMD_LIFETIME_WARNING = 0x16;
 
 // WebSocket close codes (4000–4999: application-specific)
+const CLOSE_UNAUTHORIZED = 4401;
+const CLOSE_FORBIDDEN = 4403;
 const CLOSE_SESSION_NOT_FOUND = 4404;
+const CLOSE_IDLE_TIMEOUT = 4408;
+const CLOSE_TOO_MANY_SESSIONS = 4429;
 
 const RECONNECT_BASE_MS = 1000;
 const RECONNECT_MAX_MS = 5000;
@@ -139,13 +146,16 @@ const createStatusBar = (readonly) => {
     sbStatus.innerHTML = `<span class="sb-${color}">${STATUS_ICONS[color] || ""}</span> ${label}`;
   };
 
-  const setSid = (sid) => {
-    sbSid.textContent = `\uF489 ${sid.substring(0, 8)}`;
-    sbCopy.disabled = false;
-    sbView.disabled = false;
+  const setSid = (sid, name) => {
+    sbSid.textContent = `\uF489 ${name || sid.substring(0, 8)}`;
   };
 
-  return { setStatus, setSid, sbCopy, sbView, sbNew };
+  const setLinks = ({ control, view }) => {
+    sbCopy.disabled = !control;
+    sbView.disabled = !view;
+  };
+
+  return { setStatus, setSid, setLinks, sbCopy, sbView, sbNew };
 };
 
 // ── Connection ────────────────────────────────────────────────────────
@@ -158,9 +168,22 @@ const connect = (ctx) => {
   let resizeSent = false;
   let shellExited = false;
   let replaying = false;
-  let currentSid = new URLSearchParams(location.search).get("sid");
+  const params = new URLSearchParams(location.search);
+  let currentSid = params.get("sid");
+  let currentKey = params.get("key");
+  let controlKey = readonly ? null : currentKey;
+  let viewKey = readonly ? currentKey : null;
+  const profile = params.get("profile");
+  const sessionName = params.get("session");
   let wsLog = log;
 
+  const sessionUrl = (key, view) => {
+    const query = new URLSearchParams(
+      sessionName ? { session: sessionName, key } : { sid: currentSid, key },
+    );
+    return `/?${query}${view ? "&view" : ""}`;
+  };
+
   const sendResize = () => {
     if (readonly) return;
     if (!resizeSent && ws && ws.readyState === WebSocket.OPEN) {
@@ -172,12 +195,16 @@ const connect = (ctx) => {
   const openWs = () => {
     const protocol = location.protocol === "https:" ? "wss:" : "ws:";
     let wsUrl = `${protocol}//${location.host}/ws`;
-    const sid = new URLSearchParams(location.search).get("sid");
-    if (sid) {
-      wsUrl += `?sid=${encodeURIComponent(sid)}`;
-    }
-    if (readonly) {
-      wsUrl += (sid ? "&" : "?") + "view";
+    if (currentSid) {
+      const query = new URLSearchParams({ sid: currentSid, key: currentKey || "" });
+      wsUrl += `?${query}`;
+    } else if (sessionName) {
+      const query = new URLSearchParams({ session: sessionName });
+      if (currentKey) query.set("key", currentKey);
+      if (profile) query.set("profile", profile);
+      wsUrl += `?${query}`;
+    } else if (profile) {
+      wsUrl += `?${new URLSearchParams({ profile })}`;
     }
     ws = new WebSocket(wsUrl);
     ws.binaryType = "arraybuffer";
@@ -208,8 +235,18 @@ const connect = (ctx) => {
           replaying = true;
           term.reset();
           currentSid = newSid;
-          history.replaceState(null, "", `/?sid=${newSid}${readonly ? "&view" : ""}`);
-          statusBar.setSid(newSid);
+          statusBar.setSid(newSid, sessionName);
+          if (readonly) {
+            history.replaceState(null, "", sessionUrl(currentKey, true));
+            statusBar.setLinks({ control: false, view: !!viewKey });
+          }
+          break;
+        }
+        case CMD_SESSION_KEYS: {
+          [controlKey, viewKey] = new TextDecoder().decode(payload).split(":");
+          currentKey = controlKey;
+          history.replaceState(null, "", sessionUrl(controlKey, false));
+          statusBar.setLinks({ control: true, view: true });
           break;
         }
         case CMD_REPLAY_END:
@@ -227,6 +264,20 @@ const connect = (ctx) => {
             term.resize(cols, rows);
           }
           break;
+        case CMD_IDLE_WARNING: {
+          if (payload.length < 4) break;
+          const secs = new DataView(payload.buffer, payload.byteOffset, 4).getUint32(0);
+          wsLog.info("idle warning:", secs, "s");
+          term.write(`\r\n\x1b[33m[Idle: session times out in ${secs}s without input.]\x1b[0m\r\n`);
+          break;
+        }
+        case CMD_LIFETIME_WARNING: {
+          if (payload.length < 4) break;
+          const secs = new DataView(payload.buffer, payload.byteOffset, 4).getUint32(0);
+          wsLog.info("lifetime warning:", secs, "s");
+          term.write(`\r\n\x1b[33m[Session reaches its maximum lifetime in ${secs}s.]\x1b[0m\r\n`);
+          break;
+        }
         case CMD_SHELL_EXIT:
           shellExited = true;
           wsLog.info("shell exited");
@@ -237,12 +288,36 @@ const connect = (ctx) => {
     };
 
     ws.onclose = (ev) => {
+      if (ev.code === CLOSE_UNAUTHORIZED) {
+        wsLog.warn("unauthorized, code:", ev.code);
+        term.write("\r\n\x1b[90m[Unauthorized.]\x1b[0m\r\n");
+        statusBar.setStatus("unauthorized", "red");
+        return;
+      }
+      if (ev.code === CLOSE_FORBIDDEN) {
+        wsLog.warn("forbidden, code:", ev.code);
+        term.write("\r\n\x1b[90m[Not permitted to open a session.]\x1b[0m\r\n");
+        statusBar.setStatus("forbidden", "red");
+        return;
+      }
       if (ev.code === CLOSE_SESSION_NOT_FOUND) {
         wsLog.warn("session not found, code:", ev.code);
         term.write("\r\n\x1b[90m[Session not found.]\x1b[0m\r\n");
         statusBar.setStatus("no session", "red");
         return;
       }
+      if (ev.code === CLOSE_IDLE_TIMEOUT) {
+        wsLog.info("idle timeout, code:", ev.code);
+        term.write("\r\n\x1b[90m[Disconnected after inactivity. Reload to reconnect.]\x1b[0m\r\n");
+        statusBar.setStatus("idle", "red");
+        return;
+      }
+      if (ev.code === CLOSE_TOO_MANY_SESSIONS) {
+        wsLog.warn("session refused:", ev.reason);
+        term.write(`\r\n\x1b[90m[Cannot open a session: ${ev.reason || "too many sessions"}.]\x1b[0m\r\n`);
+        statusBar.setStatus("too many sessions", "red");
+        return;
+      }
       if (shellExited) return;
       wsLog.info("disconnected, code:", ev.code);
       statusBar.setStatus("reconnecting", "yellow");
@@ -289,14 +364,14 @@ const connect = (ctx) => {
   };
 
   statusBar.sbCopy.addEventListener("click", async () => {
-    if (!currentSid) return;
-    await navigator.clipboard.writeText(`${location.origin}/?sid=${currentSid}`);
+    if (!currentSid || !controlKey) return;
+    await navigator.clipboard.writeText(`${location.origin}${sessionUrl(controlKey, false)}`);
     flashButton(statusBar.sbCopy, "\uF0C1 Copy link");
   });
 
   statusBar.sbView.addEventListener("click", async () => {
-    if (!currentSid) return;
-    await navigator.clipboard.writeText(`${location.origin}/?sid=${currentSid}&view`);
+    if (!currentSid || !viewKey) return;
+    await navigator.clipboard.writeText(`${location.origin}${sessionUrl(viewKey, true)}`);
     flashButton(statusBar.sbView, "\uF06E View link");
   });
 
@@ -0,0 +1,370 @@
+//! Keystroke and session audit log.
+//!
+//! [`AuditLog`] appends one JSON object per line to a file, recording who
+//! created, attached to, detached from and killed which session, every input
+//! payload a client sent, and when the shell exited:
+//!
+//! ```json
+//! {"ts":"2026-01-01T12:00:00.123Z","event":"input","session":"3f2a…","client":"192.0.2.7:51234","user":"alice","access":"control","data":"ls -la\r"}
+//! ```
+//!
+//! Input that is not valid UTF-8 is stored base64-encoded in `data_base64`
+//! instead of `data`.
+//!
+//! Events are handed to a dedicated writer thread, so recording does not
+//! wait for the disk. The queue holds [`QUEUE_LEN`] lines; once the writer is
+//! that far behind, recording waits for it rather than dropping events.
+//! [`AuditLog::flush`] waits until everything queued has been written, for
+//! use before the process exits.
+//!
+//! Each line is written with a single `write` call. When the file would
+//! exceed its size limit it is rotated: `audit.log` becomes `audit.log.1`,
+//! `audit.log.1` becomes `audit.log.2`, and so on. Input may include
+//! passwords, so the log and its rotated files are kept at mode `600`.
+
+use std::fs::File;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+use base64::Engine;
+use serde::Serialize;
+
+use crate::session::Access;
+
+/// Lines queued for the writer before [`AuditLog::record`] waits for it.
+pub const QUEUE_LEN: usize = 4096;
+
+/// What happened in a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditKind {
+    /// A new session (and shell) was created.
+    Create,
+    /// A client attached.
+    Attach,
+    /// A client detached.
+    Detach,
+    /// A client sent terminal input.
+    Input,
+    /// The shell exited.
+    Exit,
+    /// A client terminated the session through the REST API.
+    Kill,
+}
+
+/// A single audit record. Unset fields are omitted from the log line.
+#[derive(Clone, Copy, Debug)]
+pub struct AuditEvent<'a> {
+    /// Event type.
+    pub kind: AuditKind,
+    /// Session ID.
+    pub session: &'a str,
+    /// Client address.
+    pub client: Option<SocketAddr>,
+    /// Authenticated user name.
+    pub user: Option<&'a str>,
+    /// Access level of the client.
+    pub access: Option<Access>,
+    /// Input payload.
+    pub data: Option<&'a [u8]>,
+}
+
+impl<'a> AuditEvent<'a> {
+    /// An event of `kind` in `session`, with no client details.
+    pub fn new(kind: AuditKind, session: &'a str) -> Self {
+        Self {
+            kind,
+            session,
+            client: None,
+            user: None,
+            access: None,
+            data: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    ts: String,
+    event: AuditKind,
+    session: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    access: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_base64: Option<String>,
+}
+
+impl AuditEvent<'_> {
+    fn encode(&self, now: SystemTime) -> Vec<u8> {
+        let (data, data_base64) = match self.data.map(std::str::from_utf8) {
+            Some(Ok(text)) => (Some(text), None),
+            Some(Err(_)) => (
+                None,
+                self.data
+                    .map(|d| base64::engine::general_purpose::STANDARD.encode(d)),
+            ),
+            None => (None, None),
+        };
+        let line = Line {
+            ts: humantime::format_rfc3339_millis(now).to_string(),
+            event: self.kind,
+            session: self.session,
+            client: self.client.map(|addr| addr.to_string()),
+            user: self.user,
+            access: self.access.map(|access| match access {
+                Access::Control => "control",
+                Access::View => "view",
+            }),
+            data,
+            data_base64,
+        };
+        let mut bytes = serde_json::to_vec(&line).expect("audit line serializes");
+        bytes.push(b'\n');
+        bytes
+    }
+}
+
+/// Handle to the audit writer. Cheap to clone; the writer thread exits when
+/// the last handle is dropped.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    tx: mpsc::SyncSender<Message>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Line(Vec<u8>),
+    /// Acknowledge once every line queued before has been written.
+    Flush(mpsc::SyncSender<()>),
+}
+
+impl AuditLog {
+    /// Open (or create) the log at `path` for appending. The file is rotated
+    /// once it would grow past `max_bytes`, keeping `keep` old files.
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let writer = Writer::open(path.into(), max_bytes, keep)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("audit-log".into())
+            .spawn(move || writer.run(rx))?;
+        Ok(Self { tx })
+    }
+
+    /// Queue an event for writing, waiting for room if the queue is full.
+    pub fn record(&self, event: &AuditEvent<'_>) {
+        let line = Message::Line(event.encode(SystemTime::now()));
+        if let Err(mpsc::TrySendError::Full(line)) = self.tx.try_send(line) {
+            tracing::warn!("audit log writer is {QUEUE_LEN} events behind, waiting");
+            let _ = self.tx.send(line);
+        }
+    }
+
+    /// Wait until every event recorded so far has been written. Blocks the
+    /// calling thread.
+    pub fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::sync_channel(1);
+        if self.tx.send(Message::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl Writer {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        // Rotated files written before their mode was restricted.
+        for n in 1..=keep {
+            match std::fs::set_permissions(rotated(&path, n), private()) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<Message>) {
+        for message in rx {
+            match message {
+                Message::Line(line) => {
+                    if let Err(e) = self.write(&line) {
+                        tracing::error!("failed to write audit log {}: {}", self.path.display(), e);
+                    }
+                }
+                Message::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let _ = std::fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1));
+            }
+            std::fs::rename(&self.path, rotated(&self.path, 1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Open `path` for appending, readable and writable by the owner only.
+fn open_append(path: &Path) -> std::io::Result<File> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies to new files.
+    file.set_permissions(private())?;
+    Ok(file)
+}
+
+fn private() -> std::fs::Permissions {
+    std::fs::Permissions::from_mode(0o600)
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tty-web-{name}-{}.log", std::process::id()));
+        for n in 0..4 {
+            let _ = std::fs::remove_file(if n == 0 {
+                path.clone()
+            } else {
+                rotated(&path, n)
+            });
+        }
+        path
+    }
+
+    #[test]
+    fn test_line_format() {
+        let event = AuditEvent {
+            client: Some("192.0.2.7:51234".parse().unwrap()),
+            user: Some("alice"),
+            access: Some(Access::Control),
+            data: Some(b"ls\r"),
+            ..AuditEvent::new(AuditKind::Input, "sid")
+        };
+        let line = event.encode(SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            std::str::from_utf8(&line).unwrap(),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"event\":\"input\",\"session\":\"sid\",\
+             \"client\":\"192.0.2.7:51234\",\"user\":\"alice\",\"access\":\"control\",\
+             \"data\":\"ls\\r\"}\n"
+        );
+
+        let binary = AuditEvent {
+            data: Some(&[0xff, 0x00]),
+            ..AuditEvent::new(AuditKind::Input, "sid")
+        };
+        let line = String::from_utf8(binary.encode(SystemTime::UNIX_EPOCH)).unwrap();
+        assert!(line.contains("\"data_base64\":\"/wA=\""), "{line}");
+        assert!(!line.contains("\"data\""));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let path = temp_path("audit-rotate");
+        // Files left world-readable by an older version are restricted too.
+        std::fs::write(rotated(&path, 2), b"").unwrap();
+        std::fs::set_permissions(rotated(&path, 2), std::fs::Permissions::from_mode(0o644))
+            .unwrap();
+        let mut writer = Writer::open(path.clone(), 10, 2).unwrap();
+        let mode = std::fs::metadata(rotated(&path, 2))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        for line in [b"aaaaaaaa\n", b"bbbbbbbb\n", b"cccccccc\n", b"dddddddd\n"] {
+            writer.write(line).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(
+            std::fs::read_to_string(rotated(&path, 1)).unwrap(),
+            "cccccccc\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(rotated(&path, 2)).unwrap(),
+            "bbbbbbbb\n"
+        );
+        assert!(!rotated(&path, 3).exists(), "only `keep` files are kept");
+        for path in [path.clone(), rotated(&path, 1), rotated(&path, 2)] {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_record_writes_file() {
+        let path = temp_path("audit-record");
+        let log = AuditLog::open(&path, 1 << 20, 1).unwrap();
+        log.record(&AuditEvent::new(AuditKind::Create, "s1"));
+        log.record(&AuditEvent::new(AuditKind::Exit, "s1"));
+        log.flush();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let events: Vec<&str> = contents.lines().collect();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains("\"event\":\"create\""));
+        assert!(events[1].contains("\"event\":\"exit\""));
+    }
+
+    #[test]
+    fn test_full_queue_keeps_events() {
+        let path = temp_path("audit-full");
+        let log = AuditLog::open(&path, 1 << 30, 1).unwrap();
+        for _ in 0..QUEUE_LEN + 10 {
+            log.record(&AuditEvent::new(AuditKind::Attach, "s1"));
+        }
+        log.flush();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), QUEUE_LEN + 10);
+    }
+}
@@ -0,0 +1,281 @@
+//! Per-session cgroup v2 resource limits.
+//!
+//! When configured, every shell is placed in its own child cgroup under a
+//! delegated parent (e.g. a systemd unit with `Delegate=yes`) before `exec`,
+//! so a runaway build in one session cannot starve the host. The cgroup is
+//! killed and removed when the [`Terminal`](crate::terminal::Terminal) is
+//! dropped, which also takes out any daemonized leftovers.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `cpu.max` period in microseconds.
+const CPU_PERIOD_USEC: u64 = 100_000;
+/// How long to wait for killed processes to leave before giving up on
+/// removing a cgroup.
+const REMOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where to create session cgroups and which limits to apply.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CgroupConfig {
+    /// Delegated cgroup directory (e.g. `/sys/fs/cgroup/tty-web.service/sessions`).
+    pub parent: PathBuf,
+    /// `memory.max` in bytes.
+    pub memory_max: Option<u64>,
+    /// `cpu.max` in CPUs (e.g. `1.5`).
+    pub cpu_max: Option<f64>,
+    /// `pids.max`.
+    pub pids_max: Option<u64>,
+}
+
+impl CgroupConfig {
+    /// Session cgroups under `parent` without any limits.
+    pub fn new(parent: impl Into<PathBuf>) -> Self {
+        Self {
+            parent: parent.into(),
+            memory_max: None,
+            cpu_max: None,
+            pids_max: None,
+        }
+    }
+
+    /// Controllers needed for the configured limits.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        controllers
+    }
+
+    /// Check that the parent is a cgroup v2 directory and enable the
+    /// controllers required by the limits for its children.
+    ///
+    /// Call once at startup. Enabling controllers fails if the parent itself
+    /// contains processes (e.g. tty-web), so delegate a dedicated subtree.
+    pub fn prepare(&self) -> std::io::Result<()> {
+        let available =
+            std::fs::read_to_string(self.parent.join("cgroup.controllers")).map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{} is not a cgroup v2 directory: {e}",
+                        self.parent.display()
+                    ),
+                )
+            })?;
+        let enabled = std::fs::read_to_string(self.parent.join("cgroup.subtree_control"))?;
+        for controller in self.controllers() {
+            if enabled.split_whitespace().any(|c| c == controller) {
+                continue;
+            }
+            if !available.split_whitespace().any(|c| c == controller) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!(
+                        "{} controller is not available in {}",
+                        controller,
+                        self.parent.display()
+                    ),
+                ));
+            }
+            write_file(
+                &self.parent.join("cgroup.subtree_control"),
+                &format!("+{controller}"),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Resource usage of a session cgroup. Fields are `None` when the
+/// corresponding controller is not enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CgroupUsage {
+    /// `memory.current` in bytes.
+    pub memory_bytes: Option<u64>,
+    /// Total CPU time (`usage_usec` from `cpu.stat`).
+    pub cpu_usec: Option<u64>,
+    /// `pids.current`.
+    pub pids: Option<u64>,
+}
+
+/// A per-session child cgroup, killed and removed on drop.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a uniquely named child of `config.parent` and apply the limits.
+    pub fn create(config: &CgroupConfig) -> std::io::Result<Self> {
+        let name = format!("tty-web-{}", uuid::Uuid::new_v4().simple());
+        let path = config.parent.join(name);
+        std::fs::create_dir(&path)?;
+        let cgroup = Self { path };
+
+        if let Some(bytes) = config.memory_max {
+            write_file(&cgroup.path.join("memory.max"), &bytes.to_string())?;
+        }
+        if let Some(cpus) = config.cpu_max {
+            let quota = ((cpus * CPU_PERIOD_USEC as f64) as u64).max(1000);
+            write_file(
+                &cgroup.path.join("cpu.max"),
+                &format!("{quota} {CPU_PERIOD_USEC}"),
+            )?;
+        }
+        if let Some(pids) = config.pids_max {
+            write_file(&cgroup.path.join("pids.max"), &pids.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    /// Take over a session cgroup created earlier, e.g. by a previous
+    /// tty-web process that [handed off](crate::handoff) its sessions. It is
+    /// removed on drop like one made by [`Cgroup::create`].
+    pub fn adopt(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Directory of this cgroup.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open `cgroup.procs` for writing. Writing `0` to it moves the calling
+    /// process into the cgroup, which the forked child does before `exec`.
+    pub(crate) fn open_procs(&self) -> std::io::Result<File> {
+        File::options()
+            .write(true)
+            .open(self.path.join("cgroup.procs"))
+    }
+
+    /// Current resource usage.
+    pub fn usage(&self) -> CgroupUsage {
+        let read = |file: &str| {
+            std::fs::read_to_string(self.path.join(file))
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+        };
+        let cpu_usec = std::fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|v| v.trim().parse().ok())
+            });
+        CgroupUsage {
+            memory_bytes: read("memory.current"),
+            cpu_usec,
+            pids: read("pids.current"),
+        }
+    }
+
+    /// Kill every process left in the cgroup and remove it.
+    ///
+    /// Blocks for up to a second while the kernel reaps the killed processes.
+    fn remove(&self) -> std::io::Result<()> {
+        // cgroup.kill needs Linux 5.14; the shell is already gone anyway.
+        let _ = write_file(&self.path.join("cgroup.kill"), "1");
+        let deadline = std::time::Instant::now() + REMOVE_TIMEOUT;
+        loop {
+            match std::fs::remove_dir(&self.path) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) if std::time::Instant::now() >= deadline => return Err(e),
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            tracing::warn!("failed to remove cgroup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Parse a `cpu.max` limit in CPUs, such as `1.5`.
+pub fn parse_cpus(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|cpus| cpus.is_finite() && *cpus > 0.0)
+        .ok_or_else(|| format!("invalid CPU count {s:?}, expected a number above 0 such as 1.5"))
+}
+
+fn write_file(path: &Path, value: &str) -> std::io::Result<()> {
+    let mut file = File::options().write(true).open(path)?;
+    file.write_all(value.as_bytes()).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("writing {value:?} to {}: {e}", path.display()),
+        )
+    })
+}
+
+/// A cgroup v2 directory tests may create children in, if this machine has
+/// one.
+#[cfg(test)]
+pub(crate) fn writable_test_parent() -> Option<PathBuf> {
+    ["/sys/fs/cgroup", "/sys/fs/cgroup/unified"]
+        .into_iter()
+        .map(PathBuf::from)
+        .find(|p| p.join("cgroup.controllers").exists())
+        .filter(|p| {
+            let probe = p.join(format!("tty-web-probe-{}", std::process::id()));
+            std::fs::create_dir(&probe).is_ok() && std::fs::remove_dir(&probe).is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controllers() {
+        let mut config = CgroupConfig::new("/x");
+        assert!(config.controllers().is_empty());
+        config.memory_max = Some(1);
+        config.pids_max = Some(1);
+        assert_eq!(config.controllers(), ["memory", "pids"]);
+    }
+
+    #[test]
+    fn test_parse_cpus() {
+        assert_eq!(parse_cpus("1.5"), Ok(1.5));
+        assert_eq!(parse_cpus("2"), Ok(2.0));
+        for bad in ["0", "-1", "NaN", "inf", "", "two"] {
+            assert!(parse_cpus(bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a writable cgroup v2 hierarchy; run with --ignored"]
+    fn test_create_and_remove() {
+        let parent = writable_test_parent().expect("no writable cgroup v2 hierarchy");
+        let cgroup = Cgroup::create(&CgroupConfig::new(&parent)).unwrap();
+        let path = cgroup.path().to_owned();
+        assert!(path.join("cgroup.procs").exists());
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("60")
+            .spawn()
+            .unwrap();
+        write_file(&path.join("cgroup.procs"), &child.id().to_string()).unwrap();
+        assert!(cgroup.usage().cpu_usec.is_some());
+
+        drop(cgroup);
+        assert!(!path.exists(), "cgroup should be removed");
+        assert!(child.wait().unwrap().code().is_none(), "sleep was killed");
+    }
+}
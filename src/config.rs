@@ -2,9 +2,17 @@
 
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 use clap::{Parser, ValueEnum};
 
+use crate::filter::OutputPolicy;
+use crate::limits::{ProcessLimits, parse_profile, parse_profile_user};
+use crate::session::{IdleAction, IdlePolicy};
+use crate::size::parse_size;
+use crate::web::peer::{CertPattern, parse_cert_pattern};
+
 /// Log output format.
 #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogFormat {
@@ -20,6 +28,7 @@ pub enum LogFormat {
 /// variable (`TTY_WEB_ADDRESS`). Defaults are suitable for local development.
 #[derive(Parser, Debug, Clone)]
 #[command(name = "tty-web", about = "Web-based terminal emulator")]
+#[command(group(clap::ArgGroup::new("jwt_key").args(["jwt_secret", "jwt_secret_file", "jwt_jwks"])))]
 pub struct Config {
     /// Address to bind to
     #[arg(long, default_value = "127.0.0.1", env = "TTY_WEB_ADDRESS")]
@@ -49,14 +58,356 @@ pub struct Config {
     #[arg(long, default_value_t = 256, env = "TTY_WEB_SCROLLBACK_LIMIT")]
     pub scrollback_limit: usize,
 
+    /// Compress older scrollback so the limit holds more history
+    #[arg(long, env = "TTY_WEB_SCROLLBACK_COMPRESSION")]
+    pub scrollback_compression: bool,
+
+    /// Spool scrollback evicted from memory to files in this directory
+    #[arg(long, env = "TTY_WEB_SCROLLBACK_SPOOL")]
+    pub scrollback_spool: Option<PathBuf>,
+
+    /// Maximum size of one session's scrollback spool (K/M/G/T suffix allowed)
+    #[arg(long, env = "TTY_WEB_SCROLLBACK_SPOOL_MAX_SIZE", default_value = "16M", value_parser = parse_size, requires = "scrollback_spool")]
+    pub scrollback_spool_max_size: u64,
+
     /// Session orphan timeout in seconds — remove session after this long with no clients
     #[arg(long, default_value_t = 60, env = "TTY_WEB_ORPHAN_TIMEOUT")]
     pub orphan_timeout: u64,
+
+    /// Seconds without input before --idle-action is taken (disabled if unset or 0)
+    #[arg(long, env = "TTY_WEB_IDLE_TIMEOUT")]
+    pub idle_timeout: Option<u64>,
+
+    /// Seconds before the idle timeout at which clients are warned, shorter than the timeout [default: 60, or a quarter of the timeout if shorter]
+    #[arg(long, env = "TTY_WEB_IDLE_WARNING", requires = "idle_timeout")]
+    pub idle_warning: Option<u64>,
+
+    /// What to do with idle sessions: hangup (end the shell) or detach (disconnect clients)
+    #[arg(
+        long,
+        default_value = "hangup",
+        env = "TTY_WEB_IDLE_ACTION",
+        requires = "idle_timeout"
+    )]
+    pub idle_action: IdleAction,
+
+    /// Seconds after which a session's shell is terminated regardless of activity (unlimited if unset or 0)
+    #[arg(long, env = "TTY_WEB_MAX_SESSION_LIFETIME")]
+    pub max_session_lifetime: Option<u64>,
+
+    /// Access token required on the WebSocket and static routes
+    #[arg(long, env = "TTY_WEB_AUTH_TOKEN", hide_env_values = true)]
+    pub auth_token: Option<String>,
+
+    /// File containing the access token (alternative to --auth-token)
+    #[arg(long, env = "TTY_WEB_AUTH_TOKEN_FILE", conflicts_with = "auth_token")]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// htpasswd file (bcrypt or argon2 hashes) for HTTP Basic authentication
+    #[arg(long, env = "TTY_WEB_HTPASSWD")]
+    pub htpasswd: Option<PathBuf>,
+
+    /// HMAC secret for verifying JWTs presented as the access token
+    #[arg(long, env = "TTY_WEB_JWT_SECRET", hide_env_values = true)]
+    pub jwt_secret: Option<String>,
+
+    /// File containing the JWT HMAC secret (alternative to --jwt-secret)
+    #[arg(long, env = "TTY_WEB_JWT_SECRET_FILE")]
+    pub jwt_secret_file: Option<PathBuf>,
+
+    /// JWKS file with the public keys for verifying JWTs
+    #[arg(long, env = "TTY_WEB_JWT_JWKS")]
+    pub jwt_jwks: Option<PathBuf>,
+
+    /// Accepted JWT audience (repeatable); not checked if unset
+    #[arg(
+        long,
+        env = "TTY_WEB_JWT_AUDIENCE",
+        value_delimiter = ',',
+        requires = "jwt_key"
+    )]
+    pub jwt_audience: Vec<String>,
+
+    /// Accepted JWT issuer (repeatable); not checked if unset
+    #[arg(
+        long,
+        env = "TTY_WEB_JWT_ISSUER",
+        value_delimiter = ',',
+        requires = "jwt_key"
+    )]
+    pub jwt_issuer: Vec<String>,
+
+    /// JWT claim holding the user name
+    #[arg(long, env = "TTY_WEB_JWT_USER_CLAIM", default_value = "sub")]
+    pub jwt_user_claim: String,
+
+    /// JWT claim naming the unix user new sessions run as
+    #[arg(long, env = "TTY_WEB_JWT_RUN_AS_CLAIM", requires = "jwt_key")]
+    pub jwt_run_as_claim: Option<String>,
+
+    /// Unix user --jwt-run-as-claim may name besides --user-map targets (repeatable)
+    #[arg(
+        long,
+        env = "TTY_WEB_JWT_RUN_AS_ALLOW",
+        value_delimiter = ',',
+        requires = "jwt_run_as_claim"
+    )]
+    pub jwt_run_as_allow: Vec<String>,
+
+    /// JWT claim naming the limit profile for new sessions
+    #[arg(long, env = "TTY_WEB_JWT_PROFILE_CLAIM", requires = "jwt_key")]
+    pub jwt_profile_claim: Option<String>,
+
+    /// PEM certificate chain; enables HTTPS/WSS (reloaded on SIGHUP or change)
+    #[arg(long, env = "TTY_WEB_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "TTY_WEB_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Also listen on this port for plain HTTP and redirect to HTTPS
+    #[arg(long, env = "TTY_WEB_TLS_REDIRECT_PORT", requires = "tls_cert")]
+    pub tls_redirect_port: Option<u16>,
+
+    /// PEM CA certificates; clients must present a certificate issued by one of them
+    #[arg(long, env = "TTY_WEB_TLS_CLIENT_CA", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Client certificate allowed to open sessions, as cn:NAME, email:NAME, dns:NAME, uri:NAME or a subject DN such as CN=alice (repeatable)
+    #[arg(
+        long = "tls-client-allow",
+        env = "TTY_WEB_TLS_CLIENT_ALLOW",
+        value_delimiter = ';',
+        value_parser = parse_cert_pattern,
+        requires = "tls_client_ca"
+    )]
+    pub tls_client_allow: Vec<CertPattern>,
+
+    /// Listen on this unix domain socket instead of --address/--port
+    #[arg(long, env = "TTY_WEB_UNIX_SOCKET", conflicts_with = "tls_cert")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// File mode of --unix-socket, in octal
+    #[arg(long, env = "TTY_WEB_UNIX_SOCKET_MODE", default_value = "660", value_parser = crate::unix_socket::parse_mode, requires = "unix_socket")]
+    pub unix_socket_mode: u32,
+
+    /// Owner of --unix-socket (name or UID)
+    #[arg(long, env = "TTY_WEB_UNIX_SOCKET_OWNER", requires = "unix_socket")]
+    pub unix_socket_owner: Option<String>,
+
+    /// Group of --unix-socket (name or GID)
+    #[arg(long, env = "TTY_WEB_UNIX_SOCKET_GROUP", requires = "unix_socket")]
+    pub unix_socket_group: Option<String>,
+
+    /// Extra origin allowed to open WebSockets (repeatable, `*` for any); same-origin is always allowed
+    #[arg(
+        long = "allowed-origin",
+        env = "TTY_WEB_ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub allowed_origins: Vec<String>,
+
+    /// Extra command `POST /api/v1/sessions` may start instead of --shell (repeatable)
+    #[arg(
+        long = "api-command",
+        env = "TTY_WEB_API_COMMANDS",
+        value_delimiter = ','
+    )]
+    pub api_commands: Vec<String>,
+
+    /// Environment variable `POST /api/v1/sessions` may set (repeatable)
+    #[arg(long = "api-env", env = "TTY_WEB_API_ENV", value_delimiter = ',')]
+    pub api_env: Vec<String>,
+
+    /// User who may list and kill every session via the REST API, not just their own: alice for Basic, jwt:alice or cert:alice (repeatable)
+    #[arg(long = "admin", env = "TTY_WEB_ADMINS", value_delimiter = ',')]
+    pub admins: Vec<String>,
+
+    /// Content-Security-Policy for served pages (default allows only self and the xterm.js CDN)
+    #[arg(long, env = "TTY_WEB_CSP")]
+    pub csp: Option<String>,
+
+    /// Origin allowed to embed tty-web in a frame (repeatable); same-origin is always allowed
+    #[arg(
+        long = "frame-ancestor",
+        env = "TTY_WEB_FRAME_ANCESTORS",
+        value_delimiter = ','
+    )]
+    pub frame_ancestors: Vec<String>,
+
+    /// Referrer-Policy for served pages
+    #[arg(long, env = "TTY_WEB_REFERRER_POLICY", default_value = crate::web::headers::DEFAULT_REFERRER_POLICY)]
+    pub referrer_policy: String,
+
+    /// Run session shells as this unix user (name or UID); requires root
+    #[arg(long, env = "TTY_WEB_RUN_AS_USER")]
+    pub run_as_user: Option<String>,
+
+    /// Primary group for session shells (name or GID; default: the user's group)
+    #[arg(long, env = "TTY_WEB_RUN_AS_GROUP")]
+    pub run_as_group: Option<String>,
+
+    /// Map an authenticated user to a unix user, as identity=user (repeatable)
+    #[arg(long = "user-map", env = "TTY_WEB_USER_MAP", value_delimiter = ',')]
+    pub user_map: Vec<String>,
+
+    /// Start each shell in new user, PID, mount, UTS and IPC namespaces (Linux)
+    #[arg(long, env = "TTY_WEB_ISOLATE")]
+    pub isolate: bool,
+
+    /// With --isolate, also give each shell its own network namespace (loopback only)
+    #[arg(long, env = "TTY_WEB_ISOLATE_NETWORK", requires = "isolate")]
+    pub isolate_network: bool,
+
+    /// Delegated cgroup v2 directory; each session gets its own child cgroup
+    #[arg(long, env = "TTY_WEB_CGROUP_PARENT")]
+    pub cgroup_parent: Option<PathBuf>,
+
+    /// Memory limit per session (bytes, or with K/M/G/T suffix)
+    #[arg(long, env = "TTY_WEB_CGROUP_MEMORY_MAX", requires = "cgroup_parent", value_parser = parse_size)]
+    pub cgroup_memory_max: Option<u64>,
+
+    /// CPU limit per session in CPUs (e.g. 1.5)
+    #[arg(long, env = "TTY_WEB_CGROUP_CPU_MAX", value_parser = crate::cgroup::parse_cpus, requires = "cgroup_parent")]
+    pub cgroup_cpu_max: Option<f64>,
+
+    /// Maximum number of processes per session
+    #[arg(long, env = "TTY_WEB_CGROUP_PIDS_MAX", requires = "cgroup_parent")]
+    pub cgroup_pids_max: Option<u64>,
+
+    /// rlimits and priorities for session shells, e.g. `nproc=256,nofile=1024,nice=10`
+    #[arg(long, env = "TTY_WEB_LIMITS", value_parser = ProcessLimits::from_str)]
+    pub limits: Option<ProcessLimits>,
+
+    /// Named override of --limits, as name:spec (repeatable)
+    #[arg(long = "limit-profile", env = "TTY_WEB_LIMIT_PROFILES", value_delimiter = ';', value_parser = parse_profile)]
+    pub limit_profiles: Vec<(String, ProcessLimits)>,
+
+    /// Let an authenticated user select a --limit-profile, as identity=profile (repeatable)
+    #[arg(long = "limit-profile-user", env = "TTY_WEB_LIMIT_PROFILE_USERS", value_delimiter = ',', value_parser = parse_profile_user)]
+    pub limit_profile_users: Vec<(String, String)>,
+
+    /// Maximum number of concurrent sessions
+    #[arg(long, env = "TTY_WEB_MAX_SESSIONS")]
+    pub max_sessions: Option<usize>,
+
+    /// Maximum number of concurrent sessions created from one client IP (IPv6: per /64)
+    #[arg(long, env = "TTY_WEB_MAX_SESSIONS_PER_IP")]
+    pub max_sessions_per_ip: Option<usize>,
+
+    /// Sessions per minute a client IP may create (token bucket)
+    #[arg(long, env = "TTY_WEB_SESSION_RATE", value_parser = clap::value_parser!(u32).range(1..))]
+    pub session_rate: Option<u32>,
+
+    /// Sessions a client IP may create back to back (default: --session-rate)
+    #[arg(long, env = "TTY_WEB_SESSION_BURST", requires = "session_rate", value_parser = clap::value_parser!(u32).range(1..))]
+    pub session_burst: Option<u32>,
+
+    /// Control strings to drop or show to control clients only, as key=action pairs (e.g. osc52=drop,dcs=drop)
+    #[arg(long, env = "TTY_WEB_OUTPUT_FILTER", default_value = "osc52=interactive", value_parser = OutputPolicy::from_str)]
+    pub output_filter: OutputPolicy,
+
+    /// Append session events and every keystroke as JSON lines to this file
+    #[arg(long, env = "TTY_WEB_AUDIT_LOG")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log when it reaches this size (K/M/G/T suffix allowed)
+    #[arg(long, env = "TTY_WEB_AUDIT_LOG_MAX_SIZE", default_value = "100M", value_parser = parse_size)]
+    pub audit_log_max_size: u64,
+
+    /// Number of rotated audit log files to keep
+    #[arg(long, env = "TTY_WEB_AUDIT_LOG_KEEP", default_value_t = 5)]
+    pub audit_log_keep: usize,
+
+    /// Unix socket used to hand sessions to a new process on SIGUSR2
+    #[arg(long, env = "TTY_WEB_HANDOFF_SOCKET")]
+    pub handoff_socket: Option<PathBuf>,
+
+    /// Wait on --handoff-socket for a running server's sessions before serving
+    #[arg(long, env = "TTY_WEB_TAKEOVER", requires = "handoff_socket")]
+    pub takeover: bool,
+
+    /// Seconds to wait for a hand-off with --takeover
+    #[arg(
+        long,
+        env = "TTY_WEB_TAKEOVER_TIMEOUT",
+        default_value_t = 60,
+        requires = "takeover"
+    )]
+    pub takeover_timeout: u64,
+}
+
+impl Config {
+    /// The maximum session lifetime from `--max-session-lifetime`, if any.
+    pub fn max_session_lifetime(&self) -> Option<Duration> {
+        self.max_session_lifetime
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// The idle policy from `--idle-timeout`, `--idle-warning` and
+    /// `--idle-action`, if idle sessions are to be acted on. A warning that
+    /// is not shorter than the timeout is an error.
+    pub fn idle_policy(&self) -> Result<Option<IdlePolicy>, String> {
+        let Some(timeout) = self.idle_timeout.filter(|&secs| secs > 0) else {
+            return Ok(None);
+        };
+        let mut policy = IdlePolicy {
+            action: self.idle_action,
+            ..IdlePolicy::new(Duration::from_secs(timeout))
+        };
+        if let Some(warning) = self.idle_warning {
+            if warning >= timeout {
+                return Err(format!(
+                    "--idle-warning {warning} must be shorter than --idle-timeout {timeout}"
+                ));
+            }
+            policy.warning = Duration::from_secs(warning);
+        }
+        Ok(Some(policy))
+    }
+
+    /// Resolve the JWT HMAC secret from `--jwt-secret` or `--jwt-secret-file`.
+    pub fn jwt_secret(&self) -> std::io::Result<Option<String>> {
+        let secret = match (&self.jwt_secret, &self.jwt_secret_file) {
+            (Some(secret), _) => secret.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)?.trim().to_owned(),
+            (None, None) => return Ok(None),
+        };
+        if secret.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "JWT secret is empty",
+            ));
+        }
+        Ok(Some(secret))
+    }
+
+    /// Resolve the access token from `--auth-token` or `--auth-token-file`.
+    ///
+    /// Surrounding whitespace (e.g. a trailing newline) is stripped from the
+    /// file contents. An empty token is an error.
+    pub fn auth_token(&self) -> std::io::Result<Option<String>> {
+        let token = match (&self.auth_token, &self.auth_token_file) {
+            (Some(token), _) => token.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)?.trim().to_owned(),
+            (None, None) => return Ok(None),
+        };
+        if token.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "auth token is empty",
+            ));
+        }
+        Ok(Some(token))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filter::Action;
 
     #[test]
     fn test_default_values() {
@@ -68,7 +419,47 @@ mod tests {
         assert_eq!(config.log_format, LogFormat::Text);
         assert_eq!(config.pwd, None);
         assert_eq!(config.scrollback_limit, 256);
+        assert!(!config.scrollback_compression);
+        assert_eq!(config.scrollback_spool, None);
+        assert_eq!(config.scrollback_spool_max_size, 16 << 20);
         assert_eq!(config.orphan_timeout, 60);
+        assert_eq!(config.idle_policy(), Ok(None));
+        assert_eq!(config.max_session_lifetime(), None);
+        assert_eq!(config.auth_token().unwrap(), None);
+        assert_eq!(config.htpasswd, None);
+        assert_eq!(config.jwt_secret().unwrap(), None);
+        assert_eq!(config.jwt_jwks, None);
+        assert_eq!(config.jwt_user_claim, "sub");
+        assert_eq!(config.tls_cert, None);
+        assert_eq!(config.tls_redirect_port, None);
+        assert_eq!(config.tls_client_ca, None);
+        assert!(config.tls_client_allow.is_empty());
+        assert_eq!(config.unix_socket, None);
+        assert_eq!(config.unix_socket_mode, 0o660);
+        assert!(config.allowed_origins.is_empty());
+        assert!(config.api_commands.is_empty());
+        assert!(config.api_env.is_empty());
+        assert!(config.admins.is_empty());
+        assert_eq!(config.csp, None);
+        assert!(config.frame_ancestors.is_empty());
+        assert_eq!(config.referrer_policy, "no-referrer");
+        assert_eq!(config.run_as_user, None);
+        assert!(config.user_map.is_empty());
+        assert!(!config.isolate);
+        assert!(!config.isolate_network);
+        assert_eq!(config.cgroup_parent, None);
+        assert_eq!(config.limits, None);
+        assert!(config.limit_profiles.is_empty());
+        assert!(config.limit_profile_users.is_empty());
+        assert_eq!(config.max_sessions, None);
+        assert_eq!(config.session_rate, None);
+        assert_eq!(config.output_filter, "osc52=interactive".parse().unwrap());
+        assert_eq!(config.audit_log, None);
+        assert_eq!(config.audit_log_max_size, 100 << 20);
+        assert_eq!(config.audit_log_keep, 5);
+        assert_eq!(config.handoff_socket, None);
+        assert!(!config.takeover);
+        assert_eq!(config.takeover_timeout, 60);
     }
 
     #[test]
@@ -98,4 +489,410 @@ mod tests {
         let config = Config::parse_from(["tty-web", "--pwd", "/tmp"]);
         assert_eq!(config.pwd, Some(PathBuf::from("/tmp")));
     }
+
+    #[test]
+    fn test_auth_token_flag() {
+        let config = Config::parse_from(["tty-web", "--auth-token", "secret"]);
+        assert_eq!(config.auth_token().unwrap(), Some("secret".to_owned()));
+    }
+
+    #[test]
+    fn test_auth_token_file() {
+        let path = std::env::temp_dir().join(format!("tty-web-token-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let config = Config::parse_from(["tty-web", "--auth-token-file", path.to_str().unwrap()]);
+        assert_eq!(config.auth_token().unwrap(), Some("from-file".to_owned()));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_auth_token_conflicts_with_file() {
+        let result =
+            Config::try_parse_from(["tty-web", "--auth-token", "a", "--auth-token-file", "/x"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_allowed_origin_repeatable() {
+        let config = Config::parse_from([
+            "tty-web",
+            "--allowed-origin",
+            "https://a.example",
+            "--allowed-origin",
+            "https://b.example",
+        ]);
+        assert_eq!(
+            config.allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn test_api_flags() {
+        let config = Config::parse_from([
+            "tty-web",
+            "--api-command",
+            "/usr/bin/htop",
+            "--api-command",
+            "/usr/bin/tmux",
+            "--api-env",
+            "LANG,TZ",
+            "--admin",
+            "alice",
+        ]);
+        assert_eq!(config.api_commands, ["/usr/bin/htop", "/usr/bin/tmux"]);
+        assert_eq!(config.api_env, ["LANG", "TZ"]);
+        assert_eq!(config.admins, ["alice"]);
+    }
+
+    #[test]
+    fn test_tls_flags_require_each_other() {
+        assert!(Config::try_parse_from(["tty-web", "--tls-cert", "/c.pem"]).is_err());
+        assert!(Config::try_parse_from(["tty-web", "--tls-redirect-port", "80"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--tls-cert",
+            "/c.pem",
+            "--tls-key",
+            "/k.pem",
+            "--tls-redirect-port",
+            "8080",
+        ]);
+        assert_eq!(config.tls_cert, Some(PathBuf::from("/c.pem")));
+        assert_eq!(config.tls_key, Some(PathBuf::from("/k.pem")));
+        assert_eq!(config.tls_redirect_port, Some(8080));
+    }
+
+    #[test]
+    fn test_idle_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--idle-action", "detach"]).is_err());
+        assert!(
+            Config::try_parse_from(["tty-web", "--idle-timeout", "60", "--idle-action", "lock"])
+                .is_err()
+        );
+        let config = Config::parse_from(["tty-web", "--idle-timeout", "900"]);
+        assert_eq!(
+            config.idle_policy(),
+            Ok(Some(IdlePolicy::new(Duration::from_secs(900))))
+        );
+        let config = Config::parse_from(["tty-web", "--idle-timeout", "40"]);
+        assert_eq!(
+            config.idle_policy().unwrap().unwrap().warning,
+            Duration::from_secs(10)
+        );
+        let config =
+            Config::parse_from(["tty-web", "--idle-timeout", "60", "--idle-warning", "60"]);
+        assert!(config.idle_policy().is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--idle-timeout",
+            "600",
+            "--idle-warning",
+            "30",
+            "--idle-action",
+            "detach",
+        ]);
+        assert_eq!(
+            config.idle_policy(),
+            Ok(Some(IdlePolicy {
+                timeout: Duration::from_secs(600),
+                warning: Duration::from_secs(30),
+                action: IdleAction::Detach,
+            }))
+        );
+        let config = Config::parse_from(["tty-web", "--idle-timeout", "0"]);
+        assert_eq!(config.idle_policy(), Ok(None));
+    }
+
+    #[test]
+    fn test_max_session_lifetime() {
+        let config = Config::parse_from(["tty-web", "--max-session-lifetime", "28800"]);
+        assert_eq!(
+            config.max_session_lifetime(),
+            Some(Duration::from_secs(8 * 3600))
+        );
+        let config = Config::parse_from(["tty-web", "--max-session-lifetime", "0"]);
+        assert_eq!(config.max_session_lifetime(), None);
+    }
+
+    #[test]
+    fn test_tls_client_flags() {
+        use crate::web::peer::CertName;
+
+        assert!(Config::try_parse_from(["tty-web", "--tls-client-ca", "/ca.pem"]).is_err());
+        assert!(Config::try_parse_from(["tty-web", "--tls-client-allow", "cn:alice"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--tls-cert",
+            "/c.pem",
+            "--tls-key",
+            "/k.pem",
+            "--tls-client-ca",
+            "/ca.pem",
+            "--tls-client-allow",
+            "cn:alice;email:bob@example.com",
+            "--tls-client-allow",
+            "CN=carol, O=Example",
+        ]);
+        assert_eq!(config.tls_client_ca, Some(PathBuf::from("/ca.pem")));
+        assert_eq!(
+            config.tls_client_allow,
+            [
+                CertPattern::Name(CertName::Cn("alice".to_owned())),
+                CertPattern::Name(CertName::Email("bob@example.com".to_owned())),
+                CertPattern::Subject("CN=carol, O=Example".to_owned())
+            ]
+        );
+        let untyped = [
+            "tty-web",
+            "--tls-cert",
+            "/c.pem",
+            "--tls-key",
+            "/k.pem",
+            "--tls-client-ca",
+            "/ca.pem",
+            "--tls-client-allow",
+            "alice",
+        ];
+        assert!(Config::try_parse_from(untyped).is_err());
+    }
+
+    #[test]
+    fn test_isolate_network_requires_isolate() {
+        assert!(Config::try_parse_from(["tty-web", "--isolate-network"]).is_err());
+        let config = Config::parse_from(["tty-web", "--isolate", "--isolate-network"]);
+        assert!(config.isolate);
+        assert!(config.isolate_network);
+    }
+
+    #[test]
+    fn test_cgroup_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--cgroup-pids-max", "10"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--cgroup-parent",
+            "/sys/fs/cgroup/tty-web",
+            "--cgroup-memory-max",
+            "512M",
+            "--cgroup-cpu-max",
+            "1.5",
+        ]);
+        assert_eq!(config.cgroup_memory_max, Some(512 << 20));
+        assert_eq!(config.cgroup_cpu_max, Some(1.5));
+        assert_eq!(config.cgroup_pids_max, None);
+        for cpus in ["0", "-1", "NaN", "inf"] {
+            let parsed = Config::try_parse_from([
+                "tty-web",
+                "--cgroup-parent",
+                "/sys/fs/cgroup/tty-web",
+                "--cgroup-cpu-max",
+                cpus,
+            ]);
+            assert!(
+                parsed.is_err(),
+                "--cgroup-cpu-max {cpus} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_limit_flags() {
+        let config = Config::parse_from([
+            "tty-web",
+            "--limits",
+            "nproc=256,nice=10",
+            "--limit-profile",
+            "build:nproc=1024",
+            "--limit-profile",
+            "idle:ionice=idle",
+            "--limit-profile-user",
+            "alice=build,bob=idle",
+        ]);
+        assert_eq!(config.limits.unwrap().nproc, Some(256));
+        assert_eq!(config.limit_profiles.len(), 2);
+        assert_eq!(config.limit_profiles[0].0, "build");
+        assert_eq!(
+            config.limit_profile_users,
+            [
+                ("alice".to_owned(), "build".to_owned()),
+                ("bob".to_owned(), "idle".to_owned())
+            ]
+        );
+        assert!(Config::try_parse_from(["tty-web", "--limit-profile-user", "alice"]).is_err());
+        assert!(Config::try_parse_from(["tty-web", "--limits", "bogus=1"]).is_err());
+    }
+
+    #[test]
+    fn test_session_limit_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--session-burst", "5"]).is_err());
+        assert!(Config::try_parse_from(["tty-web", "--session-rate", "0"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--max-sessions",
+            "100",
+            "--max-sessions-per-ip",
+            "5",
+            "--session-rate",
+            "10",
+        ]);
+        assert_eq!(config.max_sessions, Some(100));
+        assert_eq!(config.max_sessions_per_ip, Some(5));
+        assert_eq!(config.session_rate, Some(10));
+        assert_eq!(config.session_burst, None);
+    }
+
+    #[test]
+    fn test_audit_log_flags() {
+        let config = Config::parse_from([
+            "tty-web",
+            "--audit-log",
+            "/var/log/tty-web/audit.log",
+            "--audit-log-max-size",
+            "10M",
+            "--audit-log-keep",
+            "0",
+        ]);
+        assert_eq!(
+            config.audit_log,
+            Some(PathBuf::from("/var/log/tty-web/audit.log"))
+        );
+        assert_eq!(config.audit_log_max_size, 10 << 20);
+        assert_eq!(config.audit_log_keep, 0);
+    }
+
+    #[test]
+    fn test_scrollback_compression_flag() {
+        let config = Config::parse_from(["tty-web", "--scrollback-compression"]);
+        assert!(config.scrollback_compression);
+    }
+
+    #[test]
+    fn test_scrollback_spool_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--scrollback-spool-max-size", "1M"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--scrollback-spool",
+            "/var/spool/tty-web",
+            "--scrollback-spool-max-size",
+            "64M",
+        ]);
+        assert_eq!(
+            config.scrollback_spool,
+            Some(PathBuf::from("/var/spool/tty-web"))
+        );
+        assert_eq!(config.scrollback_spool_max_size, 64 << 20);
+    }
+
+    #[test]
+    fn test_handoff_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--takeover"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--handoff-socket",
+            "/run/tty-web/handoff.sock",
+            "--takeover",
+            "--takeover-timeout",
+            "10",
+        ]);
+        assert_eq!(
+            config.handoff_socket,
+            Some(PathBuf::from("/run/tty-web/handoff.sock"))
+        );
+        assert!(config.takeover);
+        assert_eq!(config.takeover_timeout, 10);
+    }
+
+    #[test]
+    fn test_unix_socket_flags() {
+        assert!(Config::try_parse_from(["tty-web", "--unix-socket-owner", "www"]).is_err());
+        assert!(
+            Config::try_parse_from([
+                "tty-web",
+                "--unix-socket",
+                "/run/tty-web.sock",
+                "--tls-cert",
+                "/c.pem",
+                "--tls-key",
+                "/k.pem",
+            ])
+            .is_err()
+        );
+        let config = Config::parse_from([
+            "tty-web",
+            "--unix-socket",
+            "/run/tty-web.sock",
+            "--unix-socket-mode",
+            "0600",
+            "--unix-socket-group",
+            "www-data",
+        ]);
+        assert_eq!(config.unix_socket, Some(PathBuf::from("/run/tty-web.sock")));
+        assert_eq!(config.unix_socket_mode, 0o600);
+        assert_eq!(config.unix_socket_group.as_deref(), Some("www-data"));
+        assert_eq!(config.unix_socket_owner, None);
+    }
+
+    #[test]
+    fn test_output_filter_flag() {
+        let config = Config::parse_from(["tty-web", "--output-filter", "osc=drop,dcs=interactive"]);
+        assert_eq!(config.output_filter.osc, Action::Drop);
+        assert_eq!(config.output_filter.dcs, Action::Interactive);
+        assert!(config.output_filter.osc_codes.is_empty());
+        let config = Config::parse_from(["tty-web", "--output-filter", ""]);
+        assert_eq!(config.output_filter, OutputPolicy::default());
+        assert!(Config::try_parse_from(["tty-web", "--output-filter", "osc52=block"]).is_err());
+    }
+
+    #[test]
+    fn test_security_header_flags() {
+        let config = Config::parse_from([
+            "tty-web",
+            "--csp",
+            "default-src 'self'",
+            "--frame-ancestor",
+            "https://portal.example.com",
+            "--frame-ancestor",
+            "https://admin.example.com",
+            "--referrer-policy",
+            "same-origin",
+        ]);
+        assert_eq!(config.csp.as_deref(), Some("default-src 'self'"));
+        assert_eq!(
+            config.frame_ancestors,
+            ["https://portal.example.com", "https://admin.example.com"]
+        );
+        assert_eq!(config.referrer_policy, "same-origin");
+    }
+
+    #[test]
+    fn test_jwt_flags() {
+        assert!(
+            Config::try_parse_from(["tty-web", "--jwt-secret", "s", "--jwt-jwks", "/k.json"])
+                .is_err()
+        );
+        assert!(Config::try_parse_from(["tty-web", "--jwt-audience", "tty-web"]).is_err());
+        let config = Config::parse_from([
+            "tty-web",
+            "--jwt-jwks",
+            "/etc/tty-web/jwks.json",
+            "--jwt-audience",
+            "tty-web",
+            "--jwt-issuer",
+            "https://portal.example.com",
+            "--jwt-run-as-claim",
+            "unix_user",
+            "--jwt-run-as-allow",
+            "dev,ops",
+        ]);
+        assert_eq!(
+            config.jwt_jwks,
+            Some(PathBuf::from("/etc/tty-web/jwks.json"))
+        );
+        assert_eq!(config.jwt_audience, ["tty-web"]);
+        assert_eq!(config.jwt_issuer, ["https://portal.example.com"]);
+        assert_eq!(config.jwt_run_as_claim.as_deref(), Some("unix_user"));
+        assert_eq!(config.jwt_run_as_allow, ["dev", "ops"]);
+        assert!(Config::try_parse_from(["tty-web", "--jwt-run-as-allow", "dev"]).is_err());
+        assert_eq!(config.jwt_profile_claim, None);
+    }
 }
@@ -0,0 +1,463 @@
+//! Filtering of control strings in shell output.
+//!
+//! Shell output is forwarded to every attached browser, so any program in a
+//! session can emit escape sequences that act on the client side — OSC 52,
+//! for example, overwrites the clipboard of everyone watching. An
+//! [`OutputPolicy`] decides, per sequence type, whether a control string is
+//! passed through, dropped, or only passed to interactive (control) clients.
+//!
+//! Policies are written as comma-separated `key=action` pairs:
+//!
+//! ```text
+//! osc52=interactive,osc1337=drop,dcs=drop
+//! ```
+//!
+//! | Key | Sequences |
+//! |-----|-----------|
+//! | `osc<N>` | OSC with numeric code `N` (e.g. `osc52` clipboard, `osc8` hyperlinks) |
+//! | `osc` | any OSC without its own rule |
+//! | `dcs` | DCS (sixel graphics, tmux passthrough, `DECRQSS`, …) |
+//! | `apc`, `pm`, `sos` | APC, PM and SOS strings |
+//!
+//! Actions are `allow`, `drop` and `interactive`. Anything not mentioned is
+//! allowed.
+//!
+//! [`OutputFilter`] applies a policy to a byte stream. It is incremental:
+//! sequences split across reads are recognized, and both the 7-bit (`ESC ]`)
+//! and the UTF-8 encoded C1 (`U+009D`) introducers are handled.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+/// Lead byte of the UTF-8 encoding of C1 controls (`U+0080`..`U+00BF`).
+const C1_LEAD: u8 = 0xc2;
+/// Second byte of the UTF-8 encoded String Terminator (`U+009C`).
+const C1_ST: u8 = 0x9c;
+/// Longest OSC code that is still parsed as a number.
+const MAX_OSC_DIGITS: usize = 6;
+
+/// What to do with a control string.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Action {
+    /// Pass it to every client.
+    #[default]
+    Allow,
+    /// Remove it from the output.
+    Drop,
+    /// Pass it to control clients only; view-only clients never see it.
+    Interactive,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "drop" => Ok(Self::Drop),
+            "interactive" => Ok(Self::Interactive),
+            _ => Err(format!(
+                "invalid action {s:?}, expected allow, drop or interactive"
+            )),
+        }
+    }
+}
+
+/// Type of control string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringKind {
+    /// Operating System Command (`ESC ]`).
+    Osc,
+    /// Device Control String (`ESC P`).
+    Dcs,
+    /// Application Program Command (`ESC _`).
+    Apc,
+    /// Privacy Message (`ESC ^`).
+    Pm,
+    /// Start Of String (`ESC X`).
+    Sos,
+}
+
+impl StringKind {
+    /// The kind introduced by `ESC <byte>`.
+    fn from_esc(byte: u8) -> Option<Self> {
+        match byte {
+            b']' => Some(Self::Osc),
+            b'P' => Some(Self::Dcs),
+            b'_' => Some(Self::Apc),
+            b'^' => Some(Self::Pm),
+            b'X' => Some(Self::Sos),
+            _ => None,
+        }
+    }
+
+    /// The kind introduced by the UTF-8 encoded C1 control `0xC2 <byte>`.
+    fn from_c1(byte: u8) -> Option<Self> {
+        // C1 controls are 0x40 above their 7-bit `ESC` counterparts.
+        byte.checked_sub(0x40).and_then(Self::from_esc)
+    }
+}
+
+/// Per-sequence actions. The default allows everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputPolicy {
+    /// OSC sequences without a rule in `osc_codes`.
+    pub osc: Action,
+    /// OSC sequences by numeric code.
+    pub osc_codes: HashMap<u32, Action>,
+    /// DCS strings.
+    pub dcs: Action,
+    /// APC strings.
+    pub apc: Action,
+    /// PM strings.
+    pub pm: Action,
+    /// SOS strings.
+    pub sos: Action,
+}
+
+impl OutputPolicy {
+    /// Action for a string of `kind`; `code` is the numeric OSC code, if any.
+    pub fn action(&self, kind: StringKind, code: Option<u32>) -> Action {
+        match kind {
+            StringKind::Osc => code
+                .and_then(|code| self.osc_codes.get(&code).copied())
+                .unwrap_or(self.osc),
+            StringKind::Dcs => self.dcs,
+            StringKind::Apc => self.apc,
+            StringKind::Pm => self.pm,
+            StringKind::Sos => self.sos,
+        }
+    }
+
+    fn actions(&self) -> impl Iterator<Item = Action> + '_ {
+        [self.osc, self.dcs, self.apc, self.pm, self.sos]
+            .into_iter()
+            .chain(self.osc_codes.values().copied())
+    }
+}
+
+impl FromStr for OutputPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, action) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid filter rule {pair:?}, expected key=action"))?;
+            let action = action.parse()?;
+            match key {
+                "osc" => policy.osc = action,
+                "dcs" => policy.dcs = action,
+                "apc" => policy.apc = action,
+                "pm" => policy.pm = action,
+                "sos" => policy.sos = action,
+                _ => {
+                    let code = key
+                        .strip_prefix("osc")
+                        .and_then(|code| code.parse().ok())
+                        .ok_or_else(|| format!("unknown filter key {key:?}"))?;
+                    policy.osc_codes.insert(code, action);
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// Saw `ESC`.
+    Esc,
+    /// Saw the UTF-8 lead byte of a possible C1 control.
+    C1,
+    /// Reading the numeric code of an OSC.
+    OscCode,
+    /// Inside a control string.
+    Body {
+        kind: StringKind,
+        keep: bool,
+    },
+    /// Saw `ESC` inside a control string.
+    BodyEsc {
+        keep: bool,
+    },
+    /// Saw the UTF-8 lead byte of a possible ST inside a control string.
+    BodyC1 {
+        kind: StringKind,
+        keep: bool,
+    },
+}
+
+/// Incremental filter applying an [`OutputPolicy`] to terminal output.
+///
+/// Bytes whose fate is not known yet — such as an `ESC` at the end of a
+/// chunk — are held back until the next call.
+#[derive(Debug)]
+pub struct OutputFilter {
+    policy: Arc<OutputPolicy>,
+    interactive: bool,
+    state: State,
+    /// Undecided bytes: an introducer and any OSC code digits read so far.
+    pending: Vec<u8>,
+}
+
+impl OutputFilter {
+    /// Filter for the shared output stream: drops what the policy drops and
+    /// keeps interactive-only sequences. `None` if there is nothing to drop.
+    pub fn for_output(policy: Arc<OutputPolicy>) -> Option<Self> {
+        let needed = policy.actions().any(|action| action == Action::Drop);
+        needed.then(|| Self::new(policy, true))
+    }
+
+    /// Filter for a view-only client, applied on top of
+    /// [`for_output`](Self::for_output): strips interactive-only sequences.
+    /// `None` if the policy has none.
+    pub fn for_viewer(policy: Arc<OutputPolicy>) -> Option<Self> {
+        let needed = policy.actions().any(|action| action == Action::Interactive);
+        needed.then(|| Self::new(policy, false))
+    }
+
+    /// Filter for an interactive or a view-only client.
+    pub fn new(policy: Arc<OutputPolicy>, interactive: bool) -> Self {
+        Self {
+            policy,
+            interactive,
+            state: State::Ground,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Filter the next chunk of output.
+    pub fn filter(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len() + self.pending.len());
+        let mut i = 0;
+        while i < input.len() {
+            if self.state == State::Ground {
+                let start = i;
+                while i < input.len() && input[i] != ESC && input[i] != C1_LEAD {
+                    i += 1;
+                }
+                out.extend_from_slice(&input[start..i]);
+                if i == input.len() {
+                    break;
+                }
+            }
+            if self.step(input[i], &mut out) {
+                i += 1;
+            }
+        }
+        out
+    }
+
+    /// Process one byte outside the ground fast path. Returns `false` if the
+    /// byte must be processed again in the new state.
+    fn step(&mut self, byte: u8, out: &mut Vec<u8>) -> bool {
+        match self.state {
+            State::Ground => {
+                self.pending.push(byte);
+                self.state = if byte == ESC { State::Esc } else { State::C1 };
+            }
+            State::Esc | State::C1 => {
+                let kind = if self.state == State::Esc {
+                    StringKind::from_esc(byte)
+                } else {
+                    StringKind::from_c1(byte)
+                };
+                let Some(kind) = kind else {
+                    out.append(&mut self.pending);
+                    self.state = State::Ground;
+                    return false;
+                };
+                self.pending.push(byte);
+                if kind == StringKind::Osc {
+                    self.state = State::OscCode;
+                } else {
+                    self.begin(kind, None, out);
+                }
+            }
+            State::OscCode => {
+                // Both `ESC ]` and `0xC2 0x9D` are two bytes long.
+                let digits = &self.pending[2..];
+                if byte.is_ascii_digit() && digits.len() < MAX_OSC_DIGITS {
+                    self.pending.push(byte);
+                } else {
+                    let code = std::str::from_utf8(digits)
+                        .ok()
+                        .and_then(|code| code.parse().ok());
+                    self.begin(StringKind::Osc, code, out);
+                    return false;
+                }
+            }
+            State::Body { kind, keep } => match byte {
+                ESC => self.state = State::BodyEsc { keep },
+                C1_LEAD => self.state = State::BodyC1 { kind, keep },
+                BEL if kind == StringKind::Osc => self.end(byte, keep, out),
+                CAN | SUB => self.end(byte, keep, out),
+                _ if keep => out.push(byte),
+                _ => {}
+            },
+            State::BodyEsc { keep } => {
+                if byte == b'\\' {
+                    if keep {
+                        out.push(ESC);
+                    }
+                    self.end(byte, keep, out);
+                } else {
+                    // ESC aborts the string and starts a new sequence.
+                    self.pending.push(ESC);
+                    self.state = State::Esc;
+                    return false;
+                }
+            }
+            State::BodyC1 { kind, keep } => {
+                if keep {
+                    out.push(C1_LEAD);
+                }
+                if byte == C1_ST {
+                    self.end(byte, keep, out);
+                } else {
+                    self.state = State::Body { kind, keep };
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Decide what to do with a string whose introducer is in `pending`.
+    fn begin(&mut self, kind: StringKind, code: Option<u32>, out: &mut Vec<u8>) {
+        let keep = match self.policy.action(kind, code) {
+            Action::Allow => true,
+            Action::Drop => false,
+            Action::Interactive => self.interactive,
+        };
+        if keep {
+            out.append(&mut self.pending);
+        } else {
+            self.pending.clear();
+        }
+        self.state = State::Body { kind, keep };
+    }
+
+    /// Finish the current string with its terminating `byte`.
+    fn end(&mut self, byte: u8, keep: bool, out: &mut Vec<u8>) {
+        if keep {
+            out.push(byte);
+        }
+        self.state = State::Ground;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(spec: &str) -> Arc<OutputPolicy> {
+        Arc::new(spec.parse().unwrap())
+    }
+
+    fn run(filter: &mut OutputFilter, chunks: &[&[u8]]) -> Vec<u8> {
+        chunks
+            .iter()
+            .flat_map(|chunk| filter.filter(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let policy = policy("osc52=interactive, osc=drop,dcs=drop");
+        assert_eq!(
+            policy.action(StringKind::Osc, Some(52)),
+            Action::Interactive
+        );
+        assert_eq!(policy.action(StringKind::Osc, Some(0)), Action::Drop);
+        assert_eq!(policy.action(StringKind::Osc, None), Action::Drop);
+        assert_eq!(policy.action(StringKind::Dcs, None), Action::Drop);
+        assert_eq!(policy.action(StringKind::Apc, None), Action::Allow);
+        assert!("osc52".parse::<OutputPolicy>().is_err());
+        assert!("oscx=drop".parse::<OutputPolicy>().is_err());
+        assert!("osc52=block".parse::<OutputPolicy>().is_err());
+        assert_eq!("".parse::<OutputPolicy>(), Ok(OutputPolicy::default()));
+    }
+
+    #[test]
+    fn test_filters_are_only_built_when_needed() {
+        assert!(OutputFilter::for_output(policy("osc52=interactive")).is_none());
+        assert!(OutputFilter::for_viewer(policy("osc52=interactive")).is_some());
+        assert!(OutputFilter::for_output(policy("dcs=drop")).is_some());
+        assert!(OutputFilter::for_viewer(policy("dcs=drop")).is_none());
+    }
+
+    #[test]
+    fn test_drops_osc_by_code() {
+        let mut filter = OutputFilter::new(policy("osc52=drop"), true);
+        let out = filter.filter(b"a\x1b]52;c;aGk=\x07b\x1b]0;title\x1b\\c");
+        assert_eq!(out, b"ab\x1b]0;title\x1b\\c");
+    }
+
+    #[test]
+    fn test_interactive_only() {
+        let input = b"x\x1b]52;c;aGk=\x1b\\y";
+        let mut control = OutputFilter::new(policy("osc52=interactive"), true);
+        assert_eq!(control.filter(input), input);
+        let mut viewer = OutputFilter::new(policy("osc52=interactive"), false);
+        assert_eq!(viewer.filter(input), b"xy");
+    }
+
+    #[test]
+    fn test_split_across_chunks() {
+        let input: &[u8] = b"ab\x1b]52;c;aGVsbG8=\x1b\\cd\x1bPq#0;2;0;0;0\x1b\\ef";
+        let expected: &[u8] = b"abcdef";
+        for split in 0..input.len() {
+            for split2 in split..input.len() {
+                let mut filter = OutputFilter::new(policy("osc52=drop,dcs=drop"), true);
+                let out = run(
+                    &mut filter,
+                    &[&input[..split], &input[split..split2], &input[split2..]],
+                );
+                assert_eq!(out, expected, "split at {split}/{split2}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_c1_introducers() {
+        let mut filter = OutputFilter::new(policy("osc52=drop,dcs=drop"), true);
+        let out = filter.filter("a\u{9d}52;c;aGk=\u{9c}b\u{90}q\u{9c}c ©".as_bytes());
+        assert_eq!(out, "abc ©".as_bytes());
+    }
+
+    #[test]
+    fn test_other_escapes_pass_through() {
+        let mut filter = OutputFilter::new(policy("osc=drop,dcs=drop"), true);
+        let input = b"\x1b[1;31mred\x1b[0m \x1b7\x1b8 \xc2\xa9";
+        assert_eq!(filter.filter(input), input);
+    }
+
+    #[test]
+    fn test_esc_aborts_string() {
+        let mut filter = OutputFilter::new(policy("osc52=drop"), true);
+        let out = filter.filter(b"\x1b]52;c;aGk=\x1b[1mbold");
+        assert_eq!(out, b"\x1b[1mbold");
+    }
+
+    #[test]
+    fn test_bel_does_not_end_dcs() {
+        let mut filter = OutputFilter::new(policy("dcs=drop"), true);
+        assert_eq!(filter.filter(b"\x1bPq\x07still dcs\x1b\\done"), b"done");
+    }
+
+    #[test]
+    fn test_holds_back_trailing_escape() {
+        let mut filter = OutputFilter::new(policy("osc52=drop"), true);
+        assert_eq!(filter.filter(b"abc\x1b"), b"abc");
+        assert_eq!(filter.filter(b"[0m"), b"\x1b[0m");
+    }
+}
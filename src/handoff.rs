@@ -0,0 +1,545 @@
+//! Zero-downtime restarts by handing sessions to a new tty-web process.
+//!
+//! The new process is started with `--takeover` and waits on the hand-off
+//! socket ([`takeover`]). On `SIGUSR2` the running server [connects](connect)
+//! to it, [quiesces](crate::session::Session::quiesce) every session and
+//! [sends](send) it: a JSON [`HandoffRecord`] plus the PTY master fd, passed
+//! with `SCM_RIGHTS`. Output the shells write meanwhile stays in the PTYs for
+//! the new process. Once the new process has acknowledged every session, the
+//! old one exits without hanging up the shells, and the new process
+//! [restores](restore) the sessions around the received fds. Clients simply
+//! reconnect with the same session ID and key. If the hand-off fails, the old
+//! process resumes the sessions the new one did not keep and goes on
+//! serving.
+//!
+//! Both ends only talk to a peer running as the same user (or root).
+//!
+//! # Stream format
+//!
+//! [`MAGIC`], then for every session a big-endian `u32` length that carries
+//! the master fd as ancillary data, followed by that many bytes of JSON. The
+//! sender shuts down its side after the last session, and the receiver
+//! answers with a big-endian `u32`: the number of sessions it kept. The
+//! sender lets go of exactly those, even if the stream broke off early.
+
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::sys::socket::{ControlMessage, ControlMessageOwned, MsgFlags, recvmsg, sendmsg};
+use nix::unistd::{Uid, geteuid};
+use serde::{Deserialize, Serialize};
+
+use crate::session::{Session, SessionOptions, SessionSnapshot};
+use crate::terminal::Terminal;
+use crate::web::AppState;
+
+/// First bytes of a hand-off stream, including the format version.
+pub const MAGIC: &[u8; 8] = b"TTYWEB1\n";
+
+/// Largest accepted JSON record.
+const MAX_RECORD_LEN: usize = 64 << 20;
+
+/// How long the sender waits for the receiver's acknowledgement.
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Everything but the PTY master fd that a new process needs to take over a
+/// session.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HandoffRecord {
+    /// Session state.
+    pub session: SessionSnapshot,
+    /// Process ID of the shell.
+    pub pid: u32,
+    /// Command the shell was started with.
+    pub command: String,
+    /// Session cgroup the shell runs in, if any.
+    pub cgroup: Option<PathBuf>,
+}
+
+/// A session received from the previous process.
+#[derive(Debug)]
+pub struct Received {
+    /// Session and shell details.
+    pub record: HandoffRecord,
+    /// The session's PTY master.
+    pub master: OwnedFd,
+}
+
+/// Connect to a new process waiting on `path` for a hand-off.
+pub fn connect(path: &Path) -> io::Result<UnixStream> {
+    let stream = UnixStream::connect(path)?;
+    check_peer(&stream)?;
+    Ok(stream)
+}
+
+/// A [`send`] that failed. The new process still kept the sessions in
+/// `taken`, which the sender must let go of without hanging up their shells.
+#[derive(Debug)]
+pub struct SendError {
+    /// IDs of the sessions the new process took over.
+    pub taken: Vec<String>,
+    /// Why the hand-off failed.
+    pub error: io::Error,
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for SendError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Send `sessions` to the new process and wait for it to acknowledge them.
+///
+/// Sessions whose shell has no known PID are skipped. Returns the number of
+/// sessions sent, or which of them the new process kept if not all were.
+pub fn send(mut stream: UnixStream, sessions: &[Arc<Session>]) -> Result<usize, SendError> {
+    let sessions: Vec<_> = sessions
+        .iter()
+        .filter(|session| session.terminal.pid().is_some())
+        .collect();
+    let written = write_sessions(&mut stream, &sessions);
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+    // Without an acknowledgement the new process is assumed to have none.
+    let taken = read_ack(&mut stream).unwrap_or(0).min(sessions.len());
+    match written {
+        Ok(()) if taken == sessions.len() => Ok(taken),
+        written => Err(SendError {
+            taken: sessions[..taken]
+                .iter()
+                .map(|session| session.id().to_owned())
+                .collect(),
+            error: written.err().unwrap_or_else(|| {
+                io::Error::other(format!(
+                    "new process kept {taken} of {} sessions",
+                    sessions.len()
+                ))
+            }),
+        }),
+    }
+}
+
+fn write_sessions(stream: &mut UnixStream, sessions: &[&Arc<Session>]) -> io::Result<()> {
+    stream.write_all(MAGIC)?;
+    for session in sessions {
+        let Some(pid) = session.terminal.pid() else {
+            continue;
+        };
+        let record = HandoffRecord {
+            session: session.snapshot(),
+            pid,
+            command: session.terminal.command().to_owned(),
+            cgroup: session.terminal.cgroup_path().map(Path::to_owned),
+        };
+        let json = serde_json::to_vec(&record).map_err(io::Error::other)?;
+        let len = u32::try_from(json.len())
+            .map_err(io::Error::other)?
+            .to_be_bytes();
+        let fds = [session.terminal.master().as_raw_fd()];
+        let written = sendmsg::<()>(
+            stream.as_raw_fd(),
+            &[IoSlice::new(&len)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+        stream.write_all(&len[written..])?;
+        stream.write_all(&json)?;
+    }
+    Ok(())
+}
+
+fn read_ack(stream: &mut UnixStream) -> io::Result<usize> {
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let mut ack = [0u8; 4];
+    stream.read_exact(&mut ack)?;
+    Ok(u32::from_be_bytes(ack) as usize)
+}
+
+/// Read every session from a hand-off stream until the sender closes it,
+/// and acknowledge them.
+///
+/// If the stream breaks off, the sessions read completely so far are still
+/// returned and acknowledged: the sender lets go of exactly those, so
+/// dropping them would hang up their shells.
+pub fn receive(mut stream: UnixStream) -> io::Result<Vec<Received>> {
+    let mut magic = [0u8; MAGIC.len()];
+    stream.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a tty-web hand-off stream",
+        ));
+    }
+    let mut received = Vec::new();
+    if let Err(e) = read_sessions(&mut stream, &mut received) {
+        tracing::warn!(
+            "hand-off stream broke off after {} sessions: {}",
+            received.len(),
+            e
+        );
+    }
+    let ack = u32::try_from(received.len()).unwrap_or(u32::MAX);
+    if let Err(e) = stream.write_all(&ack.to_be_bytes()) {
+        // The sender is most likely gone; the sessions are ours now.
+        tracing::warn!("failed to acknowledge the hand-off: {}", e);
+    }
+    Ok(received)
+}
+
+fn read_sessions(stream: &mut UnixStream, received: &mut Vec<Received>) -> io::Result<()> {
+    loop {
+        let mut len = [0u8; 4];
+        let (read, master) = recv_with_fd(stream, &mut len)?;
+        if read == 0 {
+            if master.is_some() {
+                return Err(invalid_data("PTY fd without a record"));
+            }
+            return Ok(());
+        }
+        stream.read_exact(&mut len[read..])?;
+        let master = master.ok_or_else(|| invalid_data("record without a PTY fd"))?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(invalid_data("record too large"));
+        }
+        let mut json = vec![0u8; len];
+        stream.read_exact(&mut json)?;
+        let record = serde_json::from_slice(&json).map_err(io::Error::other)?;
+        received.push(Received { record, master });
+    }
+}
+
+/// Receive into `buf`, taking ownership of the first fd passed along.
+fn recv_with_fd(stream: &UnixStream, buf: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+    let mut iov = [IoSliceMut::new(buf)];
+    let msg = recvmsg::<()>(
+        stream.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+    let mut fds = Vec::new();
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(raw) = cmsg {
+            // Safety: the kernel just installed these fds for us.
+            fds.extend(
+                raw.into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+    Ok((msg.bytes, fds.into_iter().next()))
+}
+
+/// Listen on `path` and wait up to `timeout` for a running server to hand
+/// over its sessions. The socket file is removed again afterwards.
+pub async fn takeover(path: &Path, timeout: Duration) -> io::Result<Vec<Received>> {
+    // A stale socket from an earlier takeover would make bind fail.
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    let accepted = tokio::time::timeout(timeout, listener.accept()).await;
+    let _ = std::fs::remove_file(path);
+    let (stream, _) = accepted.map_err(|_| {
+        io::Error::new(io::ErrorKind::TimedOut, "no server handed off its sessions")
+    })??;
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+    check_peer(&stream)?;
+    tokio::task::spawn_blocking(move || receive(stream))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Rebuild received sessions and register them in `state`'s store. Returns
+/// the number of sessions restored; failures are logged and skipped.
+pub fn restore(state: &AppState, received: Vec<Received>) -> usize {
+    let mut restored = 0;
+    for Received { record, master } in received {
+        let id = record.session.id.clone();
+        let adopted = Terminal::adopt(
+            master,
+            record.pid,
+            record.command,
+            record.cgroup,
+            state.output_filter.clone(),
+        );
+        let (terminal, output_rx) = match adopted {
+            Ok(adopted) => adopted,
+            Err(e) => {
+                tracing::warn!("failed to adopt session {id}: {e}");
+                continue;
+            }
+        };
+        let session = Session::restore(
+            terminal,
+            output_rx,
+            record.session,
+            SessionOptions::new(state.scrollback_limit, state.orphan_timeout)
+                .with_scrollback_compression(state.scrollback_compression)
+                .with_audit(state.audit.clone())
+                .with_spool(state.spool.clone()),
+        );
+        if state.sessions.insert(session).is_err() {
+            tracing::warn!("session {id} has a name that is already taken");
+            continue;
+        }
+        tracing::info!("adopted session {id}");
+        restored += 1;
+    }
+    restored
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Refuse peers running as another user: the stream carries every shell.
+fn check_peer(stream: &UnixStream) -> io::Result<()> {
+    let uid = peer_uid(stream)?;
+    let euid = geteuid();
+    if uid != euid && !uid.is_root() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("hand-off peer runs as uid {uid}, not {euid}"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<Uid> {
+    use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+    let creds = getsockopt(&stream.as_fd(), PeerCredentials)?;
+    Ok(Uid::from_raw(creds.uid()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<Uid> {
+    let (uid, _) = nix::unistd::getpeereid(stream.as_fd())?;
+    Ok(uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{DEFAULT_ORPHAN_TIMEOUT, ScrollbackEvent, SessionStore};
+
+    #[tokio::test]
+    async fn test_handoff_roundtrip() {
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let options = SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT)
+            .with_name(Some("build".to_owned()))
+            .with_owner(Some("alice".to_owned()));
+        let session = Session::with_options(terminal, output_rx, options);
+        session.set_window_size(40, 120);
+
+        let (tx, rx) = UnixStream::pair().unwrap();
+        check_peer(&tx).expect("same user");
+        let sender = std::thread::spawn({
+            let session = session.clone();
+            move || send(tx, &[session])
+        });
+        let received = tokio::task::spawn_blocking(move || receive(rx))
+            .await
+            .unwrap()
+            .expect("receive");
+        assert_eq!(sender.join().unwrap().unwrap(), 1);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].record.pid, session.terminal.pid().unwrap());
+        assert_eq!(received[0].record.command, "/bin/sh");
+
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            SessionStore::new(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        assert_eq!(restore(&state, received), 1);
+        let adopted = state.sessions.get("build").expect("restored by name");
+        assert_eq!(adopted.id(), session.id());
+        assert_eq!(adopted.control_key(), session.control_key());
+        assert_eq!(adopted.window_size(), (40, 120));
+        assert_eq!(adopted.created(), session.created());
+        assert!(adopted.is_owned_by(Some("alice")));
+        assert!(adopted.terminal.is_adopted());
+
+        // The adopted terminal drives the same shell.
+        let (_sb, mut output, _ws) = adopted.attach();
+        adopted
+            .terminal
+            .write(b"echo adopted_$((6*7))\n".to_vec())
+            .await
+            .unwrap();
+        let mut collected = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Ok(data) = output.recv().await {
+                collected.push_str(&String::from_utf8_lossy(&data));
+                if collected.contains("adopted_42") {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(collected.contains("adopted_42"), "output: {collected:?}");
+    }
+
+    #[tokio::test]
+    async fn test_handoff_keeps_output_after_snapshot() {
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let session = Session::new(terminal, output_rx, 64 * 1024, DEFAULT_ORPHAN_TIMEOUT);
+        let (_sb, mut output, _ws) = session.attach();
+        session
+            .terminal
+            .write(b"echo before_$((6*7))\n".to_vec())
+            .await
+            .unwrap();
+        let mut collected = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Ok(data) = output.recv().await {
+                collected.push_str(&String::from_utf8_lossy(&data));
+                if collected.contains("before_42") {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(collected.contains("before_42"), "output: {collected:?}");
+
+        session.quiesce().await;
+        let (tx, rx) = UnixStream::pair().unwrap();
+        let sender = std::thread::spawn({
+            let session = session.clone();
+            move || send(tx, &[session])
+        });
+        let received = tokio::task::spawn_blocking(move || receive(rx))
+            .await
+            .unwrap()
+            .expect("receive");
+        assert_eq!(sender.join().unwrap().unwrap(), 1);
+        // Written after the snapshot was sent: the old session must leave
+        // the output in the PTY for the adopted one.
+        session
+            .terminal
+            .write(b"echo after_$((6*7))\n".to_vec())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            64 * 1024,
+            SessionStore::new(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        assert_eq!(restore(&state, received), 1);
+        let adopted = state.sessions.get(session.id()).expect("restored");
+
+        let (sb, mut output, _ws) = adopted.attach();
+        let mut collected = String::new();
+        for event in sb {
+            if let ScrollbackEvent::Output(data) = event {
+                collected.push_str(&String::from_utf8_lossy(&data));
+            }
+        }
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while !collected.contains("after_42") {
+                let Ok(data) = output.recv().await else {
+                    break;
+                };
+                collected.push_str(&String::from_utf8_lossy(&data));
+            }
+        })
+        .await;
+        assert!(collected.contains("before_42"), "output: {collected:?}");
+        assert!(collected.contains("after_42"), "output: {collected:?}");
+    }
+
+    #[test]
+    fn test_receive_rejects_garbage() {
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        tx.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        drop(tx);
+        let err = receive(rx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_receive_empty() {
+        let (tx, rx) = UnixStream::pair().unwrap();
+        let sender = std::thread::spawn(move || send(tx, &[]));
+        assert!(receive(rx).unwrap().is_empty());
+        assert_eq!(sender.join().unwrap().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_receive_truncated_stream() {
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let session = Session::new(terminal, output_rx, 1024, DEFAULT_ORPHAN_TIMEOUT);
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        write_sessions(&mut tx, &[&session]).unwrap();
+        // The sender breaks off in the middle of the next record.
+        tx.write_all(&[0, 0]).unwrap();
+        tx.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let received = tokio::task::spawn_blocking(move || receive(rx))
+            .await
+            .unwrap()
+            .expect("sessions read before the break are kept");
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].record.session.id, session.id());
+        assert_eq!(read_ack(&mut tx).unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_reports_sessions_kept() {
+        let sessions: Vec<_> = (0..2)
+            .map(|_| {
+                let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+                Session::new(terminal, output_rx, 1024, DEFAULT_ORPHAN_TIMEOUT)
+            })
+            .collect();
+        // A receiver that keeps only the first session.
+        let (tx, mut rx) = UnixStream::pair().unwrap();
+        let receiver = std::thread::spawn(move || {
+            let mut data = Vec::new();
+            rx.read_to_end(&mut data).unwrap();
+            rx.write_all(&1u32.to_be_bytes()).unwrap();
+        });
+        let err = tokio::task::spawn_blocking({
+            let sessions = sessions.clone();
+            move || send(tx, &sessions)
+        })
+        .await
+        .unwrap()
+        .unwrap_err();
+        receiver.join().unwrap();
+        assert_eq!(err.taken, [sessions[0].id()]);
+
+        // No acknowledgement: the new process is assumed to have none.
+        let (tx, rx) = UnixStream::pair().unwrap();
+        drop(rx);
+        let err = tokio::task::spawn_blocking(move || send(tx, &sessions))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert!(err.taken.is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! Linux namespace isolation for session shells.
+//!
+//! With [`Isolation`] enabled, each shell gets fresh user, PID, mount, UTS and
+//! IPC namespaces (and optionally a network namespace with only loopback):
+//! it cannot see other sessions' processes, and `ps` shows only its own tree.
+//!
+//! Because a new PID namespace only applies to children of the process that
+//! creates it, the forked child splits in two:
+//!
+//! ```text
+//! tty-web ─fork─▶ keeper (outside PID ns) ─fork─▶ shell (PID 1 in new ns)
+//! ```
+//!
+//! The *keeper* is the process tty-web sees as the child: it waits for the
+//! shell and exits with its status. It forwards `SIGHUP`, `SIGINT` and
+//! `SIGTERM` to the shell's process group, so
+//! [`Terminal::signal`](crate::terminal::Terminal::signal) reaches the shell
+//! as with any other session. The shell has `PR_SET_PDEATHSIG` set to
+//! `SIGKILL`, so when the keeper dies the shell dies too and the kernel tears
+//! down everything else in its PID namespace. A keeper that dies before the
+//! shell has set it is noticed through a pipe only the keeper holds open:
+//! `getppid` cannot tell, as it is 0 for PID 1 of a namespace either way.
+//!
+//! After forwarding `SIGHUP` — e.g. from
+//! [`Terminal`](crate::terminal::Terminal)'s `Drop` — the keeper gives the
+//! shell [`HANGUP_GRACE_SECS`] to exit and then dies, because the shell, as
+//! PID 1 of its namespace, ignores signals it has no handler for.
+//!
+//! Everything here requires unprivileged user namespaces (or root).
+
+/// Namespaces to create for each session shell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Isolation {
+    /// Also unshare the network namespace (the shell only gets loopback).
+    pub network: bool,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::{Prepared, enter};
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) use fallback::{Prepared, enter};
+
+/// Seconds an isolated shell gets to exit after a forwarded `SIGHUP` before
+/// its keeper dies and takes the namespace down.
+pub const HANGUP_GRACE_SECS: u32 = 2;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use nix::libc;
+
+    use super::{HANGUP_GRACE_SECS, Isolation};
+
+    /// Signals the keeper forwards to the shell.
+    const FORWARDED: [libc::c_int; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+    /// PID of the shell, for the keeper's signal handler.
+    static SHELL: AtomicI32 = AtomicI32::new(0);
+
+    /// Isolation settings with everything that allocates computed up front,
+    /// so the forked child only performs raw syscalls.
+    pub(crate) struct Prepared {
+        flags: libc::c_int,
+        network: bool,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+    }
+
+    impl Prepared {
+        /// Prepare to map `uid`/`gid` (the credentials the shell will have)
+        /// to themselves inside the new user namespace.
+        pub(crate) fn new(isolation: &Isolation, uid: u32, gid: u32) -> std::io::Result<Self> {
+            let mut flags = libc::CLONE_NEWUSER
+                | libc::CLONE_NEWPID
+                | libc::CLONE_NEWNS
+                | libc::CLONE_NEWUTS
+                | libc::CLONE_NEWIPC;
+            if isolation.network {
+                flags |= libc::CLONE_NEWNET;
+            }
+            Ok(Self {
+                flags,
+                network: isolation.network,
+                uid_map: format!("{uid} {uid} 1").into_bytes(),
+                gid_map: format!("{gid} {gid} 1").into_bytes(),
+            })
+        }
+    }
+
+    fn check(ret: libc::c_int) -> std::io::Result<()> {
+        if ret == -1 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn write_file(path: &[u8], data: &[u8]) -> std::io::Result<()> {
+        let fd = unsafe { libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC) };
+        check(fd)?;
+        let n = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+        unsafe { libc::close(fd) };
+        if n == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Enter the namespaces. Runs in the forked child before `exec`.
+    ///
+    /// Only returns in the shell process (PID 1 of the new namespace); the
+    /// keeper process never returns from this function.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called between `fork` and `exec`.
+    pub(crate) unsafe fn enter(prepared: &Prepared) -> std::io::Result<()> {
+        unsafe {
+            // Own session, so signalling the keeper's process group never
+            // reaches tty-web itself.
+            check(libc::setsid())?;
+            // Handlers inherited from tokio would keep the keeper alive on
+            // SIGHUP/SIGTERM; it must die so the shell gets PDEATHSIG.
+            for sig in [
+                libc::SIGHUP,
+                libc::SIGINT,
+                libc::SIGTERM,
+                libc::SIGQUIT,
+                libc::SIGALRM,
+            ] {
+                libc::signal(sig, libc::SIG_DFL);
+            }
+
+            check(libc::unshare(prepared.flags))?;
+            // Dropping privileges clears the dumpable flag, which makes our
+            // /proc/self files root-owned and the ID maps unwritable.
+            check(libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0))?;
+            write_file(b"/proc/self/setgroups\0", b"deny")?;
+            write_file(b"/proc/self/uid_map\0", &prepared.uid_map)?;
+            write_file(b"/proc/self/gid_map\0", &prepared.gid_map)?;
+            if prepared.network {
+                bring_up_loopback();
+            }
+
+            // Hold forwarded signals until the keeper can handle them.
+            let mut forwarded: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut forwarded);
+            for sig in FORWARDED {
+                libc::sigaddset(&mut forwarded, sig);
+            }
+            libc::sigprocmask(libc::SIG_BLOCK, &forwarded, std::ptr::null_mut());
+            // Reads on `keeper_alive` see EOF once the keeper has exited.
+            let mut keeper_alive = [0; 2];
+            check(libc::pipe2(
+                keeper_alive.as_mut_ptr(),
+                libc::O_CLOEXEC | libc::O_NONBLOCK,
+            ))?;
+            let shell = libc::fork();
+            if shell > 0 {
+                SHELL.store(shell, Ordering::Relaxed);
+                for sig in FORWARDED {
+                    libc::signal(sig, forward as *const () as libc::sighandler_t);
+                }
+            }
+            libc::sigprocmask(libc::SIG_UNBLOCK, &forwarded, std::ptr::null_mut());
+            check(shell)?;
+            if shell > 0 {
+                keep(shell, keeper_alive[1]);
+            }
+
+            libc::close(keeper_alive[1]);
+            check(libc::prctl(
+                libc::PR_SET_PDEATHSIG,
+                libc::SIGKILL as libc::c_ulong,
+                0,
+                0,
+                0,
+            ))?;
+            // The keeper may have died before the line above, and then no
+            // signal will ever come.
+            let mut byte = 0u8;
+            if libc::read(keeper_alive[0], (&raw mut byte).cast(), 1) == 0 {
+                libc::_exit(1);
+            }
+            libc::close(keeper_alive[0]);
+            // Fresh /proc reflecting the new PID namespace, without
+            // propagating mounts back to the host.
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            check(libc::mount(
+                c"proc".as_ptr(),
+                c"/proc".as_ptr(),
+                c"proc".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                std::ptr::null(),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Keeper's handler: pass the signal on to the shell's process group (or
+    /// the shell alone before it has called `setsid`). A hang-up also starts
+    /// the grace period after which the keeper dies of `SIGALRM`.
+    extern "C" fn forward(sig: libc::c_int) {
+        let shell = SHELL.load(Ordering::Relaxed);
+        unsafe {
+            let errno = *libc::__errno_location();
+            if libc::kill(-shell, sig) == -1 {
+                libc::kill(shell, sig);
+            }
+            if sig == libc::SIGHUP {
+                libc::alarm(HANGUP_GRACE_SECS);
+            }
+            *libc::__errno_location() = errno;
+        }
+    }
+
+    /// Keeper: release inherited descriptors except `alive`, the write end
+    /// of the pipe the shell checks, wait for the shell, and exit with its
+    /// status.
+    unsafe fn keep(shell: libc::pid_t, alive: libc::c_int) -> ! {
+        unsafe {
+            // Closing our copy of std's CLOEXEC status pipe lets the parent
+            // learn the outcome of the shell's own `exec`.
+            close_fds(3, alive - 1);
+            close_fds(alive + 1, libc::c_int::MAX);
+            let mut status = 0;
+            while libc::waitpid(shell, &mut status, 0) == -1 {
+                if *libc::__errno_location() != libc::EINTR {
+                    libc::_exit(1);
+                }
+            }
+            if libc::WIFEXITED(status) {
+                libc::_exit(libc::WEXITSTATUS(status));
+            }
+            libc::_exit(128 + libc::WTERMSIG(status));
+        }
+    }
+
+    /// Close the descriptors `first..=last`.
+    unsafe fn close_fds(first: libc::c_int, last: libc::c_int) {
+        if first > last {
+            return;
+        }
+        unsafe {
+            if libc::syscall(libc::SYS_close_range, first, last, 0) == -1 {
+                for fd in first..=last.min(4095) {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+
+    /// Set `lo` up in a fresh network namespace (best effort).
+    unsafe fn bring_up_loopback() {
+        unsafe {
+            let sock = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+            if sock == -1 {
+                return;
+            }
+            let mut req: libc::ifreq = std::mem::zeroed();
+            for (dst, src) in req.ifr_name.iter_mut().zip(b"lo\0") {
+                *dst = *src as libc::c_char;
+            }
+            if libc::ioctl(sock, libc::SIOCGIFFLAGS as _, &mut req) == 0 {
+                req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+                libc::ioctl(sock, libc::SIOCSIFFLAGS as _, &req);
+            }
+            libc::close(sock);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use super::Isolation;
+
+    pub(crate) struct Prepared;
+
+    impl Prepared {
+        pub(crate) fn new(_isolation: &Isolation, _uid: u32, _gid: u32) -> std::io::Result<Self> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "namespace isolation is only supported on Linux",
+            ))
+        }
+    }
+
+    pub(crate) unsafe fn enter(_prepared: &Prepared) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -3,8 +3,20 @@
 //! Opens a real PTY in the browser over WebSocket. Can be used as a standalone
 //! binary or embedded as a library into other applications.
 
+pub mod audit;
+pub mod cgroup;
 pub mod config;
+pub mod filter;
+pub mod handoff;
+pub mod isolation;
+pub mod limits;
 pub(crate) mod pty;
+pub mod scrollback;
 pub mod session;
+pub mod size;
+pub mod spawn;
+pub mod spool;
 pub mod terminal;
+pub mod tls;
+pub mod unix_socket;
 pub mod web;
@@ -0,0 +1,332 @@
+//! `setrlimit`, nice and ionice settings for session shells.
+//!
+//! A lighter alternative to [cgroups](crate::cgroup): limits are applied to
+//! the shell process right before `exec` and inherited by everything it
+//! starts. Both soft and hard limits are set, so the shell cannot raise them.
+//!
+//! Limits are written as comma-separated `key=value` pairs:
+//!
+//! ```text
+//! nproc=256,cpu=3600,fsize=1G,nofile=1024,core=0,nice=10,ionice=best-effort:7
+//! ```
+//!
+//! | Key | Value |
+//! |-----|-------|
+//! | `nproc` | `RLIMIT_NPROC`: processes per user |
+//! | `cpu` | `RLIMIT_CPU`: CPU seconds |
+//! | `fsize` | `RLIMIT_FSIZE`: largest file size (`K`/`M`/`G`/`T` suffix allowed) |
+//! | `nofile` | `RLIMIT_NOFILE`: open file descriptors |
+//! | `core` | `RLIMIT_CORE`: core dump size (`K`/`M`/`G`/`T` suffix allowed) |
+//! | `nice` | scheduling priority, `-20`..`19` |
+//! | `ionice` | `idle`, `best-effort[:0-7]` or `realtime[:0-7]` (Linux only) |
+//!
+//! Any rlimit may also be `unlimited`.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use nix::libc;
+
+use crate::size::parse_size;
+
+/// I/O scheduling class and level, as set by `ionice(1)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Only gets disk time when nobody else needs it.
+    Idle,
+    /// Default class; lower levels are served first.
+    BestEffort(u8),
+    /// Always served first (requires root).
+    Realtime(u8),
+}
+
+impl FromStr for IoPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => {
+                let level = level
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|l| *l <= 7)
+                    .ok_or_else(|| format!("invalid ionice level {level:?}, expected 0-7"))?;
+                (class, Some(level))
+            }
+            None => (s, None),
+        };
+        match (class, level) {
+            ("idle", None) => Ok(Self::Idle),
+            ("best-effort", level) => Ok(Self::BestEffort(level.unwrap_or(4))),
+            ("realtime", level) => Ok(Self::Realtime(level.unwrap_or(4))),
+            _ => Err(format!(
+                "invalid ionice {s:?}, expected idle, best-effort[:N] or realtime[:N]"
+            )),
+        }
+    }
+}
+
+/// Resource limits and priorities for a shell. `None` leaves the server's
+/// own setting in place; an rlimit of `u64::MAX` means unlimited.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessLimits {
+    /// `RLIMIT_NPROC`.
+    pub nproc: Option<u64>,
+    /// `RLIMIT_CPU` in seconds.
+    pub cpu: Option<u64>,
+    /// `RLIMIT_FSIZE` in bytes.
+    pub fsize: Option<u64>,
+    /// `RLIMIT_NOFILE`.
+    pub nofile: Option<u64>,
+    /// `RLIMIT_CORE` in bytes.
+    pub core: Option<u64>,
+    /// Nice value.
+    pub nice: Option<i32>,
+    /// I/O priority.
+    pub ionice: Option<IoPriority>,
+}
+
+impl ProcessLimits {
+    /// Returns `true` if nothing would be changed.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Settings from `other`, falling back to `self` for anything `other`
+    /// leaves unset.
+    pub fn overlay(&self, other: &Self) -> Self {
+        Self {
+            nproc: other.nproc.or(self.nproc),
+            cpu: other.cpu.or(self.cpu),
+            fsize: other.fsize.or(self.fsize),
+            nofile: other.nofile.or(self.nofile),
+            core: other.core.or(self.core),
+            nice: other.nice.or(self.nice),
+            ionice: other.ionice.or(self.ionice),
+        }
+    }
+
+    /// Apply the limits to the calling process.
+    ///
+    /// # Safety
+    ///
+    /// Only performs async-signal-safe syscalls, so it may be called between
+    /// `fork` and `exec`.
+    pub(crate) unsafe fn apply(&self) -> std::io::Result<()> {
+        let rlimits = [
+            (libc::RLIMIT_NPROC, self.nproc),
+            (libc::RLIMIT_CPU, self.cpu),
+            (libc::RLIMIT_FSIZE, self.fsize),
+            (libc::RLIMIT_NOFILE, self.nofile),
+            (libc::RLIMIT_CORE, self.core),
+        ];
+        for (resource, value) in rlimits {
+            let Some(value) = value else { continue };
+            let value = match value {
+                u64::MAX => libc::RLIM_INFINITY,
+                v => v as libc::rlim_t,
+            };
+            let limit = libc::rlimit {
+                rlim_cur: value,
+                rlim_max: value,
+            };
+            if unsafe { libc::setrlimit(resource, &limit) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        if let Some(ionice) = self.ionice {
+            unsafe { set_io_priority(ionice) }?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_io_priority(priority: IoPriority) -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+    let (class, level) = match priority {
+        IoPriority::Realtime(level) => (1, level),
+        IoPriority::BestEffort(level) => (2, level),
+        IoPriority::Idle => (3, 0),
+    };
+    let value = (class << IOPRIO_CLASS_SHIFT) | libc::c_long::from(level);
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, value) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn set_io_priority(_priority: IoPriority) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "ionice is only supported on Linux",
+    ))
+}
+
+fn parse_rlimit(value: &str, sized: bool) -> Result<u64, String> {
+    match value {
+        "unlimited" => Ok(u64::MAX),
+        v if sized => parse_size(v),
+        v => v.parse().map_err(|_| format!("invalid limit {v:?}")),
+    }
+}
+
+impl FromStr for ProcessLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("invalid limit {pair:?}, expected key=value"))?;
+            match key {
+                "nproc" => limits.nproc = Some(parse_rlimit(value, false)?),
+                "cpu" => limits.cpu = Some(parse_rlimit(value, false)?),
+                "fsize" => limits.fsize = Some(parse_rlimit(value, true)?),
+                "nofile" => limits.nofile = Some(parse_rlimit(value, false)?),
+                "core" => limits.core = Some(parse_rlimit(value, true)?),
+                "nice" => {
+                    let nice = value
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|n| (-20..=19).contains(n))
+                        .ok_or_else(|| format!("invalid nice {value:?}, expected -20..19"))?;
+                    limits.nice = Some(nice);
+                }
+                "ionice" => limits.ionice = Some(value.parse()?),
+                _ => return Err(format!("unknown limit {key:?}")),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+/// Parse a `name:spec` profile definition.
+pub fn parse_profile(s: &str) -> Result<(String, ProcessLimits), String> {
+    match s.split_once(':') {
+        Some((name, spec)) if !name.is_empty() => Ok((name.to_owned(), spec.parse()?)),
+        _ => Err(format!("invalid limit profile {s:?}, expected name:spec")),
+    }
+}
+
+/// Parse an `identity=profile` pair allowing a user to select a profile.
+pub fn parse_profile_user(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((identity, profile)) if !identity.is_empty() && !profile.is_empty() => {
+            Ok((identity.to_owned(), profile.to_owned()))
+        }
+        _ => Err(format!(
+            "invalid profile user {s:?}, expected identity=profile"
+        )),
+    }
+}
+
+/// Default limits plus named profiles that override them per session.
+#[derive(Clone, Debug, Default)]
+pub struct LimitProfiles {
+    /// Limits for sessions without a profile (`--limits`).
+    pub default: ProcessLimits,
+    /// Named overrides (`--limit-profile`).
+    pub profiles: HashMap<String, ProcessLimits>,
+    /// Profiles each authenticated user may select themselves
+    /// (`--limit-profile-user`).
+    pub users: HashMap<String, Vec<String>>,
+}
+
+impl LimitProfiles {
+    /// Returns `true` if the client `identity` may select `profile` itself.
+    /// Clients without a user name never can.
+    pub fn may_select(&self, identity: Option<&str>, profile: &str) -> bool {
+        identity
+            .and_then(|name| self.users.get(name))
+            .is_some_and(|profiles| profiles.iter().any(|p| p == profile))
+    }
+
+    /// Limits for a new session: the defaults, overlaid with `profile` if
+    /// given. Returns `None` for an unknown profile.
+    pub fn resolve(&self, profile: Option<&str>) -> Option<ProcessLimits> {
+        match profile {
+            None => Some(self.default.clone()),
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(|limits| self.default.overlay(limits)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let limits: ProcessLimits =
+            "nproc=256, cpu=unlimited,fsize=1G,nofile=1024,core=0,nice=10,ionice=idle"
+                .parse()
+                .unwrap();
+        assert_eq!(limits.nproc, Some(256));
+        assert_eq!(limits.cpu, Some(u64::MAX));
+        assert_eq!(limits.fsize, Some(1 << 30));
+        assert_eq!(limits.core, Some(0));
+        assert_eq!(limits.nice, Some(10));
+        assert_eq!(limits.ionice, Some(IoPriority::Idle));
+        assert!("".parse::<ProcessLimits>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("nproc".parse::<ProcessLimits>().is_err());
+        assert!("stack=1".parse::<ProcessLimits>().is_err());
+        assert!("nice=40".parse::<ProcessLimits>().is_err());
+        assert!("nofile=1G".parse::<ProcessLimits>().is_err());
+        assert!("ionice=best-effort:9".parse::<ProcessLimits>().is_err());
+        assert_eq!(
+            "realtime:0".parse::<IoPriority>(),
+            Ok(IoPriority::Realtime(0))
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let (name, build) = parse_profile("build:nproc=1024,nice=5").unwrap();
+        assert_eq!(name, "build");
+        assert!(parse_profile(":nproc=1").is_err());
+
+        let profiles = LimitProfiles {
+            default: "nproc=128,nofile=256".parse().unwrap(),
+            profiles: HashMap::from([(name, build)]),
+            ..Default::default()
+        };
+        let limits = profiles.resolve(Some("build")).unwrap();
+        assert_eq!(limits.nproc, Some(1024));
+        assert_eq!(limits.nofile, Some(256));
+        assert_eq!(limits.nice, Some(5));
+        assert_eq!(profiles.resolve(None).unwrap().nproc, Some(128));
+        assert_eq!(profiles.resolve(Some("missing")), None);
+    }
+
+    #[test]
+    fn test_profile_users() {
+        let (user, profile) = parse_profile_user("alice=build").unwrap();
+        assert!(parse_profile_user("alice").is_err());
+        assert!(parse_profile_user("=build").is_err());
+
+        let profiles = LimitProfiles {
+            users: HashMap::from([(user, vec![profile])]),
+            ..Default::default()
+        };
+        assert!(profiles.may_select(Some("alice"), "build"));
+        assert!(!profiles.may_select(Some("alice"), "idle"));
+        assert!(!profiles.may_select(Some("bob"), "build"));
+        assert!(!profiles.may_select(None, "build"));
+    }
+}
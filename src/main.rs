@@ -3,12 +3,42 @@
 //! Opens a real PTY in the browser over WebSocket. Each connection is backed by
 //! a persistent session that survives tab closes and reconnects.
 
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::Router;
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
 use clap::Parser;
 use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
 use tracing_subscriber::EnvFilter;
 
+use tty_web::audit::AuditLog;
+use tty_web::cgroup::CgroupConfig;
 use tty_web::config::{Config, LogFormat};
+use tty_web::handoff;
+use tty_web::isolation::Isolation;
+use tty_web::limits::LimitProfiles;
 use tty_web::session::SessionStore;
+use tty_web::spawn::{RunAs, RunAsPolicy};
+use tty_web::spool::SpoolConfig;
+use tty_web::tls::{self, TlsListener};
+use tty_web::unix_socket::UnixSocketConfig;
+use tty_web::web::AppState;
+use tty_web::web::auth::Auth;
+use tty_web::web::headers::SecurityHeaders;
+use tty_web::web::htpasswd::Htpasswd;
+use tty_web::web::jwt::{JwtConfig, JwtVerifier};
+use tty_web::web::limiter::{RateLimit, SessionLimits};
+use tty_web::web::origin::OriginPolicy;
+use tty_web::web::peer::Peer;
+
+/// How long a process started with `--takeover` keeps trying to bind its
+/// listener while the previous one is still exiting.
+const TAKEOVER_BIND_RETRY: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() {
@@ -25,44 +55,416 @@ async fn main() {
             .init(),
     };
 
+    let mut auth = match config.auth_token() {
+        Ok(Some(token)) => Auth::default().with_token(token),
+        Ok(None) => Auth::default(),
+        Err(e) => {
+            tracing::error!("failed to load auth token: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let jwt_config = JwtConfig {
+        audience: config.jwt_audience.clone(),
+        issuer: config.jwt_issuer.clone(),
+        name_claim: config.jwt_user_claim.clone(),
+        run_as_claim: config.jwt_run_as_claim.clone(),
+        profile_claim: config.jwt_profile_claim.clone(),
+    };
+    match config.jwt_secret() {
+        Ok(Some(secret)) => {
+            auth = auth.with_jwt(JwtVerifier::hmac(secret.as_bytes(), jwt_config));
+        }
+        Ok(None) => {
+            if let Some(path) = &config.jwt_jwks {
+                match JwtVerifier::load_jwks(path, jwt_config) {
+                    Ok(verifier) => auth = auth.with_jwt(verifier),
+                    Err(e) => {
+                        tracing::error!("failed to load {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("failed to load JWT secret: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &config.htpasswd {
+        match Htpasswd::load(path) {
+            Ok(htpasswd) => auth = auth.with_htpasswd(htpasswd),
+            Err(e) => {
+                tracing::error!("failed to load {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if !auth.is_enabled() && config.tls_client_ca.is_none() && !config.address.is_loopback() {
+        tracing::warn!(
+            "authentication is disabled while listening on {}; anyone who can reach it gets a shell",
+            config.address
+        );
+    }
+
+    let user_map = RunAsPolicy::parse_user_map(&config.user_map).unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+    let run_as = RunAsPolicy {
+        default_user: config.run_as_user.clone(),
+        group: config.run_as_group.clone(),
+        user_map,
+        claim_users: config.jwt_run_as_allow.clone(),
+    };
+    if let Some(user) = &run_as.default_user {
+        if let Err(e) = RunAs::resolve(user, run_as.group.as_deref()) {
+            tracing::error!("invalid --run-as-user: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let cgroup = config.cgroup_parent.as_ref().map(|parent| CgroupConfig {
+        memory_max: config.cgroup_memory_max,
+        cpu_max: config.cgroup_cpu_max,
+        pids_max: config.cgroup_pids_max,
+        ..CgroupConfig::new(parent)
+    });
+    if let Some(cgroup) = &cgroup {
+        if let Err(e) = cgroup.prepare() {
+            tracing::error!("invalid --cgroup-parent: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let audit = config.audit_log.as_ref().map(|path| {
+        AuditLog::open(path, config.audit_log_max_size, config.audit_log_keep).unwrap_or_else(|e| {
+            tracing::error!("failed to open audit log {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+
+    let spool = config.scrollback_spool.as_ref().map(|dir| {
+        let spool = SpoolConfig::new(dir, config.scrollback_spool_max_size);
+        spool.prepare().unwrap_or_else(|e| {
+            tracing::error!("invalid --scrollback-spool {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+        spool
+    });
+
+    let security_headers = SecurityHeaders::new(
+        config.csp.as_deref(),
+        &config.frame_ancestors,
+        &config.referrer_policy,
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!("invalid security headers: {}", e);
+        std::process::exit(1);
+    });
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(load_tls(cert, key, config.tls_client_ca.as_deref())),
+        _ => None,
+    };
+
     let sessions = SessionStore::new();
     let addr = std::net::SocketAddr::new(config.address, config.port);
-    let orphan_timeout = std::time::Duration::from_secs(config.orphan_timeout);
-    let app = tty_web::web::router(
+    let orphan_timeout = Duration::from_secs(config.orphan_timeout);
+    let idle = config.idle_policy().unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+    let max_lifetime = config.max_session_lifetime();
+    let mut limits = LimitProfiles {
+        default: config.limits.unwrap_or_default(),
+        profiles: config.limit_profiles.into_iter().collect(),
+        ..LimitProfiles::default()
+    };
+    for (user, profile) in config.limit_profile_users {
+        if !limits.profiles.contains_key(&profile) {
+            tracing::error!("--limit-profile-user {}: unknown profile {}", user, profile);
+            std::process::exit(1);
+        }
+        limits.users.entry(user).or_default().push(profile);
+    }
+    let state = AppState::new(
         config.shell,
         config.pwd,
         config.scrollback_limit * 1024,
-        sessions,
+        sessions.clone(),
         orphan_timeout,
-    );
+    )
+    .with_auth(auth.with_secure_cookie(tls.is_some()))
+    .with_origins(OriginPolicy::new(&config.allowed_origins))
+    .with_run_as(run_as)
+    .with_isolation(config.isolate.then_some(Isolation {
+        network: config.isolate_network,
+    }))
+    .with_cgroup(cgroup)
+    .with_limits(limits)
+    .with_session_limits(SessionLimits {
+        max_sessions: config.max_sessions,
+        max_sessions_per_ip: config.max_sessions_per_ip,
+        rate: config.session_rate.map(|per_minute| RateLimit {
+            per_minute,
+            burst: config.session_burst.unwrap_or(per_minute),
+        }),
+    })
+    .with_audit(audit.clone())
+    .with_scrollback_compression(config.scrollback_compression)
+    .with_spool(spool)
+    .with_output_filter(config.output_filter)
+    .with_security_headers(security_headers)
+    .with_client_cert_allow(config.tls_client_allow)
+    .with_idle(idle)
+    .with_max_lifetime(max_lifetime)
+    .with_api_commands(config.api_commands)
+    .with_api_env(config.api_env)
+    .with_admins(config.admins);
+
+    if let (true, Some(path)) = (config.takeover, &config.handoff_socket) {
+        tracing::info!("waiting for a hand-off on {}", path.display());
+        let timeout = Duration::from_secs(config.takeover_timeout);
+        match handoff::takeover(path, timeout).await {
+            Ok(received) => {
+                let restored = handoff::restore(&state, received);
+                tracing::info!("took over {} sessions", restored);
+            }
+            Err(e) => tracing::warn!("no sessions taken over: {}", e),
+        }
+    }
+    if let Some(spool) = &state.spool {
+        let live = sessions.list();
+        let live: Vec<&str> = live.iter().map(|session| session.id()).collect();
+        match spool.remove_stale(&live) {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("removed {} stale scrollback spool files", removed),
+            Err(e) => tracing::warn!("failed to clean {}: {}", spool.dir.display(), e),
+        }
+    }
+    let app = tty_web::web::router_with_state(state);
+    let handoff = Handoff {
+        socket: config.handoff_socket,
+        sessions,
+        audit,
+    };
+
+    if let Some(path) = config.unix_socket {
+        let socket = UnixSocketConfig {
+            mode: config.unix_socket_mode,
+            owner: config.unix_socket_owner,
+            group: config.unix_socket_group,
+            ..UnixSocketConfig::new(path)
+        };
+        let bound = bind(config.takeover, || async { socket.bind() }).await;
+        let (listener, file) = bound.unwrap_or_else(|e| {
+            tracing::error!("failed to bind to {}: {}", socket.path.display(), e);
+            std::process::exit(1);
+        });
+        tracing::info!("listening on unix:{}", file.path().display());
+        serve(listener, app, handoff).await;
+        return;
+    }
 
-    let listener = TcpListener::bind(addr).await.unwrap_or_else(|e| {
+    let bound = bind(config.takeover, || TcpListener::bind(addr)).await;
+    let listener = bound.unwrap_or_else(|e| {
         tracing::error!("failed to bind to {}: {}", addr, e);
         std::process::exit(1);
     });
 
-    tracing::info!("listening on http://{}", addr);
+    let Some(acceptor) = tls else {
+        tracing::info!("listening on http://{}", addr);
+        serve(listener, app, handoff).await;
+        return;
+    };
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
+    if let Some(port) = config.tls_redirect_port {
+        let redirect_addr = std::net::SocketAddr::new(config.address, port);
+        let redirect_listener = TcpListener::bind(redirect_addr).await.unwrap_or_else(|e| {
+            tracing::error!("failed to bind to {}: {}", redirect_addr, e);
+            std::process::exit(1);
+        });
+        tracing::info!("redirecting http://{} to https", redirect_addr);
+        tokio::spawn(async move {
+            let redirect = tls::redirect_router(config.port);
+            if let Err(e) = axum::serve(redirect_listener, redirect).await {
+                tracing::error!("redirect server error: {}", e);
+            }
+        });
+    }
+
+    let listener = TlsListener::new(listener, acceptor).unwrap_or_else(|e| {
+        tracing::error!("failed to start TLS listener: {}", e);
+        std::process::exit(1);
+    });
+    tracing::info!("listening on https://{}", addr);
+    serve(listener, app, handoff).await;
+}
+
+/// Bind a listener with `bind`. With `takeover`, an address still in use is
+/// retried for [`TAKEOVER_BIND_RETRY`]: the previous process only releases it
+/// once it has handed off its sessions.
+async fn bind<T, F, Fut>(takeover: bool, mut bind: F) -> std::io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::io::Result<T>>,
+{
+    let deadline = Instant::now() + TAKEOVER_BIND_RETRY;
+    loop {
+        match bind().await {
+            Err(e)
+                if takeover
+                    && e.kind() == std::io::ErrorKind::AddrInUse
+                    && Instant::now() < deadline =>
+            {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Load the certificate pair and start watching it for changes. With
+/// `client_ca`, clients must present a certificate issued by it.
+fn load_tls(cert: &Path, key: &Path, client_ca: Option<&Path>) -> TlsAcceptor {
+    let resolver = tls::CertResolver::new(cert.to_owned(), key.to_owned())
+        .map(Arc::new)
         .unwrap_or_else(|e| {
-            tracing::error!("server error: {}", e);
+            tracing::error!("failed to load TLS certificate: {}", e);
             std::process::exit(1);
         });
+    let client_verifier = client_ca.map(|ca| {
+        tls::client_verifier(ca).unwrap_or_else(|e| {
+            tracing::error!("failed to load TLS client CA: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let config = tls::server_config(resolver.clone(), client_verifier).unwrap_or_else(|e| {
+        tracing::error!("failed to configure TLS: {}", e);
+        std::process::exit(1);
+    });
+    tokio::spawn(tls::watch_reload(resolver));
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// Where to hand sessions over on SIGUSR2, if anywhere, and the audit log
+/// to flush before exiting.
+struct Handoff {
+    socket: Option<PathBuf>,
+    sessions: Arc<SessionStore>,
+    audit: Option<AuditLog>,
+}
+
+async fn serve<L>(listener: L, app: Router, handoff: Handoff)
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+    for<'a> Peer: Connected<IncomingStream<'a, L>>,
+{
+    let audit = handoff.audit.clone();
+    let result = axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
+        .with_graceful_shutdown(shutdown_signal(handoff))
+        .await;
+    flush_audit(audit).await;
+    if let Err(e) = result {
+        tracing::error!("server error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Wait until the audit log has written every event recorded so far.
+async fn flush_audit(audit: Option<AuditLog>) {
+    if let Some(audit) = audit {
+        let _ = tokio::task::spawn_blocking(move || audit.flush()).await;
+    }
+}
+
+/// Hand every session to the new process waiting on `path`, while still
+/// serving. Exits once the new process has them all; otherwise the sessions
+/// it did not keep are resumed and the server keeps running.
+async fn hand_off(path: &Path, store: &SessionStore, audit: Option<AuditLog>) {
+    let stream = match handoff::connect(path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::error!("failed to connect to {}: {}", path.display(), e);
+            return;
+        }
+    };
+    tracing::info!("received SIGUSR2, handing off to {}", path.display());
+    // Leave output the shells write from now on in the PTYs, for the new
+    // process to read.
+    let sessions = store.list();
+    for session in &sessions {
+        session.quiesce().await;
+    }
+    let sending = sessions.clone();
+    let result = tokio::task::spawn_blocking(move || handoff::send(stream, &sending))
+        .await
+        .unwrap_or_else(|e| {
+            Err(handoff::SendError {
+                taken: Vec::new(),
+                error: std::io::Error::other(e),
+            })
+        });
+    let taken = match result {
+        Ok(sent) => {
+            // Exit without running destructors: dropping the sessions would
+            // hang up the shells the new process now owns.
+            tracing::info!("handed off {} sessions", sent);
+            flush_audit(audit).await;
+            std::process::exit(0);
+        }
+        Err(e) => {
+            tracing::error!(
+                "hand-off failed, {} of {} sessions handed off: {}",
+                e.taken.len(),
+                sessions.len(),
+                e
+            );
+            e.taken
+        }
+    };
+    for session in sessions {
+        if taken.iter().any(|id| id == session.id()) {
+            store.remove(session.id());
+            session.handed_off();
+            // Never drop it: that would hang up the shell.
+            std::mem::forget(session);
+        } else {
+            session.resume();
+        }
+    }
 }
 
-async fn shutdown_signal() {
+/// Wait for Ctrl+C or SIGTERM. On SIGUSR2, when a hand-off socket is
+/// configured, [hand off](hand_off) the sessions; if that fails, keep
+/// serving.
+async fn shutdown_signal(handoff: Handoff) {
+    use tokio::signal::unix::{SignalKind, signal};
+
     let ctrl_c = tokio::signal::ctrl_c();
-    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-        .expect("failed to install SIGTERM handler");
+    tokio::pin!(ctrl_c);
+    let mut sigterm = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    let mut sigusr2 = handoff
+        .socket
+        .as_ref()
+        .map(|_| signal(SignalKind::user_defined2()).expect("failed to install SIGUSR2 handler"));
 
-    tokio::select! {
-        _ = ctrl_c => {
-            tracing::info!("received Ctrl+C, shutting down");
-        }
-        _ = sigterm.recv() => {
-            tracing::info!("received SIGTERM, shutting down");
+    loop {
+        tokio::select! {
+            _ = &mut ctrl_c => {
+                tracing::info!("received Ctrl+C, shutting down");
+                return;
+            }
+            _ = sigterm.recv() => {
+                tracing::info!("received SIGTERM, shutting down");
+                return;
+            }
+            Some(_) = async { sigusr2.as_mut()?.recv().await } => {
+                if let Some(path) = &handoff.socket {
+                    hand_off(path, &handoff.sessions, handoff.audit.clone()).await;
+                }
+            }
         }
     }
 }
@@ -12,20 +12,28 @@ use nix::fcntl;
 use nix::libc;
 use nix::pty::openpty;
 
+use crate::cgroup::Cgroup;
+use crate::isolation;
+use crate::spawn::SpawnOptions;
+
 /// Owns the master side of a PTY and the child shell process.
 pub struct PtyMaster {
     /// Master file descriptor (non-blocking).
     pub master: OwnedFd,
-    /// Child process running the shell.
+    /// Child process running the shell (with namespace isolation, the
+    /// keeper process that exits when the shell does).
     pub child: Child,
+    /// Per-session cgroup the shell was placed in, if configured.
+    pub cgroup: Option<Cgroup>,
 }
 
 impl PtyMaster {
     /// Allocate a new PTY pair, spawn `shell` on the slave side, and return the
     /// master fd set to non-blocking mode.
     ///
-    /// If `pwd` is provided, the shell process starts in that directory.
-    pub fn spawn(shell: &str, pwd: Option<&Path>) -> std::io::Result<Self> {
+    /// If `pwd` is provided, the shell process starts in that directory;
+    /// otherwise a [`RunAs`](crate::spawn::RunAs) user starts in their home.
+    pub fn spawn(shell: &str, pwd: Option<&Path>, opts: &SpawnOptions) -> std::io::Result<Self> {
         let pty = openpty(None, None).map_err(std::io::Error::other)?;
 
         let slave_out = pty.slave.try_clone()?;
@@ -38,14 +46,85 @@ impl PtyMaster {
             .env("TERM", "xterm-256color")
             .env("COLORTERM", "truecolor");
 
-        if let Some(dir) = pwd {
-            cmd.current_dir(dir);
+        if let Some(run_as) = &opts.run_as {
+            cmd.env("HOME", &run_as.home)
+                .env("USER", &run_as.name)
+                .env("LOGNAME", &run_as.name)
+                .env("SHELL", &run_as.shell);
+        }
+        cmd.args(&opts.args)
+            .envs(opts.env.iter().map(|(k, v)| (k, v)));
+
+        match (pwd, &opts.run_as) {
+            (Some(dir), _) => {
+                cmd.current_dir(dir);
+            }
+            (None, Some(run_as)) => {
+                // Like login(1): fall back to / when the home is missing.
+                if run_as.home.is_dir() {
+                    cmd.current_dir(&run_as.home);
+                } else {
+                    cmd.current_dir("/");
+                }
+            }
+            (None, None) => {}
         }
 
+        // Credentials are captured as plain integers so the child only
+        // performs raw syscalls.
+        let credentials = opts.run_as.as_ref().map(|r| {
+            let groups: Vec<libc::gid_t> = r.groups.iter().map(|g| g.as_raw()).collect();
+            (r.uid.as_raw(), r.gid.as_raw(), groups)
+        });
+        let isolation = match &opts.isolation {
+            Some(isolation) => {
+                let (uid, gid) = match &credentials {
+                    Some((uid, gid, _)) => (*uid, *gid),
+                    None => (
+                        nix::unistd::geteuid().as_raw(),
+                        nix::unistd::getegid().as_raw(),
+                    ),
+                };
+                Some(isolation::Prepared::new(isolation, uid, gid)?)
+            }
+            None => None,
+        };
+        let cgroup = opts.cgroup.as_ref().map(Cgroup::create).transpose()?;
+        let procs = cgroup.as_ref().map(Cgroup::open_procs).transpose()?;
+        let procs_fd = procs.as_ref().map(|f| f.as_raw_fd());
+        let limits = opts.limits.clone();
+
         // Safety: pre_exec runs in forked child before exec.
         // Only async-signal-safe libc calls are used.
         let child = unsafe {
-            cmd.pre_exec(|| {
+            cmd.pre_exec(move || {
+                // Join the session cgroup while still privileged, so every
+                // later process (including the isolation keeper) is in it.
+                if let Some(fd) = procs_fd {
+                    if libc::write(fd, b"0".as_ptr().cast(), 1) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                // Raising hard limits or lowering nice needs privileges.
+                limits.apply()?;
+                // Drop privileges next: groups, then gid, then uid. An
+                // unprivileged user namespace must be created by the final
+                // user so that its ID mapping is allowed.
+                if let Some((uid, gid, groups)) = &credentials {
+                    if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    if libc::setgid(*gid) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    if libc::setuid(*uid) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                // Forks: only the shell, as PID 1 of its namespace, returns.
+                if let Some(isolation) = &isolation {
+                    isolation::enter(isolation)?;
+                }
                 if libc::setsid() == -1 {
                     return Err(std::io::Error::last_os_error());
                 }
@@ -58,20 +137,25 @@ impl PtyMaster {
         };
 
         // Set master fd to non-blocking for async I/O
-        let flags =
-            fcntl::fcntl(&pty.master, fcntl::FcntlArg::F_GETFL).map_err(std::io::Error::other)?;
-        let mut flags = fcntl::OFlag::from_bits_truncate(flags);
-        flags.insert(fcntl::OFlag::O_NONBLOCK);
-        fcntl::fcntl(&pty.master, fcntl::FcntlArg::F_SETFL(flags))
-            .map_err(std::io::Error::other)?;
+        set_nonblocking(&pty.master)?;
 
         Ok(PtyMaster {
             master: pty.master,
             child,
+            cgroup,
         })
     }
 }
 
+/// Put `fd` in non-blocking mode, as the async read and write loops need.
+pub fn set_nonblocking(fd: impl AsFd) -> std::io::Result<()> {
+    let flags = fcntl::fcntl(&fd, fcntl::FcntlArg::F_GETFL).map_err(std::io::Error::other)?;
+    let mut flags = fcntl::OFlag::from_bits_truncate(flags);
+    flags.insert(fcntl::OFlag::O_NONBLOCK);
+    fcntl::fcntl(&fd, fcntl::FcntlArg::F_SETFL(flags)).map_err(std::io::Error::other)?;
+    Ok(())
+}
+
 /// Set the terminal window size on a PTY file descriptor.
 ///
 /// Safe wrapper around `ioctl(TIOCSWINSZ)`. The caller must
@@ -99,7 +183,8 @@ mod tests {
 
     #[test]
     fn test_spawn_and_child_alive() {
-        let mut pty = PtyMaster::spawn("/bin/sh", None).expect("spawn /bin/sh");
+        let mut pty =
+            PtyMaster::spawn("/bin/sh", None, &SpawnOptions::default()).expect("spawn /bin/sh");
         // Child should still be running
         assert!(
             pty.child.try_wait().unwrap().is_none(),
@@ -112,7 +197,8 @@ mod tests {
 
     #[test]
     fn test_set_window_size() {
-        let mut pty = PtyMaster::spawn("/bin/sh", None).expect("spawn /bin/sh");
+        let mut pty =
+            PtyMaster::spawn("/bin/sh", None, &SpawnOptions::default()).expect("spawn /bin/sh");
         set_window_size(&pty.master, 40, 120).expect("set_window_size should succeed");
         let _ = pty.child.kill();
         let _ = pty.child.wait();
@@ -121,7 +207,8 @@ mod tests {
     #[test]
     fn test_spawn_with_pwd() {
         let dir = std::env::temp_dir();
-        let mut pty = PtyMaster::spawn("/bin/sh", Some(dir.as_path())).expect("spawn with pwd");
+        let mut pty = PtyMaster::spawn("/bin/sh", Some(dir.as_path()), &SpawnOptions::default())
+            .expect("spawn with pwd");
         assert!(
             pty.child.try_wait().unwrap().is_none(),
             "child should be alive"
@@ -129,4 +216,122 @@ mod tests {
         let _ = pty.child.kill();
         let _ = pty.child.wait();
     }
+
+    #[test]
+    fn test_spawn_with_args_and_env() {
+        let opts = SpawnOptions::default()
+            .with_args(vec![
+                "-c".to_owned(),
+                "echo \"got:$GREETING\"; sleep 5".to_owned(),
+            ])
+            .with_env(vec![("GREETING".to_owned(), "hello".to_owned())]);
+        let mut pty = PtyMaster::spawn("/bin/sh", None, &opts).expect("spawn with args");
+        let out = read_until(&pty, "got:hello");
+        assert!(out.contains("got:hello"), "unexpected output: {out:?}");
+        let _ = pty.child.kill();
+        let _ = pty.child.wait();
+    }
+
+    /// Read from the non-blocking master until `needle` appears.
+    fn read_until(pty: &PtyMaster, needle: &str) -> String {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 1024];
+        for _ in 0..200 {
+            match nix::unistd::read(&pty.master, &mut buf) {
+                Ok(n) if n > 0 => out.extend_from_slice(&buf[..n]),
+                _ => std::thread::sleep(std::time::Duration::from_millis(25)),
+            }
+            if String::from_utf8_lossy(&out).contains(needle) {
+                break;
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    #[test]
+    #[ignore = "needs user namespaces; run with --ignored"]
+    fn test_spawn_isolated() {
+        let opts = SpawnOptions::default().with_isolation(Some(isolation::Isolation::default()));
+        let mut pty = PtyMaster::spawn("/bin/sh", None, &opts).expect("spawn isolated");
+        nix::unistd::write(&pty.master, b"printf 'pid:%s\\n' $$\n").unwrap();
+        let out = read_until(&pty, "pid:1\r");
+        assert!(out.contains("pid:1\r"), "shell should be PID 1: {out:?}");
+
+        // Killing the keeper must take the whole namespace down with it.
+        let keeper = pty.child.id();
+        let children = format!("/proc/{keeper}/task/{keeper}/children");
+        let shell: u32 = std::fs::read_to_string(children)
+            .unwrap()
+            .trim()
+            .parse()
+            .expect("keeper has one child");
+        unsafe { libc::kill(keeper as _, libc::SIGHUP) };
+        pty.child.wait().unwrap();
+        let gone = (0..100).any(|_| {
+            let stat = std::fs::read_to_string(format!("/proc/{shell}/stat"));
+            if stat.is_ok_and(|s| !s.contains(") Z ")) {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                return false;
+            }
+            true
+        });
+        assert!(gone, "shell should die with its keeper");
+    }
+
+    #[test]
+    #[ignore = "needs user namespaces; run with --ignored"]
+    fn test_isolated_shell_receives_signals() {
+        let opts = SpawnOptions::default().with_isolation(Some(isolation::Isolation::default()));
+        let mut pty = PtyMaster::spawn("/bin/sh", None, &opts).expect("spawn isolated");
+        nix::unistd::write(
+            &pty.master,
+            b"trap 'echo got_$((6*7))' INT; echo ready_$((1+1)); while :; do sleep 0.1; done\n",
+        )
+        .unwrap();
+        read_until(&pty, "ready_2\r");
+
+        // What `Terminal::signal` does: signal the keeper's process group.
+        let keeper = pty.child.id() as libc::pid_t;
+        unsafe { libc::kill(-keeper, libc::SIGINT) };
+        let out = read_until(&pty, "got_42\r");
+        assert!(out.contains("got_42\r"), "trap should run: {out:?}");
+        assert!(
+            pty.child.try_wait().unwrap().is_none(),
+            "keeper should survive SIGINT"
+        );
+        let _ = pty.child.kill();
+        let _ = pty.child.wait();
+    }
+
+    #[test]
+    #[ignore = "needs root and user namespaces; run with --ignored"]
+    fn test_spawn_isolated_as_user() {
+        assert!(nix::unistd::geteuid().is_root(), "must run as root");
+        let run_as = crate::spawn::RunAs::resolve("nobody", None).unwrap();
+        let opts = SpawnOptions::default()
+            .with_run_as(Some(run_as))
+            .with_isolation(Some(isolation::Isolation::default()));
+        let mut pty = PtyMaster::spawn("/bin/sh", None, &opts).expect("spawn as nobody");
+        nix::unistd::write(&pty.master, b"printf '%s:%s\\n' $(id -un) $$\n").unwrap();
+        let out = read_until(&pty, "nobody:1\r");
+        assert!(out.contains("nobody:1\r"), "unexpected output: {out:?}");
+        let _ = pty.child.kill();
+        let _ = pty.child.wait();
+    }
+
+    #[test]
+    fn test_spawn_with_limits() {
+        let limits = "nofile=123,core=0,nice=7".parse().unwrap();
+        let opts = SpawnOptions::default().with_limits(limits);
+        let mut pty = PtyMaster::spawn("/bin/sh", None, &opts).expect("spawn with limits");
+        nix::unistd::write(
+            &pty.master,
+            b"printf 'lim:%s:%s:%s\\n' $(ulimit -n) $(ulimit -c) $(nice)\n",
+        )
+        .unwrap();
+        let out = read_until(&pty, "lim:123:0:7\r");
+        assert!(out.contains("lim:123:0:7\r"), "unexpected output: {out:?}");
+        let _ = pty.child.kill();
+        let _ = pty.child.wait();
+    }
 }
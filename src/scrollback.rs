@@ -0,0 +1,258 @@
+//! In-memory scrollback log of a session.
+//!
+//! [`Scrollback`] keeps the most recent [`ScrollbackEvent`]s within a byte
+//! budget, evicting the oldest ones first. With compression enabled, older
+//! events are packed into deflate-compressed [`Block`]s of about
+//! [`BLOCK_SIZE`] bytes whose compressed size is what counts against the
+//! budget, so the same limit holds several times more history of typical
+//! terminal output. The newest events always stay uncompressed, and a block
+//! is evicted as a whole; limits below twice the block size never compress.
+//!
+//! Blocks use the [spool](crate::spool) record encoding and are decompressed
+//! lazily while a [`Replay`](crate::spool::Replay) is streamed to a client.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::session::ScrollbackEvent;
+use crate::spool;
+
+/// Uncompressed size of the events packed into one block.
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+/// A run of events stored compressed.
+#[derive(Clone, Debug)]
+pub(crate) struct Block {
+    data: Arc<[u8]>,
+}
+
+impl Block {
+    fn compress<'a>(events: impl IntoIterator<Item = &'a ScrollbackEvent>) -> Self {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        for event in events {
+            // Writing into a `Vec` cannot fail.
+            encoder.write_all(&spool::encode(event)).unwrap();
+        }
+        Self {
+            data: encoder.finish().unwrap().into(),
+        }
+    }
+
+    /// Decompress the block's events, oldest first.
+    pub(crate) fn events(&self) -> io::Result<Vec<ScrollbackEvent>> {
+        let mut decoder = DeflateDecoder::new(&self.data[..]);
+        let mut events = Vec::new();
+        while let Some(event) = spool::read_record(&mut decoder)? {
+            events.push(event);
+        }
+        // Anything after the last record means the block is corrupt.
+        if decoder.read(&mut [0u8])? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "corrupt scrollback block",
+            ));
+        }
+        Ok(events)
+    }
+}
+
+/// One entry of the scrollback: a single event or a compressed block.
+#[derive(Clone, Debug)]
+pub(crate) enum Chunk {
+    Event(ScrollbackEvent),
+    Block(Block),
+}
+
+impl Chunk {
+    /// Bytes the chunk counts against the scrollback limit.
+    fn cost(&self) -> usize {
+        match self {
+            Self::Event(event) => event.byte_cost(),
+            Self::Block(block) => block.data.len(),
+        }
+    }
+}
+
+/// Scrollback event log with a byte budget.
+pub(crate) struct Scrollback {
+    /// Compressed blocks, then uncompressed events, oldest first.
+    chunks: VecDeque<Chunk>,
+    /// Number of blocks at the front of `chunks`.
+    blocks: usize,
+    /// Cost of the uncompressed events.
+    raw_bytes: usize,
+    /// Cost of all chunks.
+    bytes: usize,
+    limit: usize,
+    compress: bool,
+}
+
+impl Scrollback {
+    /// Log holding up to `limit` bytes, compressing older events if
+    /// `compress` is set.
+    pub(crate) fn new(limit: usize, compress: bool) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            blocks: 0,
+            raw_bytes: 0,
+            bytes: 0,
+            limit,
+            compress,
+        }
+    }
+
+    /// Append an event and return the chunks evicted to stay within the
+    /// limit, oldest first.
+    pub(crate) fn push(&mut self, event: ScrollbackEvent) -> Vec<Chunk> {
+        let cost = event.byte_cost();
+        self.chunks.push_back(Chunk::Event(event));
+        self.raw_bytes += cost;
+        self.bytes += cost;
+        if self.compress && self.raw_bytes >= 2 * BLOCK_SIZE {
+            self.compress_oldest();
+        }
+        let mut evicted = Vec::new();
+        while self.bytes > self.limit {
+            let Some(old) = self.chunks.pop_front() else {
+                break;
+            };
+            let cost = old.cost();
+            self.bytes -= cost;
+            match old {
+                Chunk::Block(_) => self.blocks -= 1,
+                Chunk::Event(_) => self.raw_bytes -= cost,
+            }
+            evicted.push(old);
+        }
+        evicted
+    }
+
+    /// Pack the oldest uncompressed events into a block.
+    fn compress_oldest(&mut self) {
+        let mut end = self.blocks;
+        let mut packed = 0;
+        while packed < BLOCK_SIZE {
+            let Some(chunk) = self.chunks.get(end) else {
+                break;
+            };
+            packed += chunk.cost();
+            end += 1;
+        }
+        let block = Block::compress(
+            self.chunks
+                .range(self.blocks..end)
+                .map(|chunk| match chunk {
+                    Chunk::Event(event) => event,
+                    Chunk::Block(_) => unreachable!("blocks precede events"),
+                }),
+        );
+        let block = Chunk::Block(block);
+        self.raw_bytes -= packed;
+        self.bytes = self.bytes - packed + block.cost();
+        self.chunks.drain(self.blocks..end);
+        self.chunks.insert(self.blocks, block);
+        self.blocks += 1;
+    }
+
+    /// Bytes counted against the limit.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The chunks held, oldest first, for a [`Replay`](crate::spool::Replay).
+    pub(crate) fn chunks(&self) -> Vec<Chunk> {
+        self.chunks.iter().cloned().collect()
+    }
+
+    /// Every event held, oldest first, decompressing blocks. Blocks that fail
+    /// to decompress are skipped.
+    pub(crate) fn events(&self) -> Vec<ScrollbackEvent> {
+        let mut events = Vec::new();
+        for chunk in &self.chunks {
+            match chunk {
+                Chunk::Event(event) => events.push(event.clone()),
+                Chunk::Block(block) => match block.events() {
+                    Ok(block) => events.extend(block),
+                    Err(e) => tracing::warn!("failed to decompress scrollback: {}", e),
+                },
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(n: usize) -> ScrollbackEvent {
+        ScrollbackEvent::Output(format!("line {n:06}: make[2]: Entering directory\r\n").into())
+    }
+
+    #[test]
+    fn test_uncompressed_evicts_whole_events() {
+        let mut sb = Scrollback::new(10, false);
+        assert!(
+            sb.push(ScrollbackEvent::Output(b"aaaaa".to_vec()))
+                .is_empty()
+        );
+        assert!(sb.push(ScrollbackEvent::WindowSize(40, 120)).is_empty());
+        let evicted = sb.push(ScrollbackEvent::Output(b"ccc".to_vec()));
+        assert!(matches!(
+            evicted.as_slice(),
+            [Chunk::Event(ScrollbackEvent::Output(data))] if data == b"aaaaa"
+        ));
+        assert_eq!(sb.bytes(), 7);
+        assert_eq!(sb.blocks, 0);
+    }
+
+    #[test]
+    fn test_compression_holds_more_history() {
+        let limit = 64 * 1024;
+        let mut plain = Scrollback::new(limit, false);
+        let mut compressed = Scrollback::new(limit, true);
+        let events: Vec<_> = (0..20_000).map(output).collect();
+        for event in &events {
+            plain.push(event.clone());
+            compressed.push(event.clone());
+        }
+        assert!(compressed.bytes() <= limit);
+        assert!(compressed.blocks > 0);
+        assert!(compressed.raw_bytes < 2 * BLOCK_SIZE);
+
+        let plain = plain.events();
+        let kept = compressed.events();
+        assert!(
+            kept.len() > 3 * plain.len(),
+            "{} events compressed, {} plain",
+            kept.len(),
+            plain.len()
+        );
+        // The compressed log is a contiguous tail of the history.
+        assert_eq!(kept[..], events[events.len() - kept.len()..]);
+    }
+
+    #[test]
+    fn test_evicted_block_holds_oldest_events() {
+        let mut sb = Scrollback::new(40 * 1024, true);
+        let mut evicted = Vec::new();
+        for n in 0..50_000 {
+            for chunk in sb.push(output(n)) {
+                match chunk {
+                    Chunk::Event(event) => evicted.push(event),
+                    Chunk::Block(block) => evicted.extend(block.events().unwrap()),
+                }
+            }
+        }
+        let kept = sb.events();
+        assert!(sb.blocks > 0);
+        assert_eq!(evicted.len() + kept.len(), 50_000);
+        assert_eq!(evicted.first(), Some(&output(0)));
+        assert_eq!(kept.last(), Some(&output(49_999)));
+    }
+}
@@ -2,29 +2,65 @@
 //!
 //! A [`Session`] wraps a [`Terminal`] and adds:
 //! - a configurable ring-buffer of recent output (scrollback, default 256 KiB),
+//!   optionally [compressing](crate::scrollback) older output and spilling
+//!   evicted output to a [spool](crate::spool) on disk,
 //! - client attach/detach tracking,
-//! - orphan detection (no clients for 60 s → auto-remove).
+//! - orphan detection (no clients for 60 s → auto-remove),
+//! - two secret keys granting [`Access::Control`] or [`Access::View`],
+//! - an optional unique name, usable in place of the ID,
+//! - the creating client's address, for per-client session limits,
+//! - an optional [audit log](crate::audit) recording the shell's exit,
+//! - an optional [idle policy](IdlePolicy) acting on sessions nobody types in,
+//! - an optional maximum lifetime, after which the shell is terminated.
 //!
-//! [`SessionStore`] is the global session registry. Each session gets a reaper
-//! task that periodically checks for removal conditions.
+//! A [`SessionSnapshot`] carries a session across a
+//! [hand-off](crate::handoff) to a new process.
+//!
+//! [`SessionStore`] is the global session registry, resolving sessions by ID
+//! or name. Each session gets a reaper
+//! task that periodically checks for removal conditions and enforces the idle
+//! policy and maximum lifetime.
 
 use std::collections::HashMap;
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock, Weak};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::sync::{broadcast, watch};
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 
+use crate::audit::{AuditEvent, AuditKind, AuditLog};
+use crate::cgroup::CgroupUsage;
+use crate::scrollback::Scrollback;
+use crate::spool::{Replay, Spool, SpoolConfig};
 use crate::terminal::Terminal;
 
 /// Default time without any attached clients before a session is reaped.
 pub const DEFAULT_ORPHAN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
 
-/// Return type of [`Session::attach`]: scrollback events, output stream,
+/// Default time before the idle action at which clients are warned.
+pub const DEFAULT_IDLE_WARNING: Duration = Duration::from_secs(60);
+
+/// Remaining lifetimes at which clients are warned that the session ends.
+const LIFETIME_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(300),
+    Duration::from_secs(60),
+    Duration::from_secs(10),
+];
+
+/// Time the shell gets to exit after `SIGHUP` before it is killed.
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+const NOTICE_CHANNEL_SIZE: usize = 4;
+
+/// Return type of [`Session::attach`]: scrollback replay, output stream,
 /// and window-size watch.
 pub type AttachResult = (
-    Vec<ScrollbackEvent>,
+    Replay,
     broadcast::Receiver<Vec<u8>>,
     watch::Receiver<(u16, u16)>,
 );
@@ -33,17 +69,17 @@ pub type AttachResult = (
 ///
 /// Storing events instead of raw bytes ensures that eviction never splits
 /// an escape sequence and that resize history is preserved for replay.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ScrollbackEvent {
     /// Raw terminal output bytes.
-    Output(Vec<u8>),
+    Output(#[serde(with = "base64_bytes")] Vec<u8>),
     /// PTY window size changed (rows, cols).
     WindowSize(u16, u16),
 }
 
 impl ScrollbackEvent {
     /// Logical byte cost used for eviction accounting.
-    fn byte_cost(&self) -> usize {
+    pub(crate) fn byte_cost(&self) -> usize {
         match self {
             Self::Output(data) => data.len(),
             Self::WindowSize(_, _) => 4,
@@ -51,20 +87,285 @@ impl ScrollbackEvent {
     }
 }
 
+/// What a client attached to a session is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Send input and resize the terminal.
+    Control,
+    /// Read-only: receive output only.
+    View,
+}
+
+/// What happens when nobody has typed in a session for its idle timeout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Send `SIGHUP` to the shell, ending the session.
+    #[default]
+    Hangup,
+    /// Disconnect every client; the shell keeps running and can be
+    /// reattached with its keys.
+    Detach,
+}
+
+impl FromStr for IdleAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hangup" => Ok(Self::Hangup),
+            "detach" => Ok(Self::Detach),
+            _ => Err(format!(
+                "unknown idle action {s:?} (expected hangup or detach)"
+            )),
+        }
+    }
+}
+
+/// Idle-input policy for a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdlePolicy {
+    /// Time without input before [`IdlePolicy::action`] is taken.
+    pub timeout: Duration,
+    /// How long before the timeout attached clients are warned.
+    pub warning: Duration,
+    /// What to do when the timeout elapses.
+    pub action: IdleAction,
+}
+
+impl IdlePolicy {
+    /// Hang up after `timeout`, warning [`DEFAULT_IDLE_WARNING`] before, or
+    /// a quarter of `timeout` before if that is shorter.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            warning: DEFAULT_IDLE_WARNING.min(timeout / 4),
+            action: IdleAction::default(),
+        }
+    }
+
+    /// This policy with the timeout shortened to `timeout`, as a client may
+    /// ask, and the warning to at most a quarter of it. A longer `timeout`
+    /// changes nothing.
+    pub fn shortened(self, timeout: Duration) -> Self {
+        if timeout >= self.timeout {
+            return self;
+        }
+        Self {
+            timeout,
+            warning: self.warning.min(timeout / 4),
+            ..self
+        }
+    }
+}
+
+/// Notice from a session to its attached clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionNotice {
+    /// The idle action will be taken after this long unless input arrives.
+    IdleWarning(Duration),
+    /// The session timed out with [`IdleAction::Detach`]; clients must
+    /// disconnect.
+    IdleDetach,
+    /// The session reaches its maximum lifetime after this long.
+    LifetimeWarning(Duration),
+    /// The session was killed; clients must treat the shell as exited.
+    Killed,
+    /// Another process took the session over; clients must reconnect.
+    HandedOff,
+}
+
+/// Longest accepted session name.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Returns `true` if `name` can name a session: 1 to [`MAX_NAME_LEN`] ASCII
+/// letters, digits, `.`, `_` or `-`, and not a UUID (which would be mistaken
+/// for a session ID).
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+        && uuid::Uuid::parse_str(name).is_err()
+}
+
+/// Generate an unguessable session key (122 random bits, hex-encoded).
+fn generate_key() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Settings for a new [`Session`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SessionOptions {
+    /// Scrollback buffer size in bytes.
+    pub scrollback_limit: usize,
+    /// Store older scrollback compressed (off by default).
+    pub scrollback_compression: bool,
+    /// Time without clients before the session is reaped.
+    pub orphan_timeout: std::time::Duration,
+    /// Address of the client that created the session, for per-client limits.
+    pub client_ip: Option<IpAddr>,
+    /// Audit log for this session's events.
+    pub audit: Option<AuditLog>,
+    /// What to do when nobody types for a while (nothing by default).
+    pub idle: Option<IdlePolicy>,
+    /// Time after which the shell is terminated regardless of activity
+    /// (unlimited by default).
+    pub max_lifetime: Option<Duration>,
+    /// Unique name the session can be found by (see [`is_valid_name`]).
+    pub name: Option<String>,
+    /// User who created the session; they may attach by name without a key.
+    pub owner: Option<String>,
+    /// Where to spool output evicted from the scrollback (dropped by
+    /// default).
+    pub spool: Option<SpoolConfig>,
+}
+
+impl SessionOptions {
+    /// Options with the required settings; everything else is unset.
+    pub fn new(scrollback_limit: usize, orphan_timeout: std::time::Duration) -> Self {
+        Self {
+            scrollback_limit,
+            scrollback_compression: false,
+            orphan_timeout,
+            client_ip: None,
+            audit: None,
+            idle: None,
+            max_lifetime: None,
+            name: None,
+            owner: None,
+            spool: None,
+        }
+    }
+
+    /// Compress older scrollback so the limit holds more history.
+    pub fn with_scrollback_compression(mut self, compress: bool) -> Self {
+        self.scrollback_compression = compress;
+        self
+    }
+
+    /// Record the creating client's address.
+    pub fn with_client_ip(mut self, client_ip: Option<IpAddr>) -> Self {
+        self.client_ip = client_ip;
+        self
+    }
+
+    /// Record session events (and the shell's exit) in `audit`.
+    pub fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Warn, then hang up or detach clients when nobody types for a while.
+    pub fn with_idle(mut self, idle: Option<IdlePolicy>) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Terminate the shell once the session is `max_lifetime` old.
+    pub fn with_max_lifetime(mut self, max_lifetime: Option<Duration>) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Name the session.
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Record the user who created the session.
+    pub fn with_owner(mut self, owner: Option<String>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// Spool output evicted from the scrollback to disk.
+    pub fn with_spool(mut self, spool: Option<SpoolConfig>) -> Self {
+        self.spool = spool;
+        self
+    }
+}
+
+/// State of a session carried over to a new process by a
+/// [hand-off](crate::handoff); the terminal is handed over separately.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    /// Session UUID.
+    pub id: String,
+    /// Session name, if it has one.
+    pub name: Option<String>,
+    /// User who created the session.
+    pub owner: Option<String>,
+    /// Key granting [`Access::Control`].
+    pub control_key: String,
+    /// Key granting [`Access::View`].
+    pub view_key: String,
+    /// Creation time; the maximum lifetime keeps counting from it.
+    pub created: SystemTime,
+    /// Current PTY window size.
+    pub window_size: (u16, u16),
+    /// Scrollback event log, oldest first.
+    pub scrollback: Vec<ScrollbackEvent>,
+    /// Time without clients before the session is reaped.
+    pub orphan_timeout: Duration,
+    /// Address of the client that created the session.
+    pub client_ip: Option<IpAddr>,
+    /// Idle-input policy.
+    pub idle: Option<IdlePolicy>,
+    /// Maximum lifetime.
+    pub max_lifetime: Option<Duration>,
+}
+
+/// Scrollback output as base64 in a [`SessionSnapshot`].
+mod base64_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
 /// A persistent terminal session.
 ///
 /// Tracks connected clients, buffers recent output for replay on reconnect,
-/// and detects when the session becomes orphaned.
+/// and detects when the session becomes orphaned. The session ID alone does
+/// not grant access: clients must also present the control or view key.
 pub struct Session {
     id: String,
+    name: Option<String>,
+    owner: Option<String>,
+    control_key: String,
+    view_key: String,
     pub terminal: Terminal,
-    scrollback: Mutex<VecDeque<ScrollbackEvent>>,
-    scrollback_bytes: Mutex<usize>,
-    scrollback_limit: usize,
+    scrollback: Mutex<Scrollback>,
+    spool: Option<Spool>,
     clients: AtomicUsize,
+    viewers: AtomicUsize,
     detached_at: Mutex<Option<Instant>>,
     window_size: watch::Sender<(u16, u16)>,
     orphan_timeout: std::time::Duration,
+    client_ip: Option<IpAddr>,
+    audit: Option<AuditLog>,
+    idle: Option<IdlePolicy>,
+    last_input: Mutex<Instant>,
+    idle_warned: AtomicBool,
+    created: SystemTime,
+    created_at: Instant,
+    max_lifetime: Option<Duration>,
+    lifetime_warnings: AtomicUsize,
+    notices: broadcast::Sender<SessionNotice>,
+    /// Asks the scrollback collector to catch up; set by `start`.
+    collector: Option<mpsc::Sender<oneshot::Sender<()>>>,
 }
 
 impl Session {
@@ -72,32 +373,198 @@ impl Session {
     ///
     /// `orphan_timeout` controls how long a session with no attached clients
     /// survives before the reaper removes it (default: [`DEFAULT_ORPHAN_TIMEOUT`]).
+    /// The timer starts at creation, so a session nobody attaches to is reaped
+    /// too.
     pub fn new(
         terminal: Terminal,
         output_rx: broadcast::Receiver<Vec<u8>>,
         scrollback_limit: usize,
         orphan_timeout: std::time::Duration,
     ) -> Arc<Self> {
-        let id = uuid::Uuid::new_v4().to_string();
+        Self::with_options(
+            terminal,
+            output_rx,
+            SessionOptions::new(scrollback_limit, orphan_timeout),
+        )
+    }
+
+    /// Create a new session with the given options.
+    pub fn with_options(
+        terminal: Terminal,
+        output_rx: broadcast::Receiver<Vec<u8>>,
+        options: SessionOptions,
+    ) -> Arc<Self> {
+        Self::from_options(terminal, options).start(output_rx)
+    }
+
+    /// Rebuild a session handed over by a previous tty-web process around
+    /// its adopted `terminal`. Clients reattach with the same ID and keys;
+    /// the orphan timer starts over.
+    ///
+    /// The scrollback limit and compression, audit log and spool are taken
+    /// from `options`,
+    /// everything else from the `snapshot`.
+    pub fn restore(
+        terminal: Terminal,
+        output_rx: broadcast::Receiver<Vec<u8>>,
+        snapshot: SessionSnapshot,
+        options: SessionOptions,
+    ) -> Arc<Self> {
+        let spool = options.spool.clone();
+        let options = SessionOptions {
+            orphan_timeout: snapshot.orphan_timeout,
+            ..options
+        }
+        .with_client_ip(snapshot.client_ip)
+        .with_idle(snapshot.idle)
+        .with_max_lifetime(snapshot.max_lifetime)
+        .with_name(snapshot.name)
+        .with_owner(snapshot.owner);
+        let mut session = Self::from_options(terminal, options);
+        // Pick up the spool the previous process left behind.
+        session.spool = spool.map(|config| Spool::open(&config, &snapshot.id));
+        session.id = snapshot.id;
+        session.control_key = snapshot.control_key;
+        session.view_key = snapshot.view_key;
+        session.created = snapshot.created;
+        // `Instant`s do not cross processes: rebase the age on the wall clock.
+        let age = SystemTime::now()
+            .duration_since(snapshot.created)
+            .unwrap_or_default();
+        session.created_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        session.window_size.send_replace(snapshot.window_size);
+        for event in snapshot.scrollback {
+            session.push_scrollback(event);
+        }
+        session.start(output_rx)
+    }
+
+    /// Stop reading the shell's output and wait until everything read so far
+    /// is in the scrollback, so that a [`Session::snapshot`] taken afterwards
+    /// misses nothing: later output stays in the PTY for the process the
+    /// session is handed to. The session shows no further output until
+    /// [`Session::resume`].
+    pub async fn quiesce(&self) {
+        self.terminal.pause().await;
+        let Some(collector) = &self.collector else {
+            return;
+        };
+        let (done_tx, done_rx) = oneshot::channel();
+        if collector.send(done_tx).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+
+    /// Show the shell's output again after a failed hand-off.
+    pub fn resume(&self) {
+        self.terminal.resume();
+    }
+
+    /// Tell attached clients that another process now serves the session.
+    pub fn handed_off(&self) {
+        let _ = self.notices.send(SessionNotice::HandedOff);
+    }
+
+    /// Capture the state a [hand-off](crate::handoff) carries over. Waits
+    /// for the spool to be written, so the new process finds it complete.
+    pub fn snapshot(&self) -> SessionSnapshot {
+        let sb = self.scrollback.lock().unwrap();
+        let scrollback = sb.events();
+        if let Some(spool) = &self.spool {
+            spool.flush();
+        }
+        drop(sb);
+        SessionSnapshot {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            control_key: self.control_key.clone(),
+            view_key: self.view_key.clone(),
+            created: self.created,
+            window_size: self.window_size(),
+            scrollback,
+            orphan_timeout: self.orphan_timeout,
+            client_ip: self.client_ip,
+            idle: self.idle,
+            max_lifetime: self.max_lifetime,
+        }
+    }
+
+    fn from_options(terminal: Terminal, options: SessionOptions) -> Self {
         let (ws_tx, _) = watch::channel((24, 80));
-        let session = Arc::new(Self {
+        let id = uuid::Uuid::new_v4().to_string();
+        Self {
+            spool: options.spool.map(|config| Spool::open(&config, &id)),
             id,
+            name: options.name,
+            owner: options.owner,
+            control_key: generate_key(),
+            view_key: generate_key(),
             terminal,
-            scrollback: Mutex::new(VecDeque::new()),
-            scrollback_bytes: Mutex::new(0),
-            scrollback_limit,
+            scrollback: Mutex::new(Scrollback::new(
+                options.scrollback_limit,
+                options.scrollback_compression,
+            )),
             clients: AtomicUsize::new(0),
-            detached_at: Mutex::new(None),
+            viewers: AtomicUsize::new(0),
+            detached_at: Mutex::new(Some(Instant::now())),
             window_size: ws_tx,
-            orphan_timeout,
-        });
+            orphan_timeout: options.orphan_timeout,
+            client_ip: options.client_ip,
+            audit: options.audit,
+            idle: options.idle,
+            last_input: Mutex::new(Instant::now()),
+            idle_warned: AtomicBool::new(false),
+            created: SystemTime::now(),
+            created_at: Instant::now(),
+            max_lifetime: options.max_lifetime,
+            lifetime_warnings: AtomicUsize::new(0),
+            notices: broadcast::channel(NOTICE_CHANNEL_SIZE).0,
+            collector: None,
+        }
+    }
+
+    /// Share the session and start its background tasks.
+    fn start(mut self, output_rx: broadcast::Receiver<Vec<u8>>) -> Arc<Self> {
+        let (collector_tx, mut collector_rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        self.collector = Some(collector_tx);
+        let session = Arc::new(self);
+
+        if let Some(audit) = session.audit.clone() {
+            let id = session.id.clone();
+            let mut closed_rx = session.terminal.closed();
+            tokio::spawn(async move {
+                let _ = closed_rx.wait_for(|&closed| closed).await;
+                audit.record(&AuditEvent::new(AuditKind::Exit, &id));
+            });
+        }
 
         // Scrollback collector
         let weak: Weak<Session> = Arc::downgrade(&session);
         let mut rx = output_rx;
         tokio::spawn(async move {
             loop {
-                match rx.recv().await {
+                let received = tokio::select! {
+                    received = rx.recv() => received,
+                    Some(done) = collector_rx.recv() => {
+                        // Catch up with everything already broadcast.
+                        loop {
+                            match rx.try_recv() {
+                                Ok(data) => {
+                                    let Some(s) = weak.upgrade() else {
+                                        return;
+                                    };
+                                    s.push_scrollback(ScrollbackEvent::Output(data));
+                                }
+                                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                                Err(_) => break,
+                            }
+                        }
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                match received {
                     Ok(data) => {
                         let Some(s) = weak.upgrade() else {
                             break;
@@ -120,55 +587,268 @@ impl Session {
         &self.id
     }
 
-    /// Push an event into the scrollback ring buffer, evicting old events
-    /// when the byte budget is exceeded.
+    /// Session name, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns `true` if `user` created the session. Sessions created
+    /// without a user name have no owner: only their keys let clients in.
+    pub fn is_owned_by(&self, user: Option<&str>) -> bool {
+        self.owner.is_some() && self.owner.as_deref() == user
+    }
+
+    /// Secret key granting [`Access::Control`].
+    pub fn control_key(&self) -> &str {
+        &self.control_key
+    }
+
+    /// Secret key granting [`Access::View`].
+    pub fn view_key(&self) -> &str {
+        &self.view_key
+    }
+
+    /// Resolve the access level granted by a presented key, or `None` if it
+    /// matches neither. Comparison is constant-time.
+    pub fn authorize(&self, key: &str) -> Option<Access> {
+        let key = key.as_bytes();
+        if bool::from(key.ct_eq(self.control_key.as_bytes())) {
+            Some(Access::Control)
+        } else if bool::from(key.ct_eq(self.view_key.as_bytes())) {
+            Some(Access::View)
+        } else {
+            None
+        }
+    }
+
+    /// Push an event into the scrollback log, evicting old events when the
+    /// byte budget is exceeded. Evicted events go to the spool, if there is
+    /// one; they are only queued under the scrollback lock, so an attach sees
+    /// each event exactly once, and written to disk off the runtime.
     fn push_scrollback(&self, event: ScrollbackEvent) {
-        let cost = event.byte_cost();
         let mut sb = self.scrollback.lock().unwrap();
-        let mut bytes = self.scrollback_bytes.lock().unwrap();
-        *bytes += cost;
-        sb.push_back(event);
-        while *bytes > self.scrollback_limit {
-            if let Some(old) = sb.pop_front() {
-                *bytes -= old.byte_cost();
-            } else {
-                break;
-            }
+        let evicted = sb.push(event);
+        if let Some(spool) = &self.spool {
+            spool.push(evicted);
         }
     }
 
     /// Attach a client: increment the counter, subscribe to live output, and
-    /// return the scrollback event log. The subscription and snapshot are taken
-    /// under the same lock so no output is lost.
+    /// return the scrollback replay, spooled events first. The subscription
+    /// and snapshot are taken under the same lock so no output is lost or
+    /// replayed twice.
     pub fn attach(&self) -> AttachResult {
+        self.attach_as(Access::Control)
+    }
+
+    /// Like [`Session::attach`], counting the client under `access`. Pair
+    /// with [`Session::detach_as`].
+    pub fn attach_as(&self, access: Access) -> AttachResult {
+        if access == Access::View {
+            self.viewers.fetch_add(1, Ordering::Relaxed);
+        }
         self.clients.fetch_add(1, Ordering::Relaxed);
         *self.detached_at.lock().unwrap() = None;
         let sb = self.scrollback.lock().unwrap();
         let rx = self.terminal.subscribe();
         let ws_rx = self.window_size.subscribe();
-        let events: Vec<ScrollbackEvent> = sb.iter().cloned().collect();
+        let (spooled, mut chunks) = match self.spool.as_ref().map(Spool::reader) {
+            Some(Ok((reader, pending))) => (Some(reader), pending),
+            Some(Err(e)) => {
+                tracing::warn!("failed to open scrollback spool: {}", e);
+                (None, Vec::new())
+            }
+            None => (None, Vec::new()),
+        };
+        chunks.extend(sb.chunks());
+        drop(sb);
+        let events = Replay::new(spooled, chunks);
         (events, rx, ws_rx)
     }
 
     /// Update the current PTY window size (broadcast to viewers) and record
     /// the resize in the scrollback log so replay clients see it too.
     pub fn set_window_size(&self, rows: u16, cols: u16) {
-        let _ = self.window_size.send((rows, cols));
+        self.window_size.send_replace((rows, cols));
         self.push_scrollback(ScrollbackEvent::WindowSize(rows, cols));
     }
 
     /// Detach a client. When the last client detaches, the orphan timer starts.
     pub fn detach(&self) {
+        self.detach_as(Access::Control);
+    }
+
+    /// Detach a client attached with [`Session::attach_as`].
+    pub fn detach_as(&self, access: Access) {
+        if access == Access::View {
+            self.viewers.fetch_sub(1, Ordering::Relaxed);
+        }
         if self.clients.fetch_sub(1, Ordering::Relaxed) == 1 {
             *self.detached_at.lock().unwrap() = Some(Instant::now());
         }
     }
 
+    /// Note that a client sent input, restarting the idle timer.
+    pub fn record_input(&self) {
+        *self.last_input.lock().unwrap() = Instant::now();
+        self.idle_warned.store(false, Ordering::Relaxed);
+    }
+
+    /// Time since a client last sent input (or since the session started).
+    pub fn idle_time(&self) -> Duration {
+        self.last_input.lock().unwrap().elapsed()
+    }
+
+    /// The session's idle policy, if any.
+    pub fn idle_policy(&self) -> Option<&IdlePolicy> {
+        self.idle.as_ref()
+    }
+
+    /// Subscribe to notices for attached clients.
+    pub fn notices(&self) -> broadcast::Receiver<SessionNotice> {
+        self.notices.subscribe()
+    }
+
+    /// Enforce the idle policy: warn attached clients once the warning period
+    /// starts, and take the idle action when the timeout elapses.
+    fn check_idle(&self) {
+        let Some(idle) = self.idle else { return };
+        let idle_time = self.idle_time();
+        if idle_time >= idle.timeout {
+            // Restart the timer so a detached session gets a full timeout
+            // once it is reattached.
+            self.record_input();
+            tracing::info!(action = ?idle.action, "session {} timed out idle", self.id);
+            match idle.action {
+                IdleAction::Hangup => {
+                    if let Err(e) = self.terminal.signal(Signal::SIGHUP) {
+                        tracing::warn!("failed to hang up session {}: {e}", self.id);
+                    }
+                }
+                IdleAction::Detach => {
+                    let _ = self.notices.send(SessionNotice::IdleDetach);
+                }
+            }
+        } else if idle_time + idle.warning >= idle.timeout
+            && self.client_count() > 0
+            && !self.idle_warned.swap(true, Ordering::Relaxed)
+        {
+            let _ = self
+                .notices
+                .send(SessionNotice::IdleWarning(idle.timeout - idle_time));
+        }
+    }
+
+    /// Time since the session was created.
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// Time left until the session's maximum lifetime, if it has one.
+    pub fn remaining_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime
+            .map(|max| max.saturating_sub(self.created_at.elapsed()))
+    }
+
+    fn is_expired(&self) -> bool {
+        self.remaining_lifetime() == Some(Duration::ZERO)
+    }
+
+    /// Warn attached clients as the end of the maximum lifetime approaches,
+    /// once per threshold in [`LIFETIME_WARNINGS`].
+    fn check_lifetime(&self) {
+        let Some(remaining) = self.remaining_lifetime() else {
+            return;
+        };
+        let due = LIFETIME_WARNINGS
+            .iter()
+            .filter(|&&w| remaining <= w)
+            .count();
+        if self.lifetime_warnings.fetch_max(due, Ordering::Relaxed) < due {
+            let _ = self.notices.send(SessionNotice::LifetimeWarning(remaining));
+        }
+    }
+
+    /// Send `signal` to the shell's process group and tell attached clients
+    /// that the shell has exited. Meant for sessions already removed from
+    /// the [`SessionStore`]: the shell receives `SIGHUP` when the last client
+    /// is gone, if it survived `signal`.
+    pub fn kill(&self, signal: Signal) -> std::io::Result<()> {
+        let result = self.terminal.signal(signal);
+        let _ = self.notices.send(SessionNotice::Killed);
+        result
+    }
+
+    /// Hang up the shell and kill its process group if it has not exited
+    /// after [`TERMINATE_GRACE`].
+    async fn terminate(&self) {
+        let mut closed_rx = self.terminal.closed();
+        if let Err(e) = self.terminal.signal(Signal::SIGHUP) {
+            tracing::warn!("failed to hang up session {}: {e}", self.id);
+        }
+        if tokio::time::timeout(TERMINATE_GRACE, closed_rx.wait_for(|&closed| closed))
+            .await
+            .is_err()
+        {
+            tracing::warn!("session {} ignored SIGHUP, killing it", self.id);
+            if let Err(e) = self.terminal.signal(Signal::SIGKILL) {
+                tracing::warn!("failed to kill session {}: {e}", self.id);
+            }
+        }
+    }
+
+    /// Address of the client that created the session, if known.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// Audit log for this session, if auditing is enabled.
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit.as_ref()
+    }
+
     /// Number of currently attached clients.
     pub fn client_count(&self) -> usize {
         self.clients.load(Ordering::Relaxed)
     }
 
+    /// Number of currently attached view-only clients.
+    pub fn view_client_count(&self) -> usize {
+        self.viewers.load(Ordering::Relaxed)
+    }
+
+    /// Wall-clock time the session was created.
+    pub fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    /// Current PTY window size (rows, cols).
+    pub fn window_size(&self) -> (u16, u16) {
+        *self.window_size.borrow()
+    }
+
+    /// Byte cost of the events currently held in the scrollback; compressed
+    /// events count with their compressed size.
+    pub fn scrollback_bytes(&self) -> usize {
+        self.scrollback.lock().unwrap().bytes()
+    }
+
+    /// Size of the session's scrollback spool on disk, in bytes.
+    pub fn spooled_bytes(&self) -> u64 {
+        self.spool.as_ref().map_or(0, Spool::len)
+    }
+
+    /// Returns `true` once the shell has exited.
+    pub fn has_exited(&self) -> bool {
+        *self.terminal.closed().borrow()
+    }
+
+    /// Current cgroup resource usage of the shell, if it runs in a session
+    /// cgroup.
+    pub fn resource_usage(&self) -> Option<CgroupUsage> {
+        self.terminal.resource_usage()
+    }
+
     fn is_orphaned(&self) -> bool {
         self.clients.load(Ordering::Relaxed) == 0
             && self
@@ -179,27 +859,61 @@ impl Session {
     }
 }
 
-/// Thread-safe session registry keyed by UUID.
+/// Thread-safe session registry keyed by UUID; sessions can also be looked
+/// up by name.
 pub struct SessionStore {
-    sessions: RwLock<HashMap<String, Arc<Session>>>,
+    sessions: RwLock<Sessions>,
+}
+
+/// Sessions by ID, plus an index of the named ones.
+#[derive(Default)]
+struct Sessions {
+    by_id: HashMap<String, Arc<Session>>,
+    by_name: HashMap<String, String>,
+}
+
+impl Sessions {
+    fn get(&self, id: &str) -> Option<&Arc<Session>> {
+        self.by_id
+            .get(id)
+            .or_else(|| self.by_id.get(self.by_name.get(id)?))
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Arc<Session>> {
+        let session = self.by_id.remove(id)?;
+        if let Some(name) = session.name() {
+            self.by_name.remove(name);
+        }
+        Some(session)
+    }
 }
 
 impl SessionStore {
     /// Create an empty session store.
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            sessions: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(Sessions::default()),
         })
     }
 
     /// Register a session and spawn a reaper task that removes it when the
-    /// shell exits with no clients or the orphan timeout elapses.
-    pub fn insert(self: &Arc<Self>, session: Arc<Session>) {
+    /// shell exits with no clients or the orphan timeout elapses. The reaper
+    /// also enforces the session's idle policy, and terminates the shell and
+    /// removes the session at the end of its maximum lifetime.
+    ///
+    /// Hands the session back if another session already has its name.
+    pub fn insert(self: &Arc<Self>, session: Arc<Session>) -> Result<(), Arc<Session>> {
         let sid = session.id().to_owned();
-        self.sessions
-            .write()
-            .unwrap()
-            .insert(sid.clone(), session.clone());
+        {
+            let mut sessions = self.sessions.write().unwrap();
+            if let Some(name) = session.name() {
+                if sessions.by_name.contains_key(name) {
+                    return Err(session);
+                }
+                sessions.by_name.insert(name.to_owned(), sid.clone());
+            }
+            sessions.by_id.insert(sid.clone(), session.clone());
+        }
 
         // Reaper task: periodically checks for removal conditions
         let store = Arc::downgrade(self);
@@ -208,33 +922,83 @@ impl SessionStore {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 let Some(store) = store.upgrade() else { return };
-                let should_remove = {
+                let (should_remove, expired) = {
                     let sessions = store.sessions.read().unwrap();
-                    match sessions.get(&sid) {
+                    match sessions.by_id.get(&sid) {
+                        Some(s) if s.is_expired() => (true, Some(s.clone())),
                         Some(s) => {
-                            s.is_orphaned()
-                                || (*closed_rx.borrow() && s.clients.load(Ordering::Relaxed) == 0)
+                            s.check_lifetime();
+                            s.check_idle();
+                            let remove = s.is_orphaned()
+                                || (*closed_rx.borrow() && s.clients.load(Ordering::Relaxed) == 0);
+                            (remove, None)
                         }
                         None => return,
                     }
                 };
                 if should_remove {
+                    if expired.is_some() {
+                        tracing::info!("session {sid} reached its maximum lifetime");
+                    }
                     store.sessions.write().unwrap().remove(&sid);
                     tracing::info!("removed session {sid}");
+                    if let Some(session) = expired {
+                        session.terminate().await;
+                    }
                     return;
                 }
             }
         });
+        Ok(())
     }
 
-    /// Look up a session by ID.
+    /// Remove a session by ID or name, returning it if it existed. Its reaper
+    /// stops on its next check.
+    pub fn remove(&self, id: &str) -> Option<Arc<Session>> {
+        let mut sessions = self.sessions.write().unwrap();
+        let id = sessions.get(id)?.id.clone();
+        sessions.remove(&id)
+    }
+
+    /// Look up a session by ID or name.
     pub fn get(&self, id: &str) -> Option<Arc<Session>> {
         self.sessions.read().unwrap().get(id).cloned()
     }
 
     /// Returns `true` if there are no active sessions.
     pub fn is_empty(&self) -> bool {
-        self.sessions.read().unwrap().is_empty()
+        self.sessions.read().unwrap().by_id.is_empty()
+    }
+
+    /// All active sessions, oldest first.
+    pub fn list(&self) -> Vec<Arc<Session>> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .read()
+            .unwrap()
+            .by_id
+            .values()
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| s.created_at);
+        sessions
+    }
+
+    /// Number of active sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.read().unwrap().by_id.len()
+    }
+
+    /// Number of active sessions created from an IP for which `matches`
+    /// returns `true`.
+    pub fn count_by_ip(&self, matches: impl Fn(IpAddr) -> bool) -> usize {
+        self.sessions
+            .read()
+            .unwrap()
+            .by_id
+            .values()
+            .filter(|s| s.client_ip.is_some_and(&matches))
+            .count()
     }
 }
 
@@ -244,14 +1008,26 @@ mod tests {
 
     const TEST_SCROLLBACK_LIMIT: usize = 256 * 1024;
 
+    /// Idle policy for the idle tests: a warning 10 seconds before a
+    /// one-minute timeout.
+    const TEST_IDLE: IdlePolicy = IdlePolicy {
+        timeout: Duration::from_secs(60),
+        warning: Duration::from_secs(10),
+        action: IdleAction::Hangup,
+    };
+
+    fn test_options() -> SessionOptions {
+        SessionOptions::new(TEST_SCROLLBACK_LIMIT, DEFAULT_ORPHAN_TIMEOUT)
+    }
+
     fn spawn_session() -> Arc<Session> {
+        spawn_session_with(test_options())
+    }
+
+    /// Spawn `/bin/sh` in a session with `options`.
+    fn spawn_session_with(options: SessionOptions) -> Arc<Session> {
         let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn /bin/sh");
-        Session::new(
-            terminal,
-            output_rx,
-            TEST_SCROLLBACK_LIMIT,
-            DEFAULT_ORPHAN_TIMEOUT,
-        )
+        Session::with_options(terminal, output_rx, options)
     }
 
     #[tokio::test]
@@ -266,6 +1042,27 @@ mod tests {
 
         session.detach();
         assert_eq!(session.clients.load(Ordering::Relaxed), 1);
+
+        let (_sb3, _rx3, _ws3) = session.attach_as(Access::View);
+        assert_eq!(session.client_count(), 2);
+        assert_eq!(session.view_client_count(), 1);
+        session.detach_as(Access::View);
+        assert_eq!(session.client_count(), 1);
+        assert_eq!(session.view_client_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_keys() {
+        let session = spawn_session();
+        assert_ne!(session.control_key(), session.view_key());
+        assert_ne!(session.control_key(), session.id());
+        assert_eq!(
+            session.authorize(session.control_key()),
+            Some(Access::Control)
+        );
+        assert_eq!(session.authorize(session.view_key()), Some(Access::View));
+        assert_eq!(session.authorize(session.id()), None);
+        assert_eq!(session.authorize(""), None);
     }
 
     #[tokio::test]
@@ -283,6 +1080,15 @@ mod tests {
         assert!(!session.is_orphaned());
     }
 
+    #[tokio::test]
+    async fn test_orphaned_if_never_attached() {
+        let session = spawn_session();
+        assert!(!session.is_orphaned());
+        *session.detached_at.lock().unwrap() =
+            Some(Instant::now() - session.orphan_timeout - std::time::Duration::from_secs(1));
+        assert!(session.is_orphaned());
+    }
+
     #[tokio::test]
     async fn test_orphaned_after_timeout() {
         let session = spawn_session();
@@ -293,6 +1099,123 @@ mod tests {
         assert!(session.is_orphaned());
     }
 
+    #[test]
+    fn test_idle_policy_warning() {
+        let policy = IdlePolicy::new(Duration::from_secs(900));
+        assert_eq!(policy.warning, DEFAULT_IDLE_WARNING);
+        let policy = IdlePolicy::new(Duration::from_secs(20));
+        assert_eq!(policy.warning, Duration::from_secs(5));
+
+        let policy = IdlePolicy {
+            timeout: Duration::from_secs(600),
+            warning: Duration::from_secs(120),
+            action: IdleAction::Detach,
+        };
+        assert_eq!(policy.shortened(Duration::from_secs(900)), policy);
+        let short = policy.shortened(Duration::from_secs(40));
+        assert_eq!(short.timeout, Duration::from_secs(40));
+        assert_eq!(short.warning, Duration::from_secs(10));
+        assert_eq!(short.action, IdleAction::Detach);
+    }
+
+    fn set_idle_time(session: &Session, idle: Duration) {
+        *session.last_input.lock().unwrap() = Instant::now() - idle;
+    }
+
+    #[tokio::test]
+    async fn test_idle_warning_and_detach() {
+        let session = spawn_session_with(test_options().with_idle(Some(IdlePolicy {
+            action: IdleAction::Detach,
+            ..TEST_IDLE
+        })));
+        let mut notices = session.notices();
+        session.check_idle();
+        assert!(notices.try_recv().is_err(), "not idle yet");
+
+        set_idle_time(&session, Duration::from_secs(55));
+        session.check_idle();
+        assert!(notices.try_recv().is_err(), "nobody to warn");
+
+        let (_sb, _rx, _ws) = session.attach();
+        session.check_idle();
+        match notices.try_recv() {
+            Ok(SessionNotice::IdleWarning(remaining)) => {
+                assert!(remaining <= Duration::from_secs(5), "{remaining:?}");
+            }
+            other => panic!("expected warning, got {other:?}"),
+        }
+        session.check_idle();
+        assert!(notices.try_recv().is_err(), "warned only once");
+
+        session.record_input();
+        assert!(session.idle_time() < Duration::from_secs(1));
+
+        set_idle_time(&session, Duration::from_secs(61));
+        session.check_idle();
+        assert_eq!(notices.try_recv(), Ok(SessionNotice::IdleDetach));
+        assert!(
+            session.idle_time() < Duration::from_secs(1),
+            "timer restarts after detaching"
+        );
+        assert!(!*session.terminal.closed().borrow());
+    }
+
+    #[tokio::test]
+    async fn test_idle_hangup() {
+        let session = spawn_session_with(test_options().with_idle(Some(TEST_IDLE)));
+        let mut closed = session.terminal.closed();
+        set_idle_time(&session, Duration::from_secs(61));
+        session.check_idle();
+        let result = tokio::time::timeout(Duration::from_secs(10), closed.wait_for(|&v| v)).await;
+        assert!(result.is_ok(), "shell should exit on SIGHUP");
+    }
+
+    #[tokio::test]
+    async fn test_lifetime_warnings() {
+        let session =
+            spawn_session_with(test_options().with_max_lifetime(Some(Duration::from_secs(3600))));
+        let mut notices = session.notices();
+        session.check_lifetime();
+        assert!(notices.try_recv().is_err(), "far from the end");
+        assert!(session.remaining_lifetime().unwrap() > Duration::from_secs(3590));
+
+        let session =
+            spawn_session_with(test_options().with_max_lifetime(Some(Duration::from_secs(30))));
+        let mut notices = session.notices();
+        session.check_lifetime();
+        match notices.try_recv() {
+            Ok(SessionNotice::LifetimeWarning(remaining)) => {
+                assert!(remaining <= Duration::from_secs(30), "{remaining:?}");
+            }
+            other => panic!("expected warning, got {other:?}"),
+        }
+        session.check_lifetime();
+        assert!(notices.try_recv().is_err(), "one warning per threshold");
+        assert!(!session.is_expired());
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_terminated_and_removed() {
+        let store = SessionStore::new();
+        let session =
+            spawn_session_with(test_options().with_max_lifetime(Some(Duration::from_millis(500))));
+        let id = session.id().to_owned();
+        let mut closed = session.terminal.closed();
+        let (_sb, _rx, _ws) = session.attach();
+        assert!(store.insert(session).is_ok());
+
+        let result = tokio::time::timeout(Duration::from_secs(10), closed.wait_for(|&v| v)).await;
+        assert!(result.is_ok(), "shell should be terminated");
+        assert!(store.get(&id).is_none(), "removed despite attached client");
+    }
+
+    #[test]
+    fn test_idle_action_from_str() {
+        assert_eq!("hangup".parse(), Ok(IdleAction::Hangup));
+        assert_eq!("detach".parse(), Ok(IdleAction::Detach));
+        assert!("lock".parse::<IdleAction>().is_err());
+    }
+
     #[tokio::test]
     async fn test_scrollback_captures_output() {
         let session = spawn_session();
@@ -305,27 +1228,153 @@ mod tests {
 
         tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
-        let (events, _rx, _ws) = session.attach();
-        let has_marker = events.iter().any(|e| match e {
+        let (mut events, _rx, _ws) = session.attach();
+        let has_marker = events.any(|e| match e {
             ScrollbackEvent::Output(data) => {
-                String::from_utf8_lossy(data).contains("scrollback_test_marker")
+                String::from_utf8_lossy(&data).contains("scrollback_test_marker")
             }
             _ => false,
         });
         assert!(has_marker, "scrollback should contain Output with marker");
     }
 
+    #[tokio::test]
+    async fn test_quiesce_and_resume() {
+        let session = spawn_session();
+        let (_sb, mut rx, _ws) = session.attach();
+        session.quiesce().await;
+        session
+            .terminal
+            .write(b"echo paused_$((6*7))\n".to_vec())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(rx.try_recv().is_err(), "no output while quiesced");
+
+        session.resume();
+        let mut output = String::new();
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !output.contains("paused_42") {
+                let Ok(data) = rx.recv().await else {
+                    break;
+                };
+                output.push_str(&String::from_utf8_lossy(&data));
+            }
+        })
+        .await;
+        assert!(output.contains("paused_42"), "output: {output:?}");
+    }
+
     #[tokio::test]
     async fn test_session_store_insert_and_get() {
         let store = SessionStore::new();
         let session = spawn_session();
         let id = session.id().to_owned();
-        store.insert(session);
+        assert!(store.insert(session).is_ok());
 
         assert!(store.get(&id).is_some());
         assert!(store.get("nonexistent").is_none());
     }
 
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("build"));
+        assert!(is_valid_name("ci-2.x_nightly"));
+        assert!(is_valid_name(&"a".repeat(MAX_NAME_LEN)));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(&"a".repeat(MAX_NAME_LEN + 1)));
+        assert!(!is_valid_name("my build"));
+        assert!(!is_valid_name("../etc"));
+        assert!(!is_valid_name("6f1c2a9e-3b7d-4e0a-9c51-2d8f4b6a7e13"));
+    }
+
+    #[tokio::test]
+    async fn test_session_store_names() {
+        let store = SessionStore::new();
+        let build = spawn_session_with(
+            test_options()
+                .with_name(Some("build".to_owned()))
+                .with_owner(Some("alice".to_owned())),
+        );
+        let id = build.id().to_owned();
+        assert!(store.insert(build).is_ok());
+        assert!(store.insert(spawn_session()).is_ok());
+
+        let found = store.get("build").expect("found by name");
+        assert_eq!(found.id(), id);
+        assert_eq!(found.name(), Some("build"));
+        assert!(found.is_owned_by(Some("alice")));
+        assert!(!found.is_owned_by(Some("bob")));
+        assert!(!found.is_owned_by(None));
+        assert!(store.get(&id).is_some());
+
+        let duplicate = spawn_session_with(
+            test_options()
+                .with_name(Some("build".to_owned()))
+                .with_owner(Some("bob".to_owned())),
+        );
+        let rejected = store.insert(duplicate).unwrap_err();
+        assert_eq!(rejected.name(), Some("build"));
+        assert_eq!(store.len(), 2);
+
+        assert_eq!(store.remove("build").map(|s| s.id().to_owned()), Some(id));
+        assert!(store.get("build").is_none());
+        let rebuilt = spawn_session_with(
+            test_options()
+                .with_name(Some("build".to_owned()))
+                .with_owner(None),
+        );
+        let id = rebuilt.id().to_owned();
+        assert!(store.insert(rebuilt).is_ok());
+        // Removing by ID frees the name too.
+        assert!(store.remove(&id).is_some());
+        assert!(store.get("build").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_session_store_remove_and_kill() {
+        let store = SessionStore::new();
+        let session = spawn_session();
+        let id = session.id().to_owned();
+        assert!(store.insert(session).is_ok());
+        let mut closed = store.get(&id).unwrap().terminal.closed();
+
+        let session = store.remove(&id).expect("session exists");
+        assert!(store.get(&id).is_none());
+        assert!(store.remove(&id).is_none());
+
+        let mut notices = session.notices();
+        session.kill(Signal::SIGKILL).unwrap();
+        assert_eq!(notices.try_recv(), Ok(SessionNotice::Killed));
+        let result = tokio::time::timeout(Duration::from_secs(10), closed.wait_for(|&v| v)).await;
+        assert!(result.is_ok(), "shell should be killed");
+    }
+
+    #[tokio::test]
+    async fn test_session_store_list() {
+        let store = SessionStore::new();
+        let first = spawn_session();
+        let second = spawn_session();
+        assert!(store.insert(second.clone()).is_ok());
+        assert!(store.insert(first.clone()).is_ok());
+
+        let ids: Vec<_> = store.list().iter().map(|s| s.id().to_owned()).collect();
+        assert_eq!(ids, [first.id(), second.id()], "oldest first");
+    }
+
+    #[tokio::test]
+    async fn test_session_store_count_by_ip() {
+        let store = SessionStore::new();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let session = spawn_session_with(test_options().with_client_ip(Some(ip)));
+        assert!(store.insert(session).is_ok());
+        assert!(store.insert(spawn_session()).is_ok());
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.count_by_ip(|client| client == ip), 1);
+        assert_eq!(store.count_by_ip(|client| client.is_ipv6()), 0);
+    }
+
     #[tokio::test]
     async fn test_scrollback_eviction_removes_whole_events() {
         let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
@@ -335,20 +1384,80 @@ mod tests {
         session.push_scrollback(ScrollbackEvent::Output(b"bbbbb".to_vec())); // 5, total 10
         session.push_scrollback(ScrollbackEvent::Output(b"ccc".to_vec())); // 3, total 13 → evict
 
-        let sb = session.scrollback.lock().unwrap();
-        let bytes = *session.scrollback_bytes.lock().unwrap();
+        let sb = session.scrollback.lock().unwrap().events();
+        let bytes = session.scrollback_bytes();
         assert!(bytes <= 10, "bytes {bytes} should be within limit");
         assert!(
             sb.iter().all(|e| matches!(e, ScrollbackEvent::Output(_))),
             "all events should be Output"
         );
         assert_ne!(
-            sb.front(),
+            sb.first(),
             Some(&ScrollbackEvent::Output(b"aaaaa".to_vec())),
             "oldest event should have been evicted"
         );
     }
 
+    #[tokio::test]
+    async fn test_scrollback_spools_evicted_events() {
+        let dir =
+            std::env::temp_dir().join(format!("tty-web-session-spool-{}", std::process::id()));
+        let spool = SpoolConfig::new(&dir, 1 << 20);
+        spool.prepare().unwrap();
+        // `cat` prints nothing by itself, so the scrollback holds only our events.
+        let (terminal, output_rx) = Terminal::spawn("/bin/cat", None).expect("spawn");
+        let options = SessionOptions::new(10, DEFAULT_ORPHAN_TIMEOUT).with_spool(Some(spool));
+        let session = Session::with_options(terminal, output_rx, options);
+
+        session.push_scrollback(ScrollbackEvent::Output(b"aaaaa".to_vec()));
+        session.push_scrollback(ScrollbackEvent::Output(b"bbbbb".to_vec()));
+        session.push_scrollback(ScrollbackEvent::Output(b"ccc".to_vec())); // evicts aaaaa
+        assert!(session.scrollback_bytes() <= 10);
+        // Spooling happens in the background.
+        session.spool.as_ref().unwrap().flush();
+        assert_eq!(session.spooled_bytes(), 5 + 5);
+
+        let (events, _rx, _ws) = session.attach();
+        assert_eq!(
+            events.collect::<Vec<_>>(),
+            [
+                ScrollbackEvent::Output(b"aaaaa".to_vec()),
+                ScrollbackEvent::Output(b"bbbbb".to_vec()),
+                ScrollbackEvent::Output(b"ccc".to_vec()),
+            ]
+        );
+
+        let path = dir.join(format!("{}.spool", session.id()));
+        assert!(path.exists());
+        drop(session);
+        assert!(!path.exists(), "spool is removed with the session");
+    }
+
+    #[tokio::test]
+    async fn test_compressed_scrollback_replays_in_order() {
+        let (terminal, output_rx) = Terminal::spawn("/bin/cat", None).expect("spawn");
+        let options = SessionOptions::new(64 * 1024, DEFAULT_ORPHAN_TIMEOUT)
+            .with_scrollback_compression(true);
+        let session = Session::with_options(terminal, output_rx, options);
+
+        let lines: Vec<_> = (0..10_000)
+            .map(|n| ScrollbackEvent::Output(format!("{n:05} compiling crate\r\n").into()))
+            .collect();
+        for line in &lines {
+            session.push_scrollback(line.clone());
+        }
+        session.set_window_size(40, 120);
+        assert!(session.scrollback_bytes() <= 64 * 1024);
+
+        let (events, _rx, _ws) = session.attach();
+        let events: Vec<_> = events.collect();
+        let (last, output) = events.split_last().unwrap();
+        assert_eq!(last, &ScrollbackEvent::WindowSize(40, 120));
+        // More than the limit of raw output is kept, oldest first.
+        assert!(output.len() * 23 > 64 * 1024, "{} lines kept", output.len());
+        assert_eq!(output, &lines[lines.len() - output.len()..]);
+    }
+
     #[tokio::test]
     async fn test_set_window_size_records_event() {
         let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
@@ -361,7 +1470,7 @@ mod tests {
 
         session.set_window_size(40, 120);
 
-        let sb = session.scrollback.lock().unwrap();
+        let sb = session.scrollback.lock().unwrap().events();
         let has_ws = sb
             .iter()
             .any(|e| matches!(e, ScrollbackEvent::WindowSize(40, 120)));
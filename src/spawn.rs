@@ -0,0 +1,318 @@
+//! Options controlling how session shells are spawned.
+//!
+//! [`SpawnOptions`] is passed to [`Terminal::spawn_with`](crate::terminal::Terminal::spawn_with)
+//! and applied in the forked child right before `exec`. The defaults spawn
+//! the shell exactly like the server process itself: same user, same
+//! environment, no arguments. The output filter is the one setting that does
+//! not affect the process itself; it is applied to everything the shell
+//! writes.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use nix::unistd::{Gid, Group, Uid, User};
+
+use crate::cgroup::CgroupConfig;
+use crate::filter::OutputPolicy;
+use crate::isolation::Isolation;
+use crate::limits::ProcessLimits;
+
+/// Settings applied to the shell process before `exec`.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct SpawnOptions {
+    /// Drop privileges to this user before starting the shell.
+    pub run_as: Option<RunAs>,
+    /// Start the shell in its own Linux namespaces.
+    pub isolation: Option<Isolation>,
+    /// Place the shell in its own cgroup with resource limits.
+    pub cgroup: Option<CgroupConfig>,
+    /// rlimits and scheduling priorities for the shell.
+    pub limits: ProcessLimits,
+    /// Which control strings in the shell's output reach clients.
+    pub output_filter: Arc<OutputPolicy>,
+    /// Arguments passed to the shell.
+    pub args: Vec<String>,
+    /// Extra environment variables, set after (and overriding) the defaults.
+    pub env: Vec<(String, String)>,
+}
+
+impl SpawnOptions {
+    /// Run the shell as the given user.
+    pub fn with_run_as(mut self, run_as: Option<RunAs>) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Isolate the shell in fresh namespaces (Linux only).
+    pub fn with_isolation(mut self, isolation: Option<Isolation>) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Run the shell in a per-session cgroup under the configured parent.
+    pub fn with_cgroup(mut self, cgroup: Option<CgroupConfig>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Apply rlimits, nice and ionice to the shell.
+    pub fn with_limits(mut self, limits: ProcessLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Filter control strings in the shell's output.
+    pub fn with_output_filter(mut self, policy: Arc<OutputPolicy>) -> Self {
+        self.output_filter = policy;
+        self
+    }
+
+    /// Pass `args` to the shell.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Set extra environment variables for the shell.
+    pub fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+}
+
+/// A resolved unix account to run a shell as.
+///
+/// Resolution (passwd/group lookups) happens in the parent process, because
+/// those lookups are not async-signal-safe and must not run after `fork`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunAs {
+    /// Login name (`USER` / `LOGNAME`).
+    pub name: String,
+    /// Target user ID.
+    pub uid: Uid,
+    /// Target primary group ID.
+    pub gid: Gid,
+    /// Supplementary group IDs, as `initgroups(3)` would set them.
+    pub groups: Vec<Gid>,
+    /// Home directory (`HOME`, and the default working directory).
+    pub home: PathBuf,
+    /// Login shell from the passwd entry (`SHELL`).
+    pub shell: PathBuf,
+}
+
+impl RunAs {
+    /// Look up `user` (name or numeric UID) and optionally override the
+    /// primary group with `group` (name or numeric GID).
+    pub fn resolve(user: &str, group: Option<&str>) -> std::io::Result<Self> {
+        let entry = lookup_user(user)?;
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => entry.gid,
+        };
+        let groups = supplementary_groups(&entry.name, gid)?;
+        Ok(Self {
+            name: entry.name,
+            uid: entry.uid,
+            gid,
+            groups,
+            home: entry.dir,
+            shell: entry.shell,
+        })
+    }
+}
+
+fn not_found(what: &str, name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{what} {name} not found"),
+    )
+}
+
+pub(crate) fn lookup_user(user: &str) -> std::io::Result<User> {
+    let entry = match user.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(user),
+    };
+    entry
+        .map_err(std::io::Error::from)?
+        .ok_or_else(|| not_found("user", user))
+}
+
+pub(crate) fn lookup_group(group: &str) -> std::io::Result<Gid> {
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(Gid::from_raw(gid));
+    }
+    Group::from_name(group)
+        .map_err(std::io::Error::from)?
+        .map(|g| g.gid)
+        .ok_or_else(|| not_found("group", group))
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn supplementary_groups(name: &str, gid: Gid) -> std::io::Result<Vec<Gid>> {
+    let name = CString::new(name).map_err(std::io::Error::other)?;
+    nix::unistd::getgrouplist(&name, gid).map_err(std::io::Error::from)
+}
+
+#[cfg(target_vendor = "apple")]
+fn supplementary_groups(name: &str, gid: Gid) -> std::io::Result<Vec<Gid>> {
+    CString::new(name).map_err(std::io::Error::other)?;
+    Ok(vec![gid])
+}
+
+/// Chooses the unix account for a new session from the client's identity.
+///
+/// Authenticated users listed in the user map run as their mapped account;
+/// everyone else runs as the default user. If a user map is configured but
+/// there is no default, unmapped identities are refused rather than silently
+/// running as the server's own user.
+#[derive(Clone, Debug, Default)]
+pub struct RunAsPolicy {
+    /// Account for sessions without a mapping (`--run-as-user`).
+    pub default_user: Option<String>,
+    /// Primary group override (`--run-as-group`).
+    pub group: Option<String>,
+    /// Authenticated identity → unix user (`--user-map`).
+    pub user_map: HashMap<String, String>,
+    /// Unix users a credential may name besides the user map's targets
+    /// (`--jwt-run-as-allow`). Root must be listed here explicitly.
+    pub claim_users: Vec<String>,
+}
+
+impl RunAsPolicy {
+    /// Parse `identity=user` pairs into a user map.
+    pub fn parse_user_map(
+        pairs: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<HashMap<String, String>, String> {
+        pairs
+            .into_iter()
+            .map(|pair| {
+                let pair = pair.as_ref();
+                match pair.split_once('=') {
+                    Some((identity, user)) if !identity.is_empty() && !user.is_empty() => {
+                        Ok((identity.to_owned(), user.to_owned()))
+                    }
+                    _ => Err(format!(
+                        "invalid user mapping {pair:?}, expected identity=user"
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    /// Resolve the account for a client, or `Ok(None)` to keep the server's
+    /// own user.
+    pub fn resolve(&self, identity: Option<&str>) -> std::io::Result<Option<RunAs>> {
+        let mapped = identity.and_then(|name| self.user_map.get(name));
+        let user = match (mapped, &self.default_user) {
+            (Some(user), _) | (None, Some(user)) => user,
+            (None, None) if self.user_map.is_empty() => return Ok(None),
+            (None, None) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!(
+                        "no unix user mapped for {}",
+                        identity.unwrap_or("anonymous")
+                    ),
+                ));
+            }
+        };
+        RunAs::resolve(user, self.group.as_deref()).map(Some)
+    }
+
+    /// Resolve the account a credential asks for (a JWT's run-as claim). It
+    /// must be listed in `claim_users` or be a user map target; root only
+    /// counts if listed in `claim_users`.
+    pub fn resolve_claim(&self, user: &str) -> std::io::Result<RunAs> {
+        let run_as = RunAs::resolve(user, self.group.as_deref())?;
+        let names = |entry: &String| {
+            entry == user
+                || *entry == run_as.name
+                || entry.parse::<u32>() == Ok(run_as.uid.as_raw())
+        };
+        let allowed = self.claim_users.iter().any(names)
+            || (!run_as.uid.is_root() && self.user_map.values().any(names));
+        if !allowed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("unix user {user} is not allowed for credentials"),
+            ));
+        }
+        Ok(run_as)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_root() {
+        let run_as = RunAs::resolve("root", None).expect("root exists");
+        assert_eq!(run_as.uid, Uid::from_raw(0));
+        assert_eq!(run_as.name, "root");
+        assert!(run_as.groups.contains(&run_as.gid));
+        assert_eq!(
+            RunAs::resolve("0", Some("0")).unwrap().gid,
+            Gid::from_raw(0)
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown_user() {
+        let err = RunAs::resolve("tty-web-no-such-user", None).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_parse_user_map() {
+        let map = RunAsPolicy::parse_user_map(["alice=dev", "bob=ops"]).unwrap();
+        assert_eq!(map["alice"], "dev");
+        assert!(RunAsPolicy::parse_user_map(["alice"]).is_err());
+        assert!(RunAsPolicy::parse_user_map(["=dev"]).is_err());
+    }
+
+    #[test]
+    fn test_policy_resolution() {
+        assert_eq!(RunAsPolicy::default().resolve(Some("alice")).unwrap(), None);
+
+        let policy = RunAsPolicy {
+            user_map: HashMap::from([("alice".to_owned(), "root".to_owned())]),
+            ..Default::default()
+        };
+        assert_eq!(policy.resolve(Some("alice")).unwrap().unwrap().name, "root");
+        let err = policy.resolve(Some("mallory")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_resolve_claim() {
+        let refused = |policy: &RunAsPolicy, user: &str| {
+            policy.resolve_claim(user).unwrap_err().kind() == std::io::ErrorKind::PermissionDenied
+        };
+        assert!(refused(&RunAsPolicy::default(), "nobody"));
+        assert!(refused(&RunAsPolicy::default(), "root"));
+
+        // User map targets may be claimed, except root.
+        let policy = RunAsPolicy {
+            user_map: HashMap::from([
+                ("alice".to_owned(), "nobody".to_owned()),
+                ("bob".to_owned(), "root".to_owned()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(policy.resolve_claim("nobody").unwrap().name, "nobody");
+        assert!(refused(&policy, "root"));
+        assert!(refused(&policy, "0"));
+
+        let policy = RunAsPolicy {
+            claim_users: vec!["root".to_owned()],
+            ..Default::default()
+        };
+        assert!(policy.resolve_claim("0").unwrap().uid.is_root());
+        assert!(refused(&policy, "nobody"));
+    }
+}
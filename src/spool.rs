@@ -0,0 +1,581 @@
+//! On-disk spill-over for session scrollback.
+//!
+//! With a [`SpoolConfig`], events evicted from a session's in-memory
+//! scrollback are appended to a per-session spool file instead of being
+//! dropped, and replay on attach ([`Replay`]) streams them back from disk
+//! before the events still held in memory, reading on a blocking thread
+//! ([`Replay::stream`]). Long build logs survive a reconnect without keeping
+//! all of them in RAM.
+//!
+//! A spool is at most two files in the spool directory, `<session>.spool`
+//! and `<session>.spool.1`. When the current file would grow past half the
+//! size cap it replaces the older one, so the spool keeps the most recent
+//! history within the cap. Files are written off the async runtime, created
+//! with mode `600` and removed when the session is dropped. Files left behind
+//! by a process that [handed off](crate::handoff) its sessions are picked up
+//! again.
+//!
+//! Each event is stored as a tag byte (`0` output, `1` window size), a
+//! big-endian `u32` payload length and the payload; a window size is rows
+//! and cols as big-endian `u16`s.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Take, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::scrollback::Chunk;
+use crate::session::ScrollbackEvent;
+
+const TAG_OUTPUT: u8 = 0;
+const TAG_WINDOW_SIZE: u8 = 1;
+const HEADER_LEN: usize = 5;
+
+/// Events [`Replay::stream`] reads ahead of the client.
+const REPLAY_BUFFER: usize = 64;
+
+/// Largest accepted record payload. Output chunks are at most a PTY read
+/// buffer, so anything bigger means the spool is corrupt.
+const MAX_PAYLOAD_LEN: usize = 1 << 20;
+
+/// Where evicted scrollback is spooled and how much of it to keep.
+#[derive(Clone, Debug)]
+pub struct SpoolConfig {
+    /// Directory holding the spool files.
+    pub dir: PathBuf,
+    /// Maximum size of one session's spool in bytes.
+    pub max_bytes: u64,
+}
+
+impl SpoolConfig {
+    /// Spool into `dir`, keeping up to `max_bytes` per session.
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        Self {
+            dir: dir.into(),
+            max_bytes,
+        }
+    }
+
+    /// Create the spool directory (mode `700`) if it does not exist yet.
+    pub fn prepare(&self) -> io::Result<()> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+    }
+
+    /// Remove spool files left behind by sessions other than `live`, such as
+    /// those of a previous process that exited without handing them off.
+    /// Returns the number of files removed.
+    pub fn remove_stale(&self, live: &[&str]) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let id = name
+                .strip_suffix(".spool.1")
+                .or(name.strip_suffix(".spool"));
+            if id.is_some_and(|id| !live.contains(&id)) {
+                std::fs::remove_file(self.dir.join(name))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// A session's spool files.
+///
+/// Evicted chunks are queued by [`Spool::push`] and written by a drain task
+/// on tokio's blocking pool, so a slow disk never holds up the PTY read loop
+/// or an attach. Readers see the bytes written so far plus the chunks still
+/// queued, so no event is lost or replayed twice.
+pub(crate) struct Spool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    /// Current file; the older one has a `.1` suffix.
+    path: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+    /// Signalled when the queue has been drained.
+    drained: Condvar,
+    /// File being appended to; only used by the drain task.
+    file: Mutex<Option<File>>,
+}
+
+/// What readers see, changed under one lock by the drain task.
+struct State {
+    /// Sizes of the older and the current file written so far.
+    sizes: [u64; 2],
+    /// Chunks queued for writing, oldest first.
+    pending: VecDeque<Chunk>,
+    draining: bool,
+    failed: bool,
+}
+
+impl Spool {
+    /// Spool for session `id`, continuing any files already there.
+    pub(crate) fn open(config: &SpoolConfig, id: &str) -> Self {
+        let path = config.dir.join(format!("{id}.spool"));
+        let size = |path: &Path| std::fs::metadata(path).map_or(0, |m| m.len());
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    sizes: [size(&older(&path)), size(&path)],
+                    pending: VecDeque::new(),
+                    draining: false,
+                    failed: false,
+                }),
+                drained: Condvar::new(),
+                file: Mutex::new(None),
+                path,
+                max_bytes: config.max_bytes,
+            }),
+        }
+    }
+
+    /// Queue evicted chunks for writing. After a write error the error is
+    /// logged and further chunks are dropped as if there were no spool.
+    pub(crate) fn push(&self, chunks: Vec<Chunk>) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.failed || chunks.is_empty() {
+                return;
+            }
+            state.pending.extend(chunks);
+            if state.draining {
+                return;
+            }
+            state.draining = true;
+        }
+        let shared = self.shared.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(move || shared.drain())),
+            Err(_) => shared.drain(),
+        }
+    }
+
+    /// Wait until every queued chunk has been written.
+    pub(crate) fn flush(&self) {
+        let state = self.shared.state.lock().unwrap();
+        let _state = self
+            .shared
+            .drained
+            .wait_while(state, |state| state.draining)
+            .unwrap();
+    }
+
+    /// Total size of the spool files in bytes, not counting queued chunks.
+    pub(crate) fn len(&self) -> u64 {
+        let state = self.shared.state.lock().unwrap();
+        state.sizes[0] + state.sizes[1]
+    }
+
+    /// Reader over the events spooled so far, oldest first, and the chunks
+    /// still queued, which follow them. Events pushed later are not
+    /// included.
+    pub(crate) fn reader(&self) -> io::Result<(SpoolReader, Vec<Chunk>)> {
+        let path = &self.shared.path;
+        // Holding the state lock keeps the drain task from rotating the
+        // files while they are opened.
+        let state = self.shared.state.lock().unwrap();
+        let mut files = VecDeque::new();
+        for (path, size) in [
+            (older(path), state.sizes[0]),
+            (path.clone(), state.sizes[1]),
+        ] {
+            if size > 0 {
+                files.push_back(BufReader::new(File::open(path)?).take(size));
+            }
+        }
+        let pending = state.pending.iter().cloned().collect();
+        Ok((SpoolReader { files }, pending))
+    }
+}
+
+impl Shared {
+    fn remove_files(&self) {
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(older(&self.path));
+    }
+
+    /// Write queued chunks until the queue is empty.
+    fn drain(&self) {
+        let mut file = self.file.lock().unwrap();
+        loop {
+            let chunk = {
+                let mut state = self.state.lock().unwrap();
+                match state.pending.front() {
+                    Some(chunk) => chunk.clone(),
+                    None => {
+                        state.draining = false;
+                        self.drained.notify_all();
+                        return;
+                    }
+                }
+            };
+            let result = encode_chunk(&chunk).and_then(|data| self.write(&mut file, &data));
+            let mut state = self.state.lock().unwrap();
+            state.pending.pop_front();
+            match result {
+                Ok(len) => state.sizes[1] += len,
+                Err(e) => {
+                    tracing::warn!(
+                        "failed to write scrollback spool {}: {}; evicted output is dropped",
+                        self.path.display(),
+                        e
+                    );
+                    state.failed = true;
+                    state.pending.clear();
+                }
+            }
+        }
+    }
+
+    /// Append `data` to `file`, rotating first if the current file would
+    /// grow past half the cap. Returns the number of bytes written.
+    fn write(&self, file: &mut Option<File>, data: &[u8]) -> io::Result<u64> {
+        let len = data.len() as u64;
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.sizes[1] > 0 && state.sizes[1] + len > self.max_bytes / 2 {
+                *file = None;
+                std::fs::rename(&self.path, older(&self.path))?;
+                state.sizes = [state.sizes[1], 0];
+            }
+        }
+        let file = match file {
+            Some(file) => file,
+            None => file.insert(
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .mode(0o600)
+                    .open(&self.path)?,
+            ),
+        };
+        file.write_all(data)?;
+        Ok(len)
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.failed = true;
+            state.pending.clear();
+        }
+        self.shared.remove_files();
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        // A drain still running when the spool was dropped may have
+        // recreated the current file.
+        self.remove_files();
+    }
+}
+
+/// Records for every event in `chunk`, decompressing a block.
+fn encode_chunk(chunk: &Chunk) -> io::Result<Vec<u8>> {
+    match chunk {
+        Chunk::Event(event) => Ok(encode(event)),
+        Chunk::Block(block) => Ok(block.events()?.iter().flat_map(encode).collect()),
+    }
+}
+
+/// Streams spooled events back from disk.
+pub(crate) struct SpoolReader {
+    files: VecDeque<Take<BufReader<File>>>,
+}
+
+impl SpoolReader {
+    fn next_event(&mut self) -> io::Result<Option<ScrollbackEvent>> {
+        while let Some(file) = self.files.front_mut() {
+            match read_record(file)? {
+                Some(event) => return Ok(Some(event)),
+                None => self.files.pop_front(),
+            };
+        }
+        Ok(None)
+    }
+}
+
+/// Scrollback replayed to an attaching client: spooled events from disk,
+/// then the events held in memory, decompressing
+/// [blocks](crate::scrollback) as it goes.
+pub struct Replay {
+    spool: Option<SpoolReader>,
+    memory: std::vec::IntoIter<Chunk>,
+    block: std::vec::IntoIter<ScrollbackEvent>,
+}
+
+impl Replay {
+    pub(crate) fn new(spool: Option<SpoolReader>, memory: Vec<Chunk>) -> Self {
+        Self {
+            spool,
+            memory: memory.into_iter(),
+            block: Vec::new().into_iter(),
+        }
+    }
+
+    /// Produce the events on a blocking thread, so that reading the spool
+    /// and decompressing blocks does not stall the async runtime. Reading
+    /// stops early when the receiver is dropped.
+    pub fn stream(self) -> mpsc::Receiver<ScrollbackEvent> {
+        let (tx, rx) = mpsc::channel(REPLAY_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            for event in self {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+}
+
+impl Iterator for Replay {
+    type Item = ScrollbackEvent;
+
+    fn next(&mut self) -> Option<ScrollbackEvent> {
+        if let Some(spool) = &mut self.spool {
+            match spool.next_event() {
+                Ok(Some(event)) => return Some(event),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("failed to read scrollback spool: {}", e),
+            }
+            self.spool = None;
+        }
+        loop {
+            if let Some(event) = self.block.next() {
+                return Some(event);
+            }
+            match self.memory.next()? {
+                Chunk::Event(event) => return Some(event),
+                Chunk::Block(block) => match block.events() {
+                    Ok(events) => self.block = events.into_iter(),
+                    Err(e) => tracing::warn!("failed to decompress scrollback: {}", e),
+                },
+            }
+        }
+    }
+}
+
+/// Encode `event` as a record.
+pub(crate) fn encode(event: &ScrollbackEvent) -> Vec<u8> {
+    let window_size;
+    let (tag, payload) = match event {
+        ScrollbackEvent::Output(data) => (TAG_OUTPUT, data.as_slice()),
+        ScrollbackEvent::WindowSize(rows, cols) => {
+            window_size = [rows.to_be_bytes(), cols.to_be_bytes()].concat();
+            (TAG_WINDOW_SIZE, window_size.as_slice())
+        }
+    };
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.push(tag);
+    record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Read the next record from `reader`, or `None` at the end of the input.
+pub(crate) fn read_record(reader: &mut impl Read) -> io::Result<Option<ScrollbackEvent>> {
+    let mut tag = [0u8; 1];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(corrupt());
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    decode(tag[0], payload).map(Some)
+}
+
+fn decode(tag: u8, payload: Vec<u8>) -> io::Result<ScrollbackEvent> {
+    match (tag, payload.as_slice()) {
+        (TAG_OUTPUT, _) => Ok(ScrollbackEvent::Output(payload)),
+        (TAG_WINDOW_SIZE, &[r0, r1, c0, c1]) => Ok(ScrollbackEvent::WindowSize(
+            u16::from_be_bytes([r0, r1]),
+            u16::from_be_bytes([c0, c1]),
+        )),
+        _ => Err(corrupt()),
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt scrollback spool")
+}
+
+fn older(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(name: &str, max_bytes: u64) -> SpoolConfig {
+        let dir = std::env::temp_dir().join(format!("tty-web-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = SpoolConfig::new(dir, max_bytes);
+        config.prepare().unwrap();
+        config
+    }
+
+    fn output(data: &[u8]) -> ScrollbackEvent {
+        ScrollbackEvent::Output(data.to_vec())
+    }
+
+    fn push(spool: &Spool, event: ScrollbackEvent) {
+        spool.push(vec![Chunk::Event(event)]);
+    }
+
+    /// Replay of the spool followed by `memory`, as an attach builds it.
+    fn replay(spool: &Spool, memory: Vec<Chunk>) -> Vec<ScrollbackEvent> {
+        let (reader, mut pending) = spool.reader().unwrap();
+        pending.extend(memory);
+        Replay::new(Some(reader), pending).collect()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let config = temp_config("spool-roundtrip", 1 << 20);
+        let spool = Spool::open(&config, "s1");
+        push(&spool, output(b"hello"));
+        push(&spool, ScrollbackEvent::WindowSize(40, 120));
+        push(&spool, output(b""));
+        assert_eq!(spool.len(), 5 + 5 + 5 + 4 + 5);
+
+        assert_eq!(
+            replay(&spool, vec![Chunk::Event(output(b"mem"))]),
+            [
+                output(b"hello"),
+                ScrollbackEvent::WindowSize(40, 120),
+                output(b""),
+                output(b"mem"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reader_ignores_later_events() {
+        let config = temp_config("spool-snapshot", 1 << 20);
+        let spool = Spool::open(&config, "s1");
+        push(&spool, output(b"before"));
+        let (reader, pending) = spool.reader().unwrap();
+        push(&spool, output(b"after"));
+        let events: Vec<_> = Replay::new(Some(reader), pending).collect();
+        assert_eq!(events, [output(b"before")]);
+    }
+
+    #[test]
+    fn test_rotation_keeps_recent_events() {
+        // Each record is 5 + 10 bytes, so a file holds three of them.
+        let config = temp_config("spool-rotate", 100);
+        let spool = Spool::open(&config, "s1");
+        for n in 0..10u8 {
+            push(&spool, output(&[b'0' + n; 10]));
+        }
+        assert!(spool.len() <= 100, "spool is {} bytes", spool.len());
+        let events = replay(&spool, Vec::new());
+        assert_eq!(events.last(), Some(&output(&[b'9'; 10])));
+        assert_eq!(events.first(), Some(&output(&[b'6'; 10])));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn test_read_record_rejects_huge_length() {
+        let mut record = vec![TAG_OUTPUT];
+        record.extend_from_slice(&u32::MAX.to_be_bytes());
+        let err = read_record(&mut record.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_remove_stale() {
+        let config = temp_config("spool-stale", 1 << 20);
+        for name in ["old.spool", "old.spool.1", "live.spool", "notes.txt"] {
+            std::fs::write(config.dir.join(name), b"x").unwrap();
+        }
+        assert_eq!(config.remove_stale(&["live"]).unwrap(), 2);
+        let mut left: Vec<_> = std::fs::read_dir(&config.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["live.spool", "notes.txt"]);
+    }
+
+    #[test]
+    fn test_drop_removes_files_and_open_continues() {
+        let config = temp_config("spool-files", 1 << 20);
+        let spool = Spool::open(&config, "s1");
+        push(&spool, output(b"kept"));
+        let path = config.dir.join("s1.spool");
+        assert!(path.exists());
+
+        // A new process picks up the files of a session handed to it.
+        let reopened = Spool::open(&config, "s1");
+        assert_eq!(reopened.len(), spool.len());
+        let events = replay(&reopened, Vec::new());
+        assert_eq!(events, [output(b"kept")]);
+
+        drop(reopened);
+        drop(spool);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_background_writes() {
+        let config = temp_config("spool-background", 1 << 20);
+        let spool = Spool::open(&config, "s1");
+        let mut sb = crate::scrollback::Scrollback::new(0, false);
+        let mut evicted = Vec::new();
+        for n in 0..100u8 {
+            evicted.extend(sb.push(output(&[n; 100])));
+        }
+        spool.push(evicted);
+        // Whether written or still queued, every event is replayed once.
+        let events = replay(&spool, Vec::new());
+        assert_eq!(events.len(), 100);
+        assert_eq!(events[99], output(&[99; 100]));
+
+        spool.flush();
+        assert_eq!(spool.len(), 100 * (5 + 100));
+        assert!(spool.reader().unwrap().1.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let config = temp_config("spool-stream", 1 << 20);
+        let spool = Spool::open(&config, "s1");
+        for n in 0..=REPLAY_BUFFER as u8 {
+            push(&spool, output(&[n]));
+        }
+        let (reader, pending) = spool.reader().unwrap();
+        let memory = vec![Chunk::Event(output(b"mem"))];
+        let mut rx = Replay::new(Some(reader), [pending, memory].concat()).stream();
+        for n in 0..=REPLAY_BUFFER as u8 {
+            assert_eq!(rx.recv().await, Some(output(&[n])));
+        }
+        assert_eq!(rx.recv().await, Some(output(b"mem")));
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -2,9 +2,15 @@
 //!
 //! [`Terminal`] owns a [`PtyMaster`] and drives async
 //! read/write loops via tokio. Output is fanned out through a broadcast channel
-//! so multiple subscribers (WebSocket clients) can receive the same stream.
+//! so multiple subscribers (WebSocket clients) can receive the same stream,
+//! after control strings dropped by the [output filter](crate::filter) have
+//! been removed.
+//!
+//! A terminal either spawns its shell or [adopts](Terminal::adopt) one that a
+//! previous tty-web process [handed off](crate::handoff).
 
-use std::path::Path;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::path::{Path, PathBuf};
 use std::process::Child;
 use std::sync::{Arc, Mutex};
 
@@ -14,7 +20,10 @@ use tokio::io::Interest;
 use tokio::io::unix::AsyncFd;
 use tokio::sync::{broadcast, mpsc, watch};
 
+use crate::cgroup::{Cgroup, CgroupUsage};
+use crate::filter::{OutputFilter, OutputPolicy};
 use crate::pty::PtyMaster;
+use crate::spawn::SpawnOptions;
 
 const OUTPUT_CHANNEL_SIZE: usize = 64;
 const INPUT_CHANNEL_SIZE: usize = 256;
@@ -27,9 +36,16 @@ const READ_BUF_SIZE: usize = 4096;
 pub struct Terminal {
     input_tx: mpsc::Sender<Vec<u8>>,
     output_tx: broadcast::Sender<Vec<u8>>,
-    fd: Arc<AsyncFd<std::os::fd::OwnedFd>>,
+    fd: Arc<AsyncFd<OwnedFd>>,
+    /// The shell process; `None` for an adopted shell, which is not our child.
     child: Mutex<Option<Child>>,
+    pid: Pid,
+    cgroup: Option<Cgroup>,
     closed_rx: watch::Receiver<bool>,
+    paused_tx: watch::Sender<bool>,
+    reading_rx: watch::Receiver<bool>,
+    output_policy: Arc<OutputPolicy>,
+    command: String,
 }
 
 impl Terminal {
@@ -41,7 +57,21 @@ impl Terminal {
         shell: &str,
         pwd: Option<&Path>,
     ) -> std::io::Result<(Self, broadcast::Receiver<Vec<u8>>)> {
-        let PtyMaster { master, mut child } = PtyMaster::spawn(shell, pwd)?;
+        Self::spawn_with(shell, pwd, &SpawnOptions::default())
+    }
+
+    /// Like [`Terminal::spawn`], with extra process settings such as the
+    /// user to run the shell as.
+    pub fn spawn_with(
+        shell: &str,
+        pwd: Option<&Path>,
+        opts: &SpawnOptions,
+    ) -> std::io::Result<(Self, broadcast::Receiver<Vec<u8>>)> {
+        let PtyMaster {
+            master,
+            mut child,
+            cgroup,
+        } = PtyMaster::spawn(shell, pwd, opts)?;
 
         let async_fd = match AsyncFd::with_interest(master, Interest::READABLE | Interest::WRITABLE)
         {
@@ -52,16 +82,63 @@ impl Terminal {
                 return Err(e);
             }
         };
-        let fd = Arc::new(async_fd);
+        let pid = Pid::from_raw(child.id() as i32);
+        Ok(Self::start(
+            async_fd,
+            Some(child),
+            pid,
+            cgroup,
+            opts.output_filter.clone(),
+            shell.to_owned(),
+        ))
+    }
+
+    /// Take over a shell started by a previous tty-web process: `master` is
+    /// its PTY master, `pid` the shell's process ID and `cgroup` the session
+    /// cgroup it runs in, if any.
+    pub fn adopt(
+        master: OwnedFd,
+        pid: u32,
+        command: String,
+        cgroup: Option<PathBuf>,
+        output_policy: Arc<OutputPolicy>,
+    ) -> std::io::Result<(Self, broadcast::Receiver<Vec<u8>>)> {
+        crate::pty::set_nonblocking(&master)?;
+        let async_fd = AsyncFd::with_interest(master, Interest::READABLE | Interest::WRITABLE)?;
+        let pid = i32::try_from(pid).map_err(std::io::Error::other)?;
+        Ok(Self::start(
+            async_fd,
+            None,
+            Pid::from_raw(pid),
+            cgroup.map(Cgroup::adopt),
+            output_policy,
+            command,
+        ))
+    }
+
+    /// Start the read and write loops around `fd`.
+    fn start(
+        fd: AsyncFd<OwnedFd>,
+        child: Option<Child>,
+        pid: Pid,
+        cgroup: Option<Cgroup>,
+        output_policy: Arc<OutputPolicy>,
+        command: String,
+    ) -> (Self, broadcast::Receiver<Vec<u8>>) {
+        let fd = Arc::new(fd);
 
         let (input_tx, input_rx) = mpsc::channel(INPUT_CHANNEL_SIZE);
         let (output_tx, output_rx) = broadcast::channel(OUTPUT_CHANNEL_SIZE);
         let (closed_tx, closed_rx) = watch::channel(false);
+        let (paused_tx, paused_rx) = watch::channel(false);
+        let (reading_tx, reading_rx) = watch::channel(true);
 
         let read_fd = fd.clone();
         let read_tx = output_tx.clone();
+        let filter = OutputFilter::for_output(output_policy.clone());
         tokio::spawn(async move {
-            read_loop(read_fd, read_tx).await;
+            read_loop(read_fd, read_tx, filter, paused_rx, &reading_tx).await;
+            reading_tx.send_replace(false);
             let _ = closed_tx.send(true);
         });
 
@@ -74,10 +151,16 @@ impl Terminal {
             input_tx,
             output_tx,
             fd,
-            child: Mutex::new(Some(child)),
+            child: Mutex::new(child),
+            pid,
+            cgroup,
             closed_rx,
+            paused_tx,
+            reading_rx,
+            output_policy,
+            command,
         };
-        Ok((terminal, output_rx))
+        (terminal, output_rx)
     }
 
     /// Subscribe to the terminal output broadcast channel.
@@ -91,6 +174,21 @@ impl Terminal {
         self.closed_rx.clone()
     }
 
+    /// Stop reading from the PTY, e.g. before a [hand-off](crate::handoff):
+    /// whatever the shell writes afterwards stays in the PTY for the next
+    /// reader of the master. Returns once the read loop has stopped, so all
+    /// output read before is in the output channel.
+    pub async fn pause(&self) {
+        self.paused_tx.send_replace(true);
+        let mut reading = self.reading_rx.clone();
+        let _ = reading.wait_for(|&reading| !reading).await;
+    }
+
+    /// Read from the PTY again after [`Terminal::pause`].
+    pub fn resume(&self) {
+        self.paused_tx.send_replace(false);
+    }
+
     /// Queue bytes to be written to the PTY.
     pub async fn write(&self, data: Vec<u8>) -> Result<(), String> {
         self.input_tx.send(data).await.map_err(|e| e.to_string())
@@ -100,27 +198,114 @@ impl Terminal {
     pub fn resize(&self, rows: u16, cols: u16) -> std::io::Result<()> {
         crate::pty::set_window_size(&*self.fd, rows, cols)
     }
+
+    /// The output filter policy the terminal was spawned with. Output is
+    /// already filtered for interactive clients; view-only clients need an
+    /// [`OutputFilter::for_viewer`] on top.
+    pub fn output_policy(&self) -> &Arc<OutputPolicy> {
+        &self.output_policy
+    }
+
+    /// The command the terminal was spawned with.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Process ID of the shell.
+    pub fn pid(&self) -> Option<u32> {
+        u32::try_from(self.pid.as_raw()).ok()
+    }
+
+    /// Send `signal` to the shell's process group. Does nothing once an
+    /// adopted shell's PTY has closed. For an
+    /// [isolated](crate::isolation) shell the signal goes to its keeper,
+    /// which forwards `SIGHUP`, `SIGINT` and `SIGTERM`.
+    pub fn signal(&self, signal: Signal) -> std::io::Result<()> {
+        if !self.may_signal() {
+            return Ok(());
+        }
+        // The shell leads its own session, so its PID is the group ID.
+        signal::killpg(self.pid, signal).map_err(std::io::Error::from)
+    }
+
+    /// Our own child stays a zombie until we reap it, so its PID is ours to
+    /// signal. An adopted shell is reaped by someone else once it exits and
+    /// its PID may be reused, so it is only signalled while the PTY is open.
+    fn may_signal(&self) -> bool {
+        self.child.lock().unwrap().is_some() || !*self.closed_rx.borrow()
+    }
+
+    /// Returns `true` if the shell was [adopted](Terminal::adopt) from a
+    /// previous process.
+    pub fn is_adopted(&self) -> bool {
+        self.child.lock().unwrap().is_none()
+    }
+
+    /// The PTY master, e.g. to [hand off](crate::handoff) the terminal.
+    pub fn master(&self) -> BorrowedFd<'_> {
+        self.fd.get_ref().as_fd()
+    }
+
+    /// Directory of the shell's cgroup, if it runs in one.
+    pub fn cgroup_path(&self) -> Option<&Path> {
+        self.cgroup.as_ref().map(Cgroup::path)
+    }
+
+    /// Resource usage of the shell's cgroup, if it runs in one.
+    pub fn resource_usage(&self) -> Option<CgroupUsage> {
+        self.cgroup.as_ref().map(Cgroup::usage)
+    }
 }
 
 impl Drop for Terminal {
     fn drop(&mut self) {
-        if let Some(mut child) = self.child.get_mut().unwrap().take() {
-            let pid = Pid::from_raw(child.id() as i32);
-            let _ = signal::kill(pid, Signal::SIGHUP);
-            // Reap the child on a dedicated OS thread so we never block
-            // the tokio runtime (which would deadlock current_thread tests
-            // and stall multi_thread ones).
+        if self.may_signal() {
+            let _ = signal::kill(self.pid, Signal::SIGHUP);
+        }
+        let child = self.child.get_mut().unwrap().take();
+        let cgroup = self.cgroup.take();
+        // Reap the child (and remove its cgroup, which waits for leftover
+        // processes to die) on a dedicated OS thread so we never block
+        // the tokio runtime (which would deadlock current_thread tests
+        // and stall multi_thread ones).
+        if child.is_some() || cgroup.is_some() {
             std::thread::spawn(move || {
-                let _ = child.wait();
+                if let Some(mut child) = child {
+                    let _ = child.wait();
+                }
+                drop(cgroup);
             });
         }
     }
 }
 
-async fn read_loop(fd: Arc<AsyncFd<std::os::fd::OwnedFd>>, tx: broadcast::Sender<Vec<u8>>) {
+/// Broadcast PTY output until the PTY closes. While `paused` is set the
+/// loop stops reading and clears `reading`.
+async fn read_loop(
+    fd: Arc<AsyncFd<OwnedFd>>,
+    tx: broadcast::Sender<Vec<u8>>,
+    mut filter: Option<OutputFilter>,
+    mut paused: watch::Receiver<bool>,
+    reading: &watch::Sender<bool>,
+) {
     let mut buf = [0u8; READ_BUF_SIZE];
     loop {
-        let mut ready = match fd.readable().await {
+        if *paused.borrow_and_update() {
+            reading.send_replace(false);
+            if paused.wait_for(|&paused| !paused).await.is_err() {
+                break;
+            }
+            reading.send_replace(true);
+        }
+        let readable = tokio::select! {
+            readable = fd.readable() => readable,
+            changed = paused.changed() => match changed {
+                Ok(()) => continue,
+                // The terminal is gone; nobody can pause it any more.
+                Err(_) => fd.readable().await,
+            },
+        };
+        let mut ready = match readable {
             Ok(r) => r,
             Err(e) => {
                 tracing::debug!("pty read await error: {}", e);
@@ -131,7 +316,11 @@ async fn read_loop(fd: Arc<AsyncFd<std::os::fd::OwnedFd>>, tx: broadcast::Sender
         match nix::unistd::read(&*fd, &mut buf) {
             Ok(0) => break,
             Ok(n) => {
-                if tx.send(buf[..n].to_vec()).is_err() {
+                let data = match &mut filter {
+                    Some(filter) => filter.filter(&buf[..n]),
+                    None => buf[..n].to_vec(),
+                };
+                if !data.is_empty() && tx.send(data).is_err() {
                     break;
                 }
                 ready.retain_ready();
@@ -147,7 +336,7 @@ async fn read_loop(fd: Arc<AsyncFd<std::os::fd::OwnedFd>>, tx: broadcast::Sender
     }
 }
 
-async fn write_loop(fd: Arc<AsyncFd<std::os::fd::OwnedFd>>, mut rx: mpsc::Receiver<Vec<u8>>) {
+async fn write_loop(fd: Arc<AsyncFd<OwnedFd>>, mut rx: mpsc::Receiver<Vec<u8>>) {
     while let Some(data) = rx.recv().await {
         let mut written = 0;
         while written < data.len() {
@@ -178,6 +367,7 @@ async fn write_loop(fd: Arc<AsyncFd<std::os::fd::OwnedFd>>, mut rx: mpsc::Receiv
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cgroup::CgroupConfig;
     use tokio::time::{Duration, timeout};
 
     #[tokio::test]
@@ -207,12 +397,80 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_spawn_as_user() {
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let Ok(run_as) = crate::spawn::RunAs::resolve("nobody", None) else {
+            return;
+        };
+        let opts = SpawnOptions::default().with_run_as(Some(run_as));
+        let (terminal, mut rx) =
+            Terminal::spawn_with("/bin/sh", None, &opts).expect("spawn as nobody");
+
+        terminal
+            .write(b"echo \"marker:$(id -un):$USER\"\n".to_vec())
+            .await
+            .unwrap();
+
+        let mut collected = String::new();
+        let _ = timeout(Duration::from_secs(3), async {
+            while let Ok(data) = rx.recv().await {
+                collected.push_str(&String::from_utf8_lossy(&data));
+                if collected.contains("marker:nobody:nobody") {
+                    break;
+                }
+            }
+        })
+        .await;
+
+        assert!(
+            collected.contains("marker:nobody:nobody"),
+            "shell should run as nobody, got: {collected}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_filter() {
+        let opts =
+            SpawnOptions::default().with_output_filter(Arc::new("osc52=drop".parse().unwrap()));
+        let (terminal, mut rx) = Terminal::spawn_with("/bin/sh", None, &opts).expect("spawn");
+
+        terminal
+            .write(b"printf 'a\\033]52;c;aGk=\\007b%s\\n' done\n".to_vec())
+            .await
+            .unwrap();
+
+        let mut collected = Vec::new();
+        let _ = timeout(Duration::from_secs(3), async {
+            while let Ok(data) = rx.recv().await {
+                collected.extend_from_slice(&data);
+                if collected.windows(6).any(|w| w == b"abdone") {
+                    break;
+                }
+            }
+        })
+        .await;
+
+        let output = String::from_utf8_lossy(&collected);
+        assert!(output.contains("abdone"), "got: {output:?}");
+        assert!(!output.contains("\x1b]52"), "OSC 52 should be dropped");
+    }
+
     #[tokio::test]
     async fn test_resize() {
         let (terminal, _rx) = Terminal::spawn("/bin/sh", None).expect("spawn /bin/sh");
         terminal.resize(50, 132).expect("resize should succeed");
     }
 
+    #[tokio::test]
+    async fn test_command_and_pid() {
+        let (terminal, _rx) = Terminal::spawn("/bin/sh", None).expect("spawn /bin/sh");
+        assert_eq!(terminal.command(), "/bin/sh");
+        assert!(terminal.pid().is_some_and(|pid| pid > 1));
+    }
+
     #[tokio::test]
     async fn test_closed_on_exit() {
         let (terminal, _rx) = Terminal::spawn("/bin/sh", None).expect("spawn /bin/sh");
@@ -227,4 +485,38 @@ mod tests {
             "closed signal should be received after exit"
         );
     }
+
+    #[tokio::test]
+    async fn test_signal() {
+        let (terminal, _rx) = Terminal::spawn("/bin/sh", None).expect("spawn /bin/sh");
+        let mut closed = terminal.closed();
+
+        terminal.signal(Signal::SIGKILL).unwrap();
+
+        let result = timeout(Duration::from_secs(10), closed.wait_for(|&v| v)).await;
+        assert!(result.is_ok(), "shell should die from the signal");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a writable cgroup v2 hierarchy; run with --ignored"]
+    async fn test_spawn_in_cgroup() {
+        let parent =
+            crate::cgroup::writable_test_parent().expect("no writable cgroup v2 hierarchy");
+        let opts = SpawnOptions::default().with_cgroup(Some(CgroupConfig::new(parent)));
+        let (terminal, _rx) = Terminal::spawn_with("/bin/sh", None, &opts).expect("spawn");
+        let path = terminal.cgroup.as_ref().unwrap().path().to_owned();
+        let procs = std::fs::read_to_string(path.join("cgroup.procs")).unwrap();
+        let pid = terminal.child.lock().unwrap().as_ref().unwrap().id();
+        assert_eq!(procs.trim(), pid.to_string());
+        assert!(terminal.resource_usage().is_some());
+
+        drop(terminal);
+        let removed = timeout(Duration::from_secs(5), async {
+            while path.exists() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        assert!(removed.is_ok(), "cgroup should be removed after drop");
+    }
 }
@@ -0,0 +1,359 @@
+//! HTTPS/WSS termination with [rustls](https://docs.rs/rustls).
+//!
+//! [`TlsListener`] wraps a TCP listener and performs TLS handshakes in
+//! background tasks, so a slow client never blocks new connections. It
+//! implements [`axum::serve::Listener`] and can be passed to [`axum::serve`]
+//! directly.
+//!
+//! The certificate pair is served through [`CertResolver`], which can swap in
+//! a new pair at any time. [`watch_reload`] reloads it on `SIGHUP` or when the
+//! files change on disk; connections established before a reload keep using
+//! the old certificate, so no session is dropped.
+//!
+//! With a [`client_verifier`], clients must present a certificate issued by
+//! one of the configured CAs before any request is served; the certificate
+//! is exposed to handlers through [`Peer`](crate::web::peer::Peer).
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::Router;
+use axum::extract::Request;
+use axum::http::{StatusCode, Uri, header};
+use axum::response::{IntoResponse, Redirect, Response};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+
+/// Maximum time a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of completed handshakes buffered before the accept loop waits.
+const ACCEPT_QUEUE_SIZE: usize = 64;
+/// How often certificate files are checked for changes.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Load a PEM certificate chain and private key into a signing key pair.
+pub fn load_certified_key(cert: &Path, key: &Path) -> std::io::Result<CertifiedKey> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| std::io::Error::other(format!("{}: {e}", cert.display())))?;
+    if chain.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: no certificates found", cert.display()),
+        ));
+    }
+    let key_der = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| std::io::Error::other(format!("{}: {e}", key.display())))?;
+    let provider = rustls::crypto::ring::default_provider();
+    CertifiedKey::from_der(chain, key_der, &provider)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Certificate resolver whose key pair can be replaced at runtime.
+#[derive(Debug)]
+pub struct CertResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    /// Load the initial certificate pair from disk.
+    pub fn new(cert_path: PathBuf, key_path: PathBuf) -> std::io::Result<Self> {
+        let key = load_certified_key(&cert_path, &key_path)?;
+        Ok(Self {
+            cert_path,
+            key_path,
+            current: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Re-read the certificate pair. On error the previous pair stays active.
+    pub fn reload(&self) -> std::io::Result<()> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    fn stamp(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert_path)?, modified(&self.key_path)?))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Build a verifier that requires a client certificate issued by one of the
+/// CA certificates in the PEM file `ca`.
+pub fn client_verifier(ca: &Path) -> std::io::Result<Arc<dyn ClientCertVerifier>> {
+    let certs = CertificateDer::pem_file_iter(ca)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| std::io::Error::other(format!("{}: {e}", ca.display())))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(cert).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {e}", ca.display()),
+            )
+        })?;
+    }
+    if roots.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: no certificates found", ca.display()),
+        ));
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+        .build()
+        .map_err(std::io::Error::other)
+}
+
+/// Build a rustls server configuration (HTTP/1.1 only, as required for
+/// WebSocket upgrades) that takes its certificate from `resolver` and, if
+/// `client_verifier` is given, requires client certificates.
+pub fn server_config(
+    resolver: Arc<CertResolver>,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> std::io::Result<ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(std::io::Error::other)?;
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Reload the certificate pair on `SIGHUP` and whenever either file's
+/// modification time changes. Runs until the task is dropped.
+pub async fn watch_reload(resolver: Arc<CertResolver>) {
+    let mut sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            tracing::warn!("failed to install SIGHUP handler: {e}");
+            None
+        }
+    };
+    let mut stamp = resolver.stamp();
+    let mut poll = tokio::time::interval(RELOAD_POLL_INTERVAL);
+    poll.tick().await;
+    loop {
+        let reason = tokio::select! {
+            Some(()) = async { sighup.as_mut()?.recv().await } => "SIGHUP",
+            _ = poll.tick() => {
+                let current = resolver.stamp();
+                if current.is_none() || current == stamp {
+                    continue;
+                }
+                stamp = current;
+                "file change"
+            }
+        };
+        match resolver.reload() {
+            Ok(()) => tracing::info!("reloaded TLS certificate ({reason})"),
+            Err(e) => tracing::error!("failed to reload TLS certificate ({reason}): {e}"),
+        }
+    }
+}
+
+/// A TCP listener that yields fully established TLS streams.
+pub struct TlsListener {
+    rx: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TlsListener {
+    /// Start accepting connections on `listener` and handshaking them with
+    /// `acceptor` in the background.
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+        let task = tokio::spawn(accept_loop(listener, acceptor, tx));
+        Ok(Self {
+            rx,
+            local_addr,
+            task,
+        })
+    }
+}
+
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.rx.recv().await {
+            Some(conn) => conn,
+            // The accept loop never exits on its own while we hold `rx`.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Same back-off as axum's TCP listener (e.g. EMFILE).
+                tracing::error!("accept error: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(tls)) => {
+                    let _ = tx.send((tls, addr)).await;
+                }
+                Ok(Err(e)) => tracing::debug!("TLS handshake with {addr} failed: {e}"),
+                Err(_) => tracing::debug!("TLS handshake with {addr} timed out"),
+            }
+        });
+    }
+}
+
+/// Router that redirects every plain-HTTP request to the HTTPS port.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new().fallback(move |request: Request| async move { redirect(&request, https_port) })
+}
+
+fn redirect(request: &Request, https_port: u16) -> Response {
+    let Some(host) = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<axum::http::uri::Authority>().ok())
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let authority = match https_port {
+        443 => host.host().to_owned(),
+        port => format!("{}:{port}", host.host()),
+    };
+    let path = request.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    match Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(path)
+        .build()
+    {
+        Ok(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+        Err(_) => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn write_self_signed(name: &str) -> (PathBuf, PathBuf) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir();
+        let pid = std::process::id();
+        let cert_path = dir.join(format!("tty-web-{name}-{pid}.crt"));
+        let key_path = dir.join(format!("tty-web-{name}-{pid}.key"));
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.signing_key.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn test_resolver_reload() {
+        let (cert_path, key_path) = write_self_signed("reload");
+        let resolver = CertResolver::new(cert_path.clone(), key_path.clone()).unwrap();
+        let before = resolver.current.read().unwrap().cert[0].clone();
+
+        let (new_cert, new_key) = write_self_signed("reload-new");
+        std::fs::rename(&new_cert, &cert_path).unwrap();
+        std::fs::rename(&new_key, &key_path).unwrap();
+        resolver.reload().unwrap();
+        let after = resolver.current.read().unwrap().cert[0].clone();
+        assert_ne!(before, after, "certificate should be replaced");
+
+        std::fs::write(&cert_path, "garbage").unwrap();
+        assert!(resolver.reload().is_err());
+        let kept = resolver.current.read().unwrap().cert[0].clone();
+        assert_eq!(after, kept, "failed reload must keep the previous pair");
+
+        let _ = std::fs::remove_file(cert_path);
+        let _ = std::fs::remove_file(key_path);
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let missing = Path::new("/nonexistent/tty-web.pem");
+        assert!(load_certified_key(missing, missing).is_err());
+        assert!(client_verifier(missing).is_err());
+    }
+
+    #[test]
+    fn test_client_verifier() {
+        let (ca_path, key_path) = write_self_signed("client-ca");
+        let verifier = client_verifier(&ca_path).unwrap();
+        assert!(verifier.client_auth_mandatory());
+        assert!(!verifier.root_hint_subjects().is_empty());
+
+        std::fs::write(&ca_path, "").unwrap();
+        assert!(client_verifier(&ca_path).is_err(), "empty CA file");
+
+        let _ = std::fs::remove_file(ca_path);
+        let _ = std::fs::remove_file(key_path);
+    }
+
+    #[test]
+    fn test_redirect() {
+        let request = Request::get("/?sid=abc")
+            .header(header::HOST, "example.com:8080")
+            .body(Body::empty())
+            .unwrap();
+        let response = redirect(&request, 8443);
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://example.com:8443/?sid=abc"
+        );
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(redirect(&request, 443).status(), StatusCode::BAD_REQUEST);
+    }
+}
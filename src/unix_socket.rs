@@ -0,0 +1,185 @@
+//! Serving over a unix domain socket instead of TCP.
+//!
+//! Useful behind a local reverse proxy: no TCP port is opened, and access is
+//! controlled by the socket file's mode and owner. [`UnixSocketConfig::bind`]
+//! replaces a stale socket left behind by a crashed instance, but refuses to
+//! touch a socket another process is still listening on or a path that is not
+//! a socket at all. The returned [`SocketFile`] removes the socket again when
+//! dropped.
+
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::net::UnixListener;
+
+use crate::spawn::{lookup_group, lookup_user};
+
+/// Where to listen and who may connect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnixSocketConfig {
+    /// Socket path.
+    pub path: PathBuf,
+    /// File mode of the socket (e.g. `0o660`).
+    pub mode: u32,
+    /// Owning user (name or UID); `None` keeps the server's.
+    pub owner: Option<String>,
+    /// Owning group (name or GID); `None` keeps the server's.
+    pub group: Option<String>,
+}
+
+impl UnixSocketConfig {
+    /// A socket at `path`, readable and writable by owner and group.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: 0o660,
+            owner: None,
+            group: None,
+        }
+    }
+
+    /// Bind the socket and apply mode and ownership.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn bind(&self) -> std::io::Result<(UnixListener, SocketFile)> {
+        remove_stale(&self.path)?;
+        let listener = UnixListener::bind(&self.path)?;
+        let file = SocketFile {
+            path: self.path.clone(),
+        };
+        std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(self.mode))?;
+        let uid = self
+            .owner
+            .as_deref()
+            .map(lookup_user)
+            .transpose()?
+            .map(|user| user.uid.as_raw());
+        let gid = self
+            .group
+            .as_deref()
+            .map(lookup_group)
+            .transpose()?
+            .map(|gid| gid.as_raw());
+        if uid.is_some() || gid.is_some() {
+            std::os::unix::fs::chown(&self.path, uid, gid)?;
+        }
+        Ok((listener, file))
+    }
+}
+
+/// Remove a socket at `path` that nobody is listening on any more.
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            tracing::info!("removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The bound socket file, removed on drop.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: PathBuf,
+}
+
+impl SocketFile {
+    /// Socket path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("failed to remove socket {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Parse an octal file mode such as `660` or `0o660`.
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("invalid mode {s:?}, expected octal such as 660"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tty-web-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode("0o600"), Ok(0o600));
+        assert_eq!(parse_mode("0777"), Ok(0o777));
+        assert!(parse_mode("1777").is_err());
+        assert!(parse_mode("689").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_sets_mode_and_removes_on_drop() {
+        let path = temp_path("bind");
+        let config = UnixSocketConfig {
+            mode: 0o600,
+            ..UnixSocketConfig::new(&path)
+        };
+        let (listener, file) = config.bind().unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        let err = config.bind().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+        drop(listener);
+        drop(file);
+        assert!(!path.exists(), "socket should be removed");
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let path = temp_path("stale");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let (_listener, _file) = UnixSocketConfig::new(&path).bind().unwrap();
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_refuses_regular_file() {
+        let path = temp_path("file");
+        std::fs::write(&path, "keep me").unwrap();
+        let err = UnixSocketConfig::new(&path).bind().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! Request authentication for the WebSocket and static routes.
+//!
+//! When a token is configured, every protected request must present it in one
+//! of three places (checked in this order):
+//!
+//! 1. an `Authorization: Bearer <token>` header,
+//! 2. the [`TOKEN_COOKIE`] cookie,
+//! 3. the [`TOKEN_QUERY`] query parameter.
+//!
+//! A token passed via the query string is promoted to an `HttpOnly` cookie so
+//! that the browser sends it along with subsequent asset and WebSocket
+//! requests — the link `/?token=<token>` is enough to open the terminal.
+//! Behind TLS the cookie is marked `Secure` ([`Auth::with_secure_cookie`]).
+//!
+//! When an [`Htpasswd`] database is configured, `Authorization: Basic`
+//! credentials are accepted as well and rejected requests carry a
+//! `WWW-Authenticate` challenge so browsers show their native login prompt.
+//!
+//! When a [`JwtVerifier`] is configured, a signed JWT is accepted in the same
+//! three places as the shared token.
+//!
+//! Client certificates are verified by the TLS layer before any request is
+//! served; the WebSocket handler uses [`Identity::Certificate`] when no other
+//! credential names the user.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use axum::extract::{Query, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use subtle::ConstantTimeEq;
+
+use crate::web::AppState;
+use crate::web::htpasswd::Htpasswd;
+use crate::web::jwt::{JwtClaims, JwtVerifier};
+use crate::web::peer::{ClientCert, Peer};
+
+/// Cookie that carries the access token.
+pub const TOKEN_COOKIE: &str = "tty_web_token";
+/// Query parameter that carries the access token.
+pub const TOKEN_QUERY: &str = "token";
+
+/// Who a request was authenticated as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Identity {
+    /// Authentication is disabled.
+    Anonymous,
+    /// The request presented the shared access token.
+    Token,
+    /// The request presented valid Basic credentials for this user.
+    User(String),
+    /// The request presented a valid JWT with these claims.
+    Jwt(Arc<JwtClaims>),
+    /// The connection presented this verified TLS client certificate.
+    Certificate(Arc<ClientCert>),
+}
+
+impl Identity {
+    /// Authenticated username, if the method identifies a user.
+    ///
+    /// Names from a JWT or a client certificate carry a `jwt:` or `cert:`
+    /// prefix, so that one kind of credential cannot pass for another: a
+    /// token with `sub: alice` is `jwt:alice`, not the htpasswd user `alice`.
+    /// Session owners, `--admin`, `--user-map` and `--limit-profile-user`
+    /// all use this form.
+    pub fn name(&self) -> Option<Cow<'_, str>> {
+        match self {
+            Self::User(name) => Some(Cow::Borrowed(name)),
+            Self::Jwt(claims) => claims
+                .name
+                .as_ref()
+                .map(|name| format!("jwt:{name}").into()),
+            Self::Certificate(cert) => cert.name().map(|name| format!("cert:{name}").into()),
+            Self::Anonymous | Self::Token => None,
+        }
+    }
+
+    /// Fall back to the client certificate of `peer` when this identity
+    /// does not name a user.
+    pub fn or_certificate(self, peer: &Peer) -> Self {
+        match (&self, &peer.client_cert) {
+            (Self::Anonymous | Self::Token, Some(cert)) => Self::Certificate(cert.clone()),
+            _ => self,
+        }
+    }
+
+    /// Unix user the credential asks new sessions to run as.
+    pub fn run_as(&self) -> Option<&str> {
+        match self {
+            Self::Jwt(claims) => claims.run_as.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Limit profile the credential assigns to new sessions.
+    pub fn profile(&self) -> Option<&str> {
+        match self {
+            Self::Jwt(claims) => claims.profile.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Where a credential was found in the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Source {
+    Header,
+    Cookie,
+    Query,
+}
+
+/// Authentication failure.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// No credential was presented.
+    Missing,
+    /// A credential was presented but did not match.
+    Invalid,
+}
+
+/// Authentication policy shared by all protected routes.
+///
+/// The default value disables authentication: every request is accepted as
+/// [`Identity::Anonymous`].
+#[derive(Clone, Default)]
+pub struct Auth {
+    token: Option<Arc<str>>,
+    htpasswd: Option<Arc<Htpasswd>>,
+    jwt: Option<Arc<JwtVerifier>>,
+    secure_cookie: bool,
+}
+
+impl Auth {
+    /// Accept the given shared token on protected requests.
+    pub fn with_token(mut self, token: impl Into<Arc<str>>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Accept HTTP Basic credentials checked against an htpasswd database.
+    pub fn with_htpasswd(mut self, htpasswd: Htpasswd) -> Self {
+        self.htpasswd = Some(Arc::new(htpasswd));
+        self
+    }
+
+    /// Accept JWTs verified by `verifier`.
+    pub fn with_jwt(mut self, verifier: JwtVerifier) -> Self {
+        self.jwt = Some(Arc::new(verifier));
+        self
+    }
+
+    /// Mark the token cookie `Secure`, so browsers never send it over plain
+    /// HTTP. Set when serving over TLS.
+    pub fn with_secure_cookie(mut self, secure: bool) -> Self {
+        self.secure_cookie = secure;
+        self
+    }
+
+    /// Returns `true` if any authentication method is configured.
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || self.htpasswd.is_some() || self.jwt.is_some()
+    }
+
+    /// Authenticate a request from its headers and query parameters.
+    pub async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>,
+    ) -> Result<Identity, AuthError> {
+        self.authenticate_with_source(headers, query)
+            .await
+            .map(|(identity, _)| identity)
+    }
+
+    async fn authenticate_with_source(
+        &self,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>,
+    ) -> Result<(Identity, Option<Source>), AuthError> {
+        if !self.is_enabled() {
+            return Ok((Identity::Anonymous, None));
+        }
+        if let (Some(htpasswd), Some((user, password))) =
+            (&self.htpasswd, basic_credentials(headers))
+        {
+            let db = htpasswd.clone();
+            let name = user.clone();
+            // Password hashes are deliberately slow; keep them off the runtime.
+            let ok = tokio::task::spawn_blocking(move || db.verify(&name, &password))
+                .await
+                .unwrap_or(false);
+            return if ok {
+                Ok((Identity::User(user), Some(Source::Header)))
+            } else {
+                Err(AuthError::Invalid)
+            };
+        }
+        if self.token.is_none() && self.jwt.is_none() {
+            return Err(AuthError::Missing);
+        }
+        let (presented, source) = find_token(headers, query).ok_or(AuthError::Missing)?;
+        if let Some(expected) = &self.token {
+            if bool::from(presented.as_bytes().ct_eq(expected.as_bytes())) {
+                return Ok((Identity::Token, Some(source)));
+            }
+        }
+        if let Some(jwt) = &self.jwt {
+            match jwt.verify(&presented) {
+                Ok(claims) => return Ok((Identity::Jwt(Arc::new(claims)), Some(source))),
+                Err(e) => tracing::debug!("rejected JWT: {e}"),
+            }
+        }
+        Err(AuthError::Invalid)
+    }
+
+    /// `Set-Cookie` value that stores `token` in the [`TOKEN_COOKIE`].
+    fn token_cookie(&self, token: &str) -> String {
+        let secure = if self.secure_cookie { "; Secure" } else { "" };
+        format!("{TOKEN_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict{secure}")
+    }
+
+    /// Build the `401 Unauthorized` response, with a Basic challenge when
+    /// htpasswd authentication is enabled.
+    fn unauthorized(&self) -> Response {
+        let mut response = StatusCode::UNAUTHORIZED.into_response();
+        if self.htpasswd.is_some() {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"tty-web\", charset=\"UTF-8\""),
+            );
+        }
+        response
+    }
+}
+
+/// Decode `Authorization: Basic` credentials into a username and password.
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_owned(), password.to_owned()))
+}
+
+/// Look for a bearer token in the header, cookie, and query string.
+fn find_token(headers: &HeaderMap, query: &HashMap<String, String>) -> Option<(String, Source)> {
+    if let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some((token.trim().to_owned(), Source::Header));
+    }
+    if let Some(token) = cookie(headers, TOKEN_COOKIE) {
+        return Some((token.to_owned(), Source::Cookie));
+    }
+    query
+        .get(TOKEN_QUERY)
+        .map(|token| (token.clone(), Source::Query))
+}
+
+/// Extract a cookie value by name from all `Cookie` headers.
+pub(crate) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Middleware that rejects unauthenticated requests with `401 Unauthorized`.
+///
+/// On success the [`Identity`] is stored in the request extensions. A token
+/// taken from the query string is echoed back as a cookie.
+pub async fn require_auth(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let query = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .map(|Query(q)| q)
+        .unwrap_or_default();
+    match state
+        .auth
+        .authenticate_with_source(request.headers(), &query)
+        .await
+    {
+        Ok((identity, source)) => {
+            let token = (source == Some(Source::Query))
+                .then(|| query.get(TOKEN_QUERY).cloned())
+                .flatten();
+            request.extensions_mut().insert(identity);
+            let mut response = next.run(request).await;
+            if let Some(token) = token {
+                if let Ok(value) = HeaderValue::from_str(&state.auth.token_cookie(&token)) {
+                    response.headers_mut().append(header::SET_COOKIE, value);
+                }
+            }
+            response
+        }
+        Err(e) => {
+            tracing::debug!("rejected unauthenticated request: {e:?}");
+            state.auth.unauthorized()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn token_auth() -> Auth {
+        Auth::default().with_token("secret")
+    }
+
+    #[tokio::test]
+    async fn test_disabled_is_anonymous() {
+        let auth = Auth::default();
+        assert!(!auth.is_enabled());
+        assert_eq!(
+            auth.authenticate(&HeaderMap::new(), &HashMap::new()).await,
+            Ok(Identity::Anonymous)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bearer_header() {
+        let auth = token_auth();
+        let h = headers(header::AUTHORIZATION, "Bearer secret");
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Ok(Identity::Token)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie() {
+        let auth = token_auth();
+        let h = headers(header::COOKIE, "theme=dark; tty_web_token=secret");
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Ok(Identity::Token)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let auth = token_auth();
+        let query = HashMap::from([("token".to_owned(), "secret".to_owned())]);
+        assert_eq!(
+            auth.authenticate(&HeaderMap::new(), &query).await,
+            Ok(Identity::Token)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_invalid() {
+        let auth = token_auth();
+        assert_eq!(
+            auth.authenticate(&HeaderMap::new(), &HashMap::new()).await,
+            Err(AuthError::Missing)
+        );
+        let h = headers(header::AUTHORIZATION, "Bearer wrong");
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Err(AuthError::Invalid)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_basic_htpasswd() {
+        let path =
+            std::env::temp_dir().join(format!("tty-web-auth-{}.htpasswd", std::process::id()));
+        std::fs::write(&path, format!("alice:{}\n", bcrypt::hash("pw", 4).unwrap())).unwrap();
+        let auth = token_auth().with_htpasswd(Htpasswd::load(&path).unwrap());
+
+        let h = headers(
+            header::AUTHORIZATION,
+            &format!("Basic {}", BASE64.encode("alice:pw")),
+        );
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Ok(Identity::User("alice".to_owned()))
+        );
+
+        let h = headers(
+            header::AUTHORIZATION,
+            &format!("Basic {}", BASE64.encode("alice:nope")),
+        );
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Err(AuthError::Invalid)
+        );
+
+        let response = auth.unauthorized();
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_jwt() {
+        use crate::web::jwt::JwtConfig;
+
+        let auth = token_auth().with_jwt(JwtVerifier::hmac(b"jwt-secret", JwtConfig::default()));
+        let claims = serde_json::json!({
+            "sub": "carol",
+            "exp": jsonwebtoken::get_current_timestamp() + 60,
+        });
+        let jwt = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"jwt-secret"),
+        )
+        .unwrap();
+
+        let query = HashMap::from([(TOKEN_QUERY.to_owned(), jwt)]);
+        let identity = auth.authenticate(&HeaderMap::new(), &query).await.unwrap();
+        assert_eq!(identity.name().as_deref(), Some("jwt:carol"));
+        assert!(matches!(identity, Identity::Jwt(_)));
+
+        let h = headers(header::AUTHORIZATION, "Bearer secret");
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Ok(Identity::Token),
+            "the shared token still works"
+        );
+        let h = headers(header::AUTHORIZATION, "Bearer a.b.c");
+        assert_eq!(
+            auth.authenticate(&h, &HashMap::new()).await,
+            Err(AuthError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_or_certificate() {
+        let cert = Arc::new(ClientCert {
+            subject: "CN=dave".to_owned(),
+            names: vec![crate::web::peer::CertName::Cn("dave".to_owned())],
+        });
+        let peer = Peer {
+            client_cert: Some(cert.clone()),
+            ..Peer::default()
+        };
+        let identity = Identity::Token.or_certificate(&peer);
+        assert_eq!(identity, Identity::Certificate(cert));
+        assert_eq!(identity.name().as_deref(), Some("cert:dave"));
+        assert_eq!(
+            Identity::User("erin".to_owned()).or_certificate(&peer),
+            Identity::User("erin".to_owned()),
+            "a named credential takes precedence"
+        );
+        assert_eq!(
+            Identity::Anonymous.or_certificate(&Peer::default()),
+            Identity::Anonymous
+        );
+    }
+}
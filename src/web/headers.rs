@@ -0,0 +1,203 @@
+//! Security headers on every response.
+//!
+//! The frontend is a terminal with full shell access, so it must not be
+//! framed by other sites (clickjacking) and should not load scripts from
+//! anywhere but itself and the CDN serving xterm.js. [`set_headers`] adds:
+//!
+//! - `Content-Security-Policy` — scripts and styles from `'self'` and
+//!   `cdn.jsdelivr.net`, connections (including the WebSocket) to `'self'`
+//!   only, and `frame-ancestors` from the embedding allow-list;
+//! - `X-Frame-Options: SAMEORIGIN` for browsers without `frame-ancestors`
+//!   support, unless other origins may embed the page;
+//! - `Referrer-Policy`, so session links with keys in the query string are not
+//!   leaked to other sites;
+//! - `X-Content-Type-Options: nosniff`.
+//!
+//! Headers already set by a handler are left alone.
+
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::web::AppState;
+
+/// Content-Security-Policy for the embedded frontend, without
+/// `frame-ancestors`.
+pub const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https://cdn.jsdelivr.net; \
+    img-src 'self' data:; \
+    font-src 'self'; \
+    connect-src 'self'; \
+    object-src 'none'; \
+    base-uri 'none'; \
+    form-action 'none'";
+
+/// Default `Referrer-Policy`.
+pub const DEFAULT_REFERRER_POLICY: &str = "no-referrer";
+
+/// Precomputed headers added to every response.
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    headers: Arc<[(HeaderName, HeaderValue)]>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self::new(None, None::<&str>, DEFAULT_REFERRER_POLICY).expect("default headers are valid")
+    }
+}
+
+impl SecurityHeaders {
+    /// Build the header set.
+    ///
+    /// `csp` replaces [`DEFAULT_CSP`]. `frame_ancestors` are origins (or CSP
+    /// source expressions) allowed to embed the pages in addition to the
+    /// same origin; `frame-ancestors` is appended to the policy unless it
+    /// already contains that directive.
+    pub fn new(
+        csp: Option<&str>,
+        frame_ancestors: impl IntoIterator<Item = impl AsRef<str>>,
+        referrer_policy: &str,
+    ) -> Result<Self, String> {
+        let ancestors: Vec<String> = frame_ancestors
+            .into_iter()
+            .map(|origin| origin.as_ref().trim().trim_end_matches('/').to_owned())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        let mut csp = csp
+            .unwrap_or(DEFAULT_CSP)
+            .trim()
+            .trim_end_matches(';')
+            .to_owned();
+        let has_frame_ancestors = csp
+            .split(';')
+            .any(|directive| directive.split_whitespace().next() == Some("frame-ancestors"));
+        if !has_frame_ancestors {
+            csp.push_str("; frame-ancestors 'self'");
+            for origin in &ancestors {
+                csp.push(' ');
+                csp.push_str(origin);
+            }
+        }
+
+        let mut headers = vec![
+            (header::CONTENT_SECURITY_POLICY, value(&csp)?),
+            (header::REFERRER_POLICY, value(referrer_policy)?),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+        ];
+        // X-Frame-Options cannot express an allow-list; browsers that
+        // understand frame-ancestors ignore it anyway.
+        if ancestors.is_empty() {
+            headers.push((
+                header::X_FRAME_OPTIONS,
+                HeaderValue::from_static("SAMEORIGIN"),
+            ));
+        }
+        Ok(Self {
+            headers: headers.into(),
+        })
+    }
+
+    /// A header set that adds nothing.
+    pub fn disabled() -> Self {
+        Self {
+            headers: Arc::new([]),
+        }
+    }
+
+    /// The headers that are added.
+    pub fn iter(&self) -> impl Iterator<Item = &(HeaderName, HeaderValue)> {
+        self.headers.iter()
+    }
+}
+
+fn value(s: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(s).map_err(|_| format!("invalid header value {s:?}"))
+}
+
+/// Middleware that adds the configured [`SecurityHeaders`] to responses.
+pub async fn set_headers(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, value) in state.security_headers.iter() {
+        if !headers.contains_key(name) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get<'a>(headers: &'a SecurityHeaders, name: &HeaderName) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let headers = SecurityHeaders::default();
+        let csp = get(&headers, &header::CONTENT_SECURITY_POLICY).unwrap();
+        assert!(csp.starts_with("default-src 'self'; script-src 'self' https://cdn.jsdelivr.net"));
+        assert!(csp.contains("connect-src 'self'"));
+        assert!(csp.ends_with("; frame-ancestors 'self'"), "{csp}");
+        assert_eq!(get(&headers, &header::X_FRAME_OPTIONS), Some("SAMEORIGIN"));
+        assert_eq!(get(&headers, &header::REFERRER_POLICY), Some("no-referrer"));
+        assert_eq!(
+            get(&headers, &header::X_CONTENT_TYPE_OPTIONS),
+            Some("nosniff")
+        );
+    }
+
+    #[test]
+    fn test_frame_ancestors() {
+        let headers = SecurityHeaders::new(
+            None,
+            ["https://portal.example.com/", "https://*.example.org"],
+            "same-origin",
+        )
+        .unwrap();
+        let csp = get(&headers, &header::CONTENT_SECURITY_POLICY).unwrap();
+        assert!(
+            csp.ends_with(
+                "frame-ancestors 'self' https://portal.example.com https://*.example.org"
+            )
+        );
+        assert_eq!(get(&headers, &header::X_FRAME_OPTIONS), None);
+        assert_eq!(get(&headers, &header::REFERRER_POLICY), Some("same-origin"));
+    }
+
+    #[test]
+    fn test_custom_csp() {
+        let headers =
+            SecurityHeaders::new(Some("default-src 'self';"), None::<&str>, "no-referrer").unwrap();
+        assert_eq!(
+            get(&headers, &header::CONTENT_SECURITY_POLICY),
+            Some("default-src 'self'; frame-ancestors 'self'")
+        );
+
+        let headers = SecurityHeaders::new(
+            Some("default-src 'self'; frame-ancestors 'none'"),
+            ["https://ignored.example.com"],
+            "no-referrer",
+        )
+        .unwrap();
+        assert_eq!(
+            get(&headers, &header::CONTENT_SECURITY_POLICY),
+            Some("default-src 'self'; frame-ancestors 'none'")
+        );
+        assert!(SecurityHeaders::new(Some("bad\nvalue"), None::<&str>, "no-referrer").is_err());
+    }
+}
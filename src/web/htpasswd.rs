@@ -0,0 +1,223 @@
+//! HTTP Basic authentication backed by an Apache-style `htpasswd` file.
+//!
+//! Each line has the form `user:hash`. Only slow, salted hashes are accepted:
+//! bcrypt (`$2a$`, `$2b$`, `$2y$`, as produced by `htpasswd -B`) and argon2
+//! (`$argon2id$`, `$argon2i$`, `$argon2d$`). Entries with other schemes are
+//! skipped with a warning.
+//!
+//! The file is re-read whenever its modification time or size changes, so
+//! users can be added or removed without restarting the server.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use sha2::{Digest, Sha256};
+
+/// A hot-reloading `htpasswd` user database.
+pub struct Htpasswd {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    entries: HashMap<String, String>,
+    stamp: Option<(SystemTime, u64)>,
+    /// Digests of credentials that already passed verification, so the slow
+    /// hash is computed once per user/password rather than once per request.
+    verified: HashMap<[u8; 32], String>,
+}
+
+impl Htpasswd {
+    /// Load the user database from `path`.
+    pub fn load(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let (entries, stamp) = read_file(&path)?;
+        tracing::info!(
+            "loaded {} htpasswd user(s) from {}",
+            entries.len(),
+            path.display()
+        );
+        Ok(Self {
+            path,
+            state: Mutex::new(State {
+                entries,
+                stamp,
+                verified: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Check a username and password, reloading the file first if it changed.
+    ///
+    /// This may run a deliberately slow hash function; call it from a
+    /// blocking-friendly context.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let digest = credential_digest(user, password);
+        let hash = {
+            let mut state = self.state.lock().unwrap();
+            self.refresh(&mut state);
+            if state.verified.get(&digest).is_some_and(|u| u == user) {
+                return true;
+            }
+            match state.entries.get(user) {
+                Some(hash) => hash.clone(),
+                None => return false,
+            }
+        };
+
+        if !verify_hash(password, &hash) {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        // Only cache if the entry was not replaced while we were hashing.
+        if state.entries.get(user) == Some(&hash) {
+            state.verified.insert(digest, user.to_owned());
+        }
+        true
+    }
+
+    fn refresh(&self, state: &mut State) {
+        let stamp = file_stamp(&self.path);
+        if stamp.is_some() && stamp == state.stamp {
+            return;
+        }
+        match read_file(&self.path) {
+            Ok((entries, stamp)) => {
+                tracing::info!(
+                    "reloaded {} htpasswd user(s) from {}",
+                    entries.len(),
+                    self.path.display()
+                );
+                state.entries = entries;
+                state.stamp = stamp;
+                state.verified.clear();
+            }
+            Err(e) => {
+                tracing::error!("failed to reload {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+type Entries = HashMap<String, String>;
+
+fn read_file(path: &Path) -> std::io::Result<(Entries, Option<(SystemTime, u64)>)> {
+    let stamp = file_stamp(path);
+    let contents = std::fs::read_to_string(path)?;
+    Ok((parse(&contents), stamp))
+}
+
+fn parse(contents: &str) -> Entries {
+    let mut entries = HashMap::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((user, hash)) = line.split_once(':') else {
+            tracing::warn!("htpasswd line {}: missing ':'", n + 1);
+            continue;
+        };
+        if !is_supported(hash) {
+            tracing::warn!(
+                "htpasswd line {}: unsupported hash for user {user} (use bcrypt or argon2)",
+                n + 1
+            );
+            continue;
+        }
+        entries.insert(user.to_owned(), hash.to_owned());
+    }
+    entries
+}
+
+fn is_supported(hash: &str) -> bool {
+    is_bcrypt(hash) || hash.starts_with("$argon2")
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p))
+}
+
+fn verify_hash(password: &str, hash: &str) -> bool {
+    if is_bcrypt(hash) {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        argon2::Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+fn credential_digest(user: &str, password: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(user.as_bytes());
+    hasher.update(b":");
+    hasher.update(password.as_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("tty-web-{name}-{}.htpasswd", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_bcrypt_and_argon2() {
+        let bcrypt_hash = bcrypt::hash("pw1", 4).unwrap();
+        let salt = SaltString::encode_b64(b"tty-web-test-salt").unwrap();
+        let argon_hash = argon2::Argon2::default()
+            .hash_password(b"pw2", &salt)
+            .unwrap()
+            .to_string();
+        let path = temp_file(
+            "hashes",
+            &format!("# comment\nalice:{bcrypt_hash}\nbob:{argon_hash}\n"),
+        );
+
+        let db = Htpasswd::load(&path).unwrap();
+        assert!(db.verify("alice", "pw1"));
+        assert!(db.verify("alice", "pw1"), "cached verification");
+        assert!(!db.verify("alice", "pw2"));
+        assert!(db.verify("bob", "pw2"));
+        assert!(!db.verify("carol", "pw1"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_unsupported_hash_skipped() {
+        let entries = parse("old:$apr1$abc$def\nplain:secret\n");
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn test_reload_on_change() {
+        let path = temp_file(
+            "reload",
+            &format!("alice:{}\n", bcrypt::hash("a", 4).unwrap()),
+        );
+        let db = Htpasswd::load(&path).unwrap();
+        assert!(db.verify("alice", "a"));
+
+        std::fs::write(&path, format!("bob:{}\n", bcrypt::hash("bb", 4).unwrap())).unwrap();
+        assert!(!db.verify("alice", "a"), "removed user must be rejected");
+        assert!(db.verify("bob", "bb"));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,340 @@
+//! JSON Web Token verification.
+//!
+//! A portal that already signs users in can hand them a JWT instead of the
+//! shared access token. The token is accepted wherever the shared token is
+//! (header, cookie or `?token=`) and verified locally, against either an HMAC
+//! secret or the public keys in a JWKS file — tty-web never fetches keys over
+//! the network.
+//!
+//! Besides the signature, `exp` is always required and checked; `aud` and
+//! `iss` are checked when expected values are configured. Selected claims
+//! become part of the client's [`Identity`](crate::web::auth::Identity):
+//!
+//! - the user name (`sub` by default), shown in logs and the audit trail;
+//! - optionally, the unix user new sessions run as;
+//! - optionally, the limit profile new sessions use.
+
+use std::path::Path;
+
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::{Map, Value};
+
+/// HMAC algorithms accepted with a shared secret.
+const HMAC_ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+/// Which claims to check and which to use.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JwtConfig {
+    /// Accepted `aud` values; any audience is accepted when empty.
+    pub audience: Vec<String>,
+    /// Accepted `iss` values; any issuer is accepted when empty.
+    pub issuer: Vec<String>,
+    /// Claim holding the user name.
+    pub name_claim: String,
+    /// Claim naming the unix user to run new sessions as.
+    pub run_as_claim: Option<String>,
+    /// Claim naming the limit profile for new sessions.
+    pub profile_claim: Option<String>,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            audience: Vec::new(),
+            issuer: Vec::new(),
+            name_claim: "sub".to_owned(),
+            run_as_claim: None,
+            profile_claim: None,
+        }
+    }
+}
+
+/// The verified contents of a token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JwtClaims {
+    /// User name from [`JwtConfig::name_claim`].
+    pub name: Option<String>,
+    /// Unix user from [`JwtConfig::run_as_claim`].
+    pub run_as: Option<String>,
+    /// Limit profile from [`JwtConfig::profile_claim`].
+    pub profile: Option<String>,
+    /// All claims of the token.
+    pub claims: Map<String, Value>,
+}
+
+/// A verification key and the algorithms it may be used with.
+struct Key {
+    kid: Option<String>,
+    key: DecodingKey,
+    algorithms: Vec<Algorithm>,
+}
+
+/// Verifies tokens against locally configured keys.
+pub struct JwtVerifier {
+    keys: Vec<Key>,
+    config: JwtConfig,
+}
+
+impl std::fmt::Debug for JwtVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtVerifier")
+            .field("keys", &self.keys.len())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl JwtVerifier {
+    /// Verify HS256/HS384/HS512 tokens signed with `secret`.
+    pub fn hmac(secret: &[u8], config: JwtConfig) -> Self {
+        Self {
+            keys: vec![Key {
+                kid: None,
+                key: DecodingKey::from_secret(secret),
+                algorithms: HMAC_ALGORITHMS.to_vec(),
+            }],
+            config,
+        }
+    }
+
+    /// Verify tokens against the keys of a JWKS document.
+    ///
+    /// A key is only used with its `alg`, or with the algorithms of its key
+    /// type if it has none. Tokens are matched to keys by `kid`; a token
+    /// without `kid` is tried against every key.
+    pub fn jwks(json: &str, config: JwtConfig) -> Result<Self, String> {
+        let set: JwkSet = serde_json::from_str(json).map_err(|e| format!("invalid JWKS: {e}"))?;
+        let mut keys = Vec::new();
+        for jwk in &set.keys {
+            let kid = jwk.common.key_id.clone();
+            let algorithms = match jwk.common.key_algorithm {
+                Some(alg) => vec![
+                    alg.to_string()
+                        .parse()
+                        .map_err(|_| format!("unsupported JWK algorithm {alg}"))?,
+                ],
+                None => key_type_algorithms(&jwk.algorithm),
+            };
+            let key = DecodingKey::from_jwk(jwk).map_err(|e| {
+                format!(
+                    "invalid JWK {}: {e}",
+                    kid.as_deref().unwrap_or("without kid")
+                )
+            })?;
+            keys.push(Key {
+                kid,
+                key,
+                algorithms,
+            });
+        }
+        if keys.is_empty() {
+            return Err("JWKS contains no keys".to_owned());
+        }
+        Ok(Self { keys, config })
+    }
+
+    /// Load a JWKS file.
+    pub fn load_jwks(path: &Path, config: JwtConfig) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Self::jwks(&json, config)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Verify `token` and extract its claims.
+    pub fn verify(&self, token: &str) -> Result<JwtClaims, jsonwebtoken::errors::Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let mut last_error = jsonwebtoken::errors::ErrorKind::InvalidKeyFormat.into();
+        let candidates = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid.is_none() || key.kid == header.kid);
+        for key in candidates {
+            if !key.algorithms.contains(&header.alg) {
+                last_error = jsonwebtoken::errors::ErrorKind::InvalidAlgorithm.into();
+                continue;
+            }
+            match jsonwebtoken::decode::<Map<String, Value>>(
+                token,
+                &key.key,
+                &self.validation(header.alg),
+            ) {
+                Ok(data) => return Ok(self.claims(data.claims)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn validation(&self, alg: Algorithm) -> Validation {
+        let mut validation = Validation::new(alg);
+        if self.config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audience);
+        }
+        if !self.config.issuer.is_empty() {
+            validation.set_issuer(&self.config.issuer);
+        }
+        validation
+    }
+
+    fn claims(&self, claims: Map<String, Value>) -> JwtClaims {
+        let string = |name: Option<&String>| {
+            name.and_then(|name| claims.get(name))
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+        };
+        JwtClaims {
+            name: string(Some(&self.config.name_claim)),
+            run_as: string(self.config.run_as_claim.as_ref()),
+            profile: string(self.config.profile_claim.as_ref()),
+            claims,
+        }
+    }
+}
+
+/// Algorithms usable with a key of the given type.
+fn key_type_algorithms(params: &AlgorithmParameters) -> Vec<Algorithm> {
+    match params {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(ec) => match ec.curve {
+            EllipticCurve::P256 => vec![Algorithm::ES256],
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => Vec::new(),
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        AlgorithmParameters::OctetKey(_) => HMAC_ALGORITHMS.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    fn now() -> u64 {
+        jsonwebtoken::get_current_timestamp()
+    }
+
+    fn sign(claims: Value, header: Header, secret: &[u8]) -> String {
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    #[test]
+    fn test_hmac_claims() {
+        let verifier = JwtVerifier::hmac(
+            b"secret",
+            JwtConfig {
+                run_as_claim: Some("unix_user".to_owned()),
+                profile_claim: Some("profile".to_owned()),
+                ..JwtConfig::default()
+            },
+        );
+        let token = sign(
+            json!({"sub": "alice", "unix_user": "dev", "profile": "build", "exp": now() + 60}),
+            Header::default(),
+            b"secret",
+        );
+        let claims = verifier.verify(&token).unwrap();
+        assert_eq!(claims.name.as_deref(), Some("alice"));
+        assert_eq!(claims.run_as.as_deref(), Some("dev"));
+        assert_eq!(claims.profile.as_deref(), Some("build"));
+
+        let forged = sign(
+            json!({"sub": "alice", "exp": now() + 60}),
+            Header::default(),
+            b"other",
+        );
+        assert!(verifier.verify(&forged).is_err());
+        assert!(verifier.verify("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn test_exp_required() {
+        let verifier = JwtVerifier::hmac(b"secret", JwtConfig::default());
+        let missing = sign(json!({"sub": "alice"}), Header::default(), b"secret");
+        assert!(verifier.verify(&missing).is_err());
+        let expired = sign(
+            json!({"sub": "alice", "exp": now() - 3600}),
+            Header::default(),
+            b"secret",
+        );
+        assert!(verifier.verify(&expired).is_err());
+    }
+
+    #[test]
+    fn test_audience_and_issuer() {
+        let verifier = JwtVerifier::hmac(
+            b"secret",
+            JwtConfig {
+                audience: vec!["tty-web".to_owned()],
+                issuer: vec!["https://portal.example.com".to_owned()],
+                ..JwtConfig::default()
+            },
+        );
+        let token = |aud: &str, iss: &str| {
+            sign(
+                json!({"sub": "a", "aud": aud, "iss": iss, "exp": now() + 60}),
+                Header::default(),
+                b"secret",
+            )
+        };
+        assert!(
+            verifier
+                .verify(&token("tty-web", "https://portal.example.com"))
+                .is_ok()
+        );
+        assert!(
+            verifier
+                .verify(&token("other", "https://portal.example.com"))
+                .is_err()
+        );
+        assert!(
+            verifier
+                .verify(&token("tty-web", "https://evil.example.com"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_jwks() {
+        let k = URL_SAFE_NO_PAD.encode(b"jwks-secret");
+        let jwks = json!({"keys": [
+            {"kty": "oct", "kid": "one", "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(b"wrong")},
+            {"kty": "oct", "kid": "two", "alg": "HS256", "k": k},
+        ]});
+        let verifier = JwtVerifier::jwks(&jwks.to_string(), JwtConfig::default()).unwrap();
+        let claims = json!({"sub": "bob", "exp": now() + 60});
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("two".to_owned());
+        let token = sign(claims.clone(), header.clone(), b"jwks-secret");
+        assert_eq!(
+            verifier.verify(&token).unwrap().name.as_deref(),
+            Some("bob")
+        );
+
+        header.kid = Some("one".to_owned());
+        let token = sign(claims.clone(), header, b"jwks-secret");
+        assert!(verifier.verify(&token).is_err(), "kid selects the key");
+
+        let token = sign(claims.clone(), Header::default(), b"jwks-secret");
+        assert!(verifier.verify(&token).is_ok(), "no kid tries every key");
+
+        let token = sign(claims, Header::new(Algorithm::HS512), b"jwks-secret");
+        assert!(verifier.verify(&token).is_err(), "alg is pinned by the JWK");
+
+        assert!(JwtVerifier::jwks(r#"{"keys": []}"#, JwtConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+//! Admission control for new sessions.
+//!
+//! Every WebSocket without a `sid` forks a new shell, so without limits a
+//! script hammering `/ws` could exhaust the host. [`SessionLimiter`] caps the
+//! number of live sessions overall and per client IP, and rate-limits session
+//! creation per client IP with a token bucket.
+//!
+//! IPv6 clients are grouped by their /64 prefix ([`client_key`]), since one
+//! host can usually pick any address in it. Clients without an IP — those on
+//! a unix socket — are only subject to the overall limit. Behind a reverse
+//! proxy every client has the proxy's IP and they share its per-IP limits.
+//!
+//! Attaching to an existing session is never limited.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::session::SessionStore;
+
+/// Rate buckets kept before full (idle) ones are pruned.
+const MAX_BUCKETS: usize = 4096;
+
+/// Length of the prefix IPv6 clients are grouped by.
+pub const IPV6_PREFIX_LEN: u32 = 64;
+
+/// The address per-IP limits are kept under for a client at `ip`: IPv4
+/// addresses as they are (also when mapped into IPv6), IPv6 addresses cut to
+/// their first [`IPV6_PREFIX_LEN`] bits.
+pub fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => {
+                let mask = u128::MAX << (128 - IPV6_PREFIX_LEN);
+                IpAddr::V6((u128::from(v6) & mask).into())
+            }
+        },
+    }
+}
+
+/// Configured session limits. `None` disables a limit.
+#[derive(Clone, Debug, Default)]
+pub struct SessionLimits {
+    /// Maximum number of live sessions (`--max-sessions`).
+    pub max_sessions: Option<usize>,
+    /// Maximum number of live sessions created from one IP
+    /// (`--max-sessions-per-ip`).
+    pub max_sessions_per_ip: Option<usize>,
+    /// Session creation rate per IP (`--session-rate`).
+    pub rate: Option<RateLimit>,
+}
+
+/// Token bucket parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Sustained sessions per minute.
+    pub per_minute: u32,
+    /// Sessions that may be created back to back.
+    pub burst: u32,
+}
+
+/// Why a new session was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refusal {
+    /// `max_sessions` reached.
+    TooManySessions,
+    /// `max_sessions_per_ip` reached for this client.
+    TooManyForClient,
+    /// The client is creating sessions too fast.
+    RateLimited,
+}
+
+impl Refusal {
+    /// Short human-readable reason, e.g. for a WebSocket close frame.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::TooManySessions => "session limit reached",
+            Self::TooManyForClient => "per-client session limit reached",
+            Self::RateLimited => "session creation rate limit exceeded",
+        }
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.reason())
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(rate.per_minute) / 60.0).min(f64::from(rate.burst));
+        self.updated = now;
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Admitted sessions that are not in the store yet.
+    pending: usize,
+    pending_by_ip: HashMap<IpAddr, usize>,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+/// Enforces [`SessionLimits`] against the live sessions in a [`SessionStore`].
+#[derive(Clone, Default)]
+pub struct SessionLimiter {
+    limits: SessionLimits,
+    state: Arc<Mutex<State>>,
+}
+
+impl SessionLimiter {
+    /// Create a limiter enforcing `limits`.
+    pub fn new(limits: SessionLimits) -> Self {
+        Self {
+            limits,
+            state: Arc::default(),
+        }
+    }
+
+    /// Decide whether a client at `ip` may create a new session. Clients
+    /// with the same [`client_key`] share the per-IP limits.
+    ///
+    /// The returned [`Permit`] counts towards the limits until it is dropped;
+    /// keep it until the session has been inserted into `sessions`, so that
+    /// concurrent requests cannot overshoot.
+    pub fn admit(&self, sessions: &SessionStore, ip: Option<IpAddr>) -> Result<Permit, Refusal> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(max) = self.limits.max_sessions {
+            if sessions.len() + state.pending >= max {
+                return Err(Refusal::TooManySessions);
+            }
+        }
+        let ip = ip.map(client_key);
+        if let Some(ip) = ip {
+            if let Some(max) = self.limits.max_sessions_per_ip {
+                let pending = state.pending_by_ip.get(&ip).copied().unwrap_or(0);
+                if sessions.count_by_ip(|client| client_key(client) == ip) + pending >= max {
+                    return Err(Refusal::TooManyForClient);
+                }
+            }
+            if let Some(rate) = self.limits.rate {
+                take_token(&mut state.buckets, ip, rate)?;
+            }
+            *state.pending_by_ip.entry(ip).or_default() += 1;
+        }
+        state.pending += 1;
+        Ok(Permit {
+            state: self.state.clone(),
+            ip,
+        })
+    }
+}
+
+fn take_token(
+    buckets: &mut HashMap<IpAddr, Bucket>,
+    ip: IpAddr,
+    rate: RateLimit,
+) -> Result<(), Refusal> {
+    let now = Instant::now();
+    if buckets.len() >= MAX_BUCKETS {
+        buckets.retain(|_, bucket| {
+            bucket.refill(rate, now);
+            bucket.tokens < f64::from(rate.burst)
+        });
+    }
+    let bucket = buckets.entry(ip).or_insert(Bucket {
+        tokens: f64::from(rate.burst),
+        updated: now,
+    });
+    bucket.refill(rate, now);
+    if bucket.tokens < 1.0 {
+        return Err(Refusal::RateLimited);
+    }
+    bucket.tokens -= 1.0;
+    Ok(())
+}
+
+/// A reserved slot for a session being created. See [`SessionLimiter::admit`].
+pub struct Permit {
+    state: Arc<Mutex<State>>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = state.pending_by_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    state.pending_by_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_max_sessions_counts_pending() {
+        let store = SessionStore::new();
+        let limiter = SessionLimiter::new(SessionLimits {
+            max_sessions: Some(1),
+            ..Default::default()
+        });
+        let permit = limiter.admit(&store, None).unwrap();
+        assert_eq!(
+            limiter.admit(&store, ip("192.0.2.1")).err(),
+            Some(Refusal::TooManySessions)
+        );
+        drop(permit);
+        assert!(limiter.admit(&store, None).is_ok());
+    }
+
+    #[test]
+    fn test_max_sessions_per_ip() {
+        let store = SessionStore::new();
+        let limiter = SessionLimiter::new(SessionLimits {
+            max_sessions_per_ip: Some(1),
+            ..Default::default()
+        });
+        let _permit = limiter.admit(&store, ip("192.0.2.1")).unwrap();
+        assert_eq!(
+            limiter.admit(&store, ip("192.0.2.1")).err(),
+            Some(Refusal::TooManyForClient)
+        );
+        assert!(limiter.admit(&store, ip("192.0.2.2")).is_ok());
+        assert!(
+            limiter.admit(&store, None).is_ok(),
+            "no IP, no per-IP limit"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_grouped_by_prefix() {
+        let store = SessionStore::new();
+        let limiter = SessionLimiter::new(SessionLimits {
+            max_sessions_per_ip: Some(1),
+            ..Default::default()
+        });
+        let _permit = limiter.admit(&store, ip("2001:db8:0:1::1")).unwrap();
+        assert_eq!(
+            limiter.admit(&store, ip("2001:db8:0:1:ffff::2")).err(),
+            Some(Refusal::TooManyForClient),
+            "same /64"
+        );
+        assert!(limiter.admit(&store, ip("2001:db8:0:2::1")).is_ok());
+
+        assert_eq!(
+            client_key("::ffff:192.0.2.1".parse().unwrap()),
+            "192.0.2.1".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let store = SessionStore::new();
+        let limiter = SessionLimiter::new(SessionLimits {
+            rate: Some(RateLimit {
+                per_minute: 1,
+                burst: 2,
+            }),
+            ..Default::default()
+        });
+        assert!(limiter.admit(&store, ip("192.0.2.1")).is_ok());
+        assert!(limiter.admit(&store, ip("192.0.2.1")).is_ok());
+        assert_eq!(
+            limiter.admit(&store, ip("192.0.2.1")).err(),
+            Some(Refusal::RateLimited)
+        );
+        assert!(limiter.admit(&store, ip("192.0.2.2")).is_ok());
+    }
+
+    #[test]
+    fn test_bucket_refill() {
+        let rate = RateLimit {
+            per_minute: 60,
+            burst: 3,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+        };
+        bucket.refill(rate, start + std::time::Duration::from_secs(2));
+        assert_eq!(bucket.tokens, 2.0);
+        bucket.refill(rate, start + std::time::Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 3.0, "capped at burst");
+    }
+}
@@ -3,9 +3,22 @@
 //! Routes:
 //! - `GET /ws` — WebSocket endpoint (terminal I/O)
 //! - `GET /api/v1/ping` — health check
+//! - `GET /api/v1/sessions`, `POST /api/v1/sessions`,
+//!   `DELETE /api/v1/sessions/{id}` — [session management](sessions)
 //! - `GET /` and `GET /*path` — embedded static frontend
+//!
+//! All routes except the health check are subject to [`auth::Auth`]. Every
+//! response carries the [security headers](headers).
 
+pub mod auth;
+pub mod headers;
 pub mod health;
+pub mod htpasswd;
+pub mod jwt;
+pub mod limiter;
+pub mod origin;
+pub mod peer;
+pub mod sessions;
 pub mod static_files;
 pub mod ws;
 
@@ -13,9 +26,17 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use axum::Router;
-use axum::routing::get;
+use axum::middleware;
+use axum::routing::{delete, get};
 
-use crate::session::SessionStore;
+use crate::audit::AuditLog;
+use crate::cgroup::CgroupConfig;
+use crate::filter::OutputPolicy;
+use crate::isolation::Isolation;
+use crate::limits::LimitProfiles;
+use crate::session::{IdlePolicy, Session, SessionStore};
+use crate::spawn::RunAsPolicy;
+use crate::spool::SpoolConfig;
 
 /// Shared state passed to all request handlers.
 #[derive(Clone)]
@@ -27,10 +48,218 @@ pub struct AppState {
     pub pwd: Option<PathBuf>,
     /// Scrollback buffer size in bytes.
     pub scrollback_limit: usize,
+    /// Compress older session scrollback (disabled by default).
+    pub scrollback_compression: bool,
     /// Global session registry.
     pub sessions: Arc<SessionStore>,
     /// Time without clients before a session is reaped.
     pub orphan_timeout: std::time::Duration,
+    /// Authentication policy for protected routes (disabled by default).
+    pub auth: auth::Auth,
+    /// Origins allowed to open a WebSocket (same-origin by default).
+    pub origins: origin::OriginPolicy,
+    /// Unix account selection for new sessions (server's own user by default).
+    pub run_as: RunAsPolicy,
+    /// Namespace isolation for new sessions (disabled by default).
+    pub isolation: Option<Isolation>,
+    /// Per-session cgroup limits (disabled by default).
+    pub cgroup: Option<CgroupConfig>,
+    /// rlimit profiles for new sessions (none by default).
+    pub limits: LimitProfiles,
+    /// Caps on the number and creation rate of sessions (none by default).
+    pub session_limiter: limiter::SessionLimiter,
+    /// Keystroke and session audit log (disabled by default).
+    pub audit: Option<AuditLog>,
+    /// Disk spool for output evicted from session scrollback (disabled by
+    /// default).
+    pub spool: Option<SpoolConfig>,
+    /// Control strings filtered from session output (none by default).
+    pub output_filter: Arc<OutputPolicy>,
+    /// Headers added to every response (CSP and friends by default).
+    pub security_headers: headers::SecurityHeaders,
+    /// Client certificate names allowed to open sessions (any by default).
+    pub client_cert_allow: Arc<[peer::CertPattern]>,
+    /// Idle-input policy for new sessions (none by default).
+    pub idle: Option<IdlePolicy>,
+    /// Maximum lifetime of new sessions (unlimited by default).
+    pub max_lifetime: Option<std::time::Duration>,
+    /// Commands `POST /api/v1/sessions` may start besides the shell (none by
+    /// default).
+    pub api_commands: Arc<[String]>,
+    /// Environment variables `POST /api/v1/sessions` may set (none by
+    /// default).
+    pub api_env: Arc<[String]>,
+    /// Users who may manage every session through the REST API, not just
+    /// their own (none by default).
+    pub admins: Arc<[String]>,
+}
+
+impl AppState {
+    /// Create the state with the required settings; optional features are
+    /// disabled and can be enabled with the `with_*` methods.
+    pub fn new(
+        shell: String,
+        pwd: Option<PathBuf>,
+        scrollback_limit: usize,
+        sessions: Arc<SessionStore>,
+        orphan_timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            shell,
+            pwd,
+            scrollback_limit,
+            scrollback_compression: false,
+            sessions,
+            orphan_timeout,
+            auth: auth::Auth::default(),
+            origins: origin::OriginPolicy::default(),
+            run_as: RunAsPolicy::default(),
+            isolation: None,
+            cgroup: None,
+            limits: LimitProfiles::default(),
+            session_limiter: limiter::SessionLimiter::default(),
+            audit: None,
+            spool: None,
+            output_filter: Arc::default(),
+            security_headers: headers::SecurityHeaders::default(),
+            client_cert_allow: Arc::new([]),
+            idle: None,
+            max_lifetime: None,
+            api_commands: Arc::new([]),
+            api_env: Arc::new([]),
+            admins: Arc::new([]),
+        }
+    }
+
+    /// Set the authentication policy.
+    pub fn with_auth(mut self, auth: auth::Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Set which cross-origin pages may open a WebSocket.
+    pub fn with_origins(mut self, origins: origin::OriginPolicy) -> Self {
+        self.origins = origins;
+        self
+    }
+
+    /// Set which unix account new sessions run as.
+    pub fn with_run_as(mut self, run_as: RunAsPolicy) -> Self {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Start new sessions in their own Linux namespaces.
+    pub fn with_isolation(mut self, isolation: Option<Isolation>) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Place each new session in its own cgroup with these limits.
+    pub fn with_cgroup(mut self, cgroup: Option<CgroupConfig>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Set the rlimit profiles new sessions can use.
+    pub fn with_limits(mut self, limits: LimitProfiles) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Limit how many sessions may exist and how fast clients create them.
+    pub fn with_session_limits(mut self, limits: limiter::SessionLimits) -> Self {
+        self.session_limiter = limiter::SessionLimiter::new(limits);
+        self
+    }
+
+    /// Record session events and keystrokes in `audit`.
+    pub fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Compress older session scrollback so the limit holds more history.
+    pub fn with_scrollback_compression(mut self, compress: bool) -> Self {
+        self.scrollback_compression = compress;
+        self
+    }
+
+    /// Spool output evicted from session scrollback to disk.
+    pub fn with_spool(mut self, spool: Option<SpoolConfig>) -> Self {
+        self.spool = spool;
+        self
+    }
+
+    /// Drop or restrict control strings in the output of new sessions.
+    pub fn with_output_filter(mut self, policy: OutputPolicy) -> Self {
+        self.output_filter = Arc::new(policy);
+        self
+    }
+
+    /// Set the security headers added to every response.
+    pub fn with_security_headers(mut self, headers: headers::SecurityHeaders) -> Self {
+        self.security_headers = headers;
+        self
+    }
+
+    /// Only let clients whose TLS certificate matches one of these names
+    /// (or subjects) open or attach to sessions. An empty list allows any
+    /// client.
+    pub fn with_client_cert_allow(
+        mut self,
+        names: impl IntoIterator<Item = peer::CertPattern>,
+    ) -> Self {
+        self.client_cert_allow = names.into_iter().collect();
+        self
+    }
+
+    /// Warn, then hang up or detach clients of new sessions nobody types in.
+    pub fn with_idle(mut self, idle: Option<IdlePolicy>) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Terminate new sessions once they are `max_lifetime` old.
+    pub fn with_max_lifetime(mut self, max_lifetime: Option<std::time::Duration>) -> Self {
+        self.max_lifetime = max_lifetime;
+        self
+    }
+
+    /// Let `POST /api/v1/sessions` start these commands instead of the shell.
+    pub fn with_api_commands(mut self, commands: impl IntoIterator<Item = String>) -> Self {
+        self.api_commands = commands.into_iter().collect();
+        self
+    }
+
+    /// Let `POST /api/v1/sessions` set these environment variables.
+    pub fn with_api_env(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.api_env = names.into_iter().collect();
+        self
+    }
+
+    /// Let these users manage sessions they do not own.
+    pub fn with_admins(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.admins = names.into_iter().collect();
+        self
+    }
+
+    /// Returns `true` if `identity` may manage `session`: it owns the
+    /// session or is an admin.
+    pub fn can_manage(&self, identity: &auth::Identity, session: &Session) -> bool {
+        let name = identity.name();
+        let name = name.as_deref();
+        session.is_owned_by(name) || name.is_some_and(|name| self.admins.iter().any(|a| a == name))
+    }
+
+    /// Returns `true` if `peer` passes the client certificate allow-list.
+    pub fn allows_client_cert(&self, peer: &peer::Peer) -> bool {
+        self.client_cert_allow.is_empty()
+            || peer
+                .client_cert
+                .as_ref()
+                .is_some_and(|cert| cert.is_allowed(&self.client_cert_allow))
+    }
 }
 
 /// Build the Axum router with all routes and shared state.
@@ -41,17 +270,124 @@ pub fn router(
     sessions: Arc<SessionStore>,
     orphan_timeout: std::time::Duration,
 ) -> Router {
-    let state = AppState {
+    router_with_state(AppState::new(
         shell,
         pwd,
         scrollback_limit,
         sessions,
         orphan_timeout,
-    };
+    ))
+}
+
+/// Build the Axum router from a fully configured [`AppState`].
+pub fn router_with_state(state: AppState) -> Router {
     Router::new()
-        .route("/ws", get(ws::ws_handler))
-        .route("/api/v1/ping", get(health::ping))
         .route("/", get(static_files::index))
         .route("/{*path}", get(static_files::static_file))
+        .route(
+            "/api/v1/sessions",
+            get(sessions::list).post(sessions::create),
+        )
+        .route("/api/v1/sessions/{id}", delete(sessions::kill))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
+        .route("/ws", get(ws::ws_handler))
+        .route("/api/v1/ping", get(health::ping))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            headers::set_headers,
+        ))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode, header};
+    use tower::ServiceExt;
+
+    fn app(auth: auth::Auth) -> Router {
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            SessionStore::new(),
+            crate::session::DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_auth(auth);
+        router_with_state(state)
+    }
+
+    async fn get(app: Router, uri: &str) -> axum::response::Response {
+        app.oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_static_requires_token() {
+        let resp = get(app(auth::Auth::default().with_token("secret")), "/").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_query_token_sets_cookie() {
+        let resp = get(
+            app(auth::Auth::default().with_token("secret")),
+            "/?token=secret",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cookie = resp.headers().get(header::SET_COOKIE).expect("set-cookie");
+        assert!(cookie.to_str().unwrap().starts_with("tty_web_token=secret"));
+        assert!(!cookie.to_str().unwrap().contains("Secure"));
+
+        let auth = auth::Auth::default()
+            .with_token("secret")
+            .with_secure_cookie(true);
+        let resp = get(app(auth), "/?token=secret").await;
+        let cookie = resp.headers().get(header::SET_COOKIE).expect("set-cookie");
+        assert!(cookie.to_str().unwrap().ends_with("; Secure"));
+    }
+
+    #[tokio::test]
+    async fn test_security_headers() {
+        let resp = get(app(auth::Auth::default()), "/").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let csp = resp.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap();
+        assert!(csp.contains("script-src 'self' https://cdn.jsdelivr.net"));
+        assert_eq!(resp.headers()[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert_eq!(resp.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let resp = get(app(auth::Auth::default().with_token("secret")), "/").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(
+            resp.headers().contains_key(header::CONTENT_SECURITY_POLICY),
+            "rejections carry the headers too"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions_require_token() {
+        let app = app(auth::Auth::default().with_token("secret"));
+        let resp = get(app.clone(), "/api/v1/sessions").await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = get(app, "/api/v1/sessions?token=secret").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ping_is_public() {
+        let resp = get(
+            app(auth::Auth::default().with_token("secret")),
+            "/api/v1/ping",
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,126 @@
+//! `Origin` checks for WebSocket upgrades.
+//!
+//! Browsers attach cookies and cached Basic credentials to cross-site
+//! WebSocket requests, so without an origin check any page a logged-in user
+//! visits could open a shell on their behalf (cross-site WebSocket hijacking).
+//!
+//! By default only same-origin upgrades are accepted: the `Origin` header must
+//! name the same host and port as the `Host` header. Additional origins can be
+//! allowed explicitly. Requests without an `Origin` header come from
+//! non-browser clients and are not subject to the check.
+
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, header};
+
+/// Which `Origin` values may open a WebSocket.
+#[derive(Clone, Debug, Default)]
+pub struct OriginPolicy {
+    allowed: Arc<[String]>,
+    any: bool,
+}
+
+impl OriginPolicy {
+    /// Allow same-origin requests plus the given origins
+    /// (`scheme://host[:port]`, or `*` for any origin).
+    pub fn new(allowed: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut any = false;
+        let mut list = Vec::new();
+        for origin in allowed {
+            let origin = origin.as_ref().trim();
+            if origin == "*" {
+                any = true;
+            } else if !origin.is_empty() {
+                list.push(origin.trim_end_matches('/').to_ascii_lowercase());
+            }
+        }
+        Self {
+            allowed: list.into(),
+            any,
+        }
+    }
+
+    /// Returns `true` if the request may be upgraded.
+    pub fn allows(&self, headers: &HeaderMap) -> bool {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return true;
+        };
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        if self.any {
+            return true;
+        }
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if self.allowed.contains(&origin) {
+            return true;
+        }
+        let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+        host.is_some_and(|host| same_origin(&origin, host))
+    }
+}
+
+/// Compare an `Origin` value with the `Host` header, treating an omitted port
+/// as the scheme's default.
+fn same_origin(origin: &str, host: &str) -> bool {
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let default_port = match scheme {
+        "http" => 80,
+        "https" => 443,
+        _ => return false,
+    };
+    let origin = split_host_port(authority, default_port);
+    let host = split_host_port(&host.to_ascii_lowercase(), default_port);
+    origin.is_some() && origin == host
+}
+
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    let authority = authority.parse::<axum::http::uri::Authority>().ok()?;
+    let port = authority.port_u16().unwrap_or(default_port);
+    Some((authority.host().to_owned(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(origin: Option<&str>, host: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_str(host).unwrap());
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_same_origin_default() {
+        let policy = OriginPolicy::default();
+        assert!(policy.allows(&headers(Some("http://localhost:9090"), "localhost:9090")));
+        assert!(policy.allows(&headers(Some("https://Example.com"), "example.com:443")));
+        assert!(!policy.allows(&headers(Some("http://evil.com"), "localhost:9090")));
+        assert!(!policy.allows(&headers(Some("http://localhost:9091"), "localhost:9090")));
+        assert!(!policy.allows(&headers(Some("null"), "localhost:9090")));
+    }
+
+    #[test]
+    fn test_missing_origin_allowed() {
+        assert!(OriginPolicy::default().allows(&headers(None, "localhost:9090")));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let policy = OriginPolicy::new(["https://portal.example.com/"]);
+        assert!(policy.allows(&headers(Some("https://portal.example.com"), "tty:9090")));
+        assert!(!policy.allows(&headers(Some("https://other.example.com"), "tty:9090")));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let policy = OriginPolicy::new(["*"]);
+        assert!(policy.allows(&headers(Some("http://anything"), "tty:9090")));
+    }
+}
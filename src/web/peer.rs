@@ -0,0 +1,238 @@
+//! Per-connection client information.
+//!
+//! [`Peer`] is attached to every request as [`ConnectInfo`](axum::extract::ConnectInfo)
+//! when the router is served with
+//! [`into_make_service_with_connect_info::<Peer>`](axum::Router::into_make_service_with_connect_info).
+//! Handlers treat it as optional, so the router keeps working when embedded
+//! without connect info.
+//!
+//! Over mutual TLS, the verified client certificate is attached as a
+//! [`ClientCert`]. Its names serve as the client's identity when no other
+//! credential names a user, and can be matched against an allow-list of
+//! [`CertPattern`]s.
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use tokio::net::{TcpListener, UnixListener};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::tls::TlsListener;
+
+/// The remote end of a client connection.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Peer {
+    /// Remote socket address, if the transport has one (not for unix
+    /// sockets).
+    pub addr: Option<SocketAddr>,
+    /// Client certificate verified during the TLS handshake.
+    pub client_cert: Option<Arc<ClientCert>>,
+}
+
+impl Peer {
+    /// Remote IP address, if known.
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.addr.map(|addr| addr.ip())
+    }
+}
+
+/// Names taken from a client certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientCert {
+    /// Subject distinguished name, e.g. `CN=alice, O=Example`.
+    pub subject: String,
+    /// Subject common names followed by the e-mail, DNS and URI subject
+    /// alternative names, in certificate order.
+    pub names: Vec<CertName>,
+}
+
+/// A name taken from a client certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertName {
+    /// Subject common name.
+    Cn(String),
+    /// E-mail (RFC 822) subject alternative name.
+    Email(String),
+    /// DNS subject alternative name.
+    Dns(String),
+    /// URI subject alternative name.
+    Uri(String),
+}
+
+impl CertName {
+    /// The name without its kind.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Cn(name) | Self::Email(name) | Self::Dns(name) | Self::Uri(name) => name,
+        }
+    }
+}
+
+/// An entry of the client certificate allow-list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CertPattern {
+    /// This name of this kind, written `cn:alice`, `email:alice@example.com`,
+    /// `dns:host.example.com` or `uri:spiffe://example.com/alice`.
+    Name(CertName),
+    /// This subject distinguished name, written as in the log, e.g.
+    /// `CN=bob, O=Ops`.
+    Subject(String),
+}
+
+/// Parse a `--tls-client-allow` entry: a typed name or a full subject.
+pub fn parse_cert_pattern(s: &str) -> Result<CertPattern, String> {
+    let name = match s.split_once(':') {
+        Some((_, "")) => None,
+        Some(("cn", name)) => Some(CertName::Cn(name.to_owned())),
+        Some(("email", name)) => Some(CertName::Email(name.to_owned())),
+        Some(("dns", name)) => Some(CertName::Dns(name.to_owned())),
+        Some(("uri", name)) => Some(CertName::Uri(name.to_owned())),
+        _ if s.contains('=') => return Ok(CertPattern::Subject(s.to_owned())),
+        _ => None,
+    };
+    name.map(CertPattern::Name).ok_or_else(|| {
+        format!(
+            "invalid client certificate entry {s:?}, expected cn:, email:, dns: or uri: and a name, or a subject such as CN=alice"
+        )
+    })
+}
+
+impl ClientCert {
+    /// Parse a DER-encoded certificate.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let mut names: Vec<CertName> = cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(|cn| CertName::Cn(cn.to_owned()))
+            .collect();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            names.extend(
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::RFC822Name(s) => Some(CertName::Email((*s).to_owned())),
+                        GeneralName::DNSName(s) => Some(CertName::Dns((*s).to_owned())),
+                        GeneralName::URI(s) => Some(CertName::Uri((*s).to_owned())),
+                        _ => None,
+                    }),
+            );
+        }
+        Some(Self {
+            subject: cert.subject().to_string(),
+            names,
+        })
+    }
+
+    /// The name identifying the client: the first common name, or the
+    /// first alternative name if the subject has none.
+    pub fn name(&self) -> Option<&str> {
+        self.names.first().map(CertName::as_str)
+    }
+
+    /// Returns `true` if any name, or the subject, matches an entry of
+    /// `allowed`. Names only match entries of the same kind.
+    pub fn is_allowed(&self, allowed: &[CertPattern]) -> bool {
+        allowed.iter().any(|pattern| match pattern {
+            CertPattern::Name(name) => self.names.contains(name),
+            CertPattern::Subject(subject) => *subject == self.subject,
+        })
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self {
+            addr: Some(*stream.remote_addr()),
+            client_cert: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let (_, connection) = stream.io().get_ref();
+        Self {
+            addr: Some(*stream.remote_addr()),
+            client_cert: connection
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|cert| ClientCert::from_der(cert))
+                .map(Arc::new),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for Peer {
+    fn connect_info(_stream: IncomingStream<'_, UnixListener>) -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_cert_names() {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["host.example.com".to_owned()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "alice");
+        params.subject_alt_names.push(rcgen::SanType::Rfc822Name(
+            "alice@example.com".try_into().unwrap(),
+        ));
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        let client = ClientCert::from_der(cert.der()).unwrap();
+        assert_eq!(client.subject, "CN=alice");
+        assert_eq!(
+            client.names,
+            [
+                CertName::Cn("alice".to_owned()),
+                CertName::Dns("host.example.com".to_owned()),
+                CertName::Email("alice@example.com".to_owned())
+            ]
+        );
+        assert_eq!(client.name(), Some("alice"));
+        let allows = |entry: &str| client.is_allowed(&[parse_cert_pattern(entry).unwrap()]);
+        assert!(allows("email:alice@example.com"));
+        assert!(allows("cn:alice"));
+        assert!(allows("dns:host.example.com"));
+        assert!(allows("CN=alice"));
+        assert!(!allows("cn:bob"));
+        assert!(!client.is_allowed(&[]));
+
+        assert_eq!(ClientCert::from_der(b"garbage"), None);
+    }
+
+    #[test]
+    fn test_cert_pattern_kinds() {
+        // A SAN that spells an allowed common name must not pass for it.
+        let client = ClientCert {
+            subject: "CN=mallory".to_owned(),
+            names: vec![
+                CertName::Cn("mallory".to_owned()),
+                CertName::Dns("alice".to_owned()),
+            ],
+        };
+        let allowed = [parse_cert_pattern("cn:alice").unwrap()];
+        assert!(!client.is_allowed(&allowed));
+        assert!(!client.is_allowed(&[parse_cert_pattern("uri:alice").unwrap()]));
+        assert!(client.is_allowed(&[parse_cert_pattern("dns:alice").unwrap()]));
+
+        assert_eq!(
+            parse_cert_pattern("CN=bob, O=Ops"),
+            Ok(CertPattern::Subject("CN=bob, O=Ops".to_owned()))
+        );
+        assert!(parse_cert_pattern("alice").is_err());
+        assert!(parse_cert_pattern("cn:").is_err());
+        assert!(parse_cert_pattern("san:alice").is_err());
+    }
+}
@@ -0,0 +1,1050 @@
+//! Session management endpoints.
+//!
+//! - `GET /api/v1/sessions` — list the caller's sessions (every session for
+//!   admins)
+//! - `POST /api/v1/sessions` — start a session with a custom command,
+//!   environment and size
+//! - `DELETE /api/v1/sessions/{id}` — signal a session's shell and remove the
+//!   session
+//!
+//! The routes are subject to [`auth::Auth`](crate::web::auth::Auth) like
+//! `/ws`, and to the client certificate allow-list. Listing and deleting only
+//! reach sessions the caller may [manage](AppState::can_manage). Session keys
+//! are only returned to the client that creates a session.
+//!
+//! [`start`] is also how `/ws` starts new sessions, so both paths apply the
+//! same unix account, limits and audit logging.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Extension;
+use axum::Json;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditEvent, AuditKind};
+use crate::session::{Access, IdlePolicy, Session, SessionOptions, is_valid_name};
+use crate::spawn::SpawnOptions;
+use crate::terminal::Terminal;
+use crate::web::AppState;
+use crate::web::auth::Identity;
+use crate::web::limiter::Refusal;
+use crate::web::peer::Peer;
+
+/// Response body of `GET /api/v1/sessions`.
+#[derive(Debug, Serialize)]
+pub struct SessionList {
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// Public description of a session.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    /// Session UUID.
+    pub id: String,
+    /// Session name, if it has one.
+    pub name: Option<String>,
+    /// Creation time, RFC 3339.
+    pub created: String,
+    /// Command the shell was started with.
+    pub command: String,
+    /// Process ID of the shell.
+    pub pid: Option<u32>,
+    /// Attached clients by access level.
+    pub clients: ClientCounts,
+    /// Current PTY window size.
+    pub window_size: WindowSize,
+    /// Bytes held in the scrollback buffer.
+    pub scrollback_bytes: usize,
+    /// Bytes of evicted scrollback spooled to disk.
+    pub spooled_bytes: u64,
+    /// Whether the shell has exited.
+    pub exited: bool,
+}
+
+/// Number of attached clients by access level.
+#[derive(Debug, Serialize)]
+pub struct ClientCounts {
+    pub interactive: usize,
+    pub view: usize,
+}
+
+/// PTY window size.
+#[derive(Debug, Serialize)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl SessionInfo {
+    /// Describe `session`.
+    pub fn new(session: &Session) -> Self {
+        let view = session.view_client_count();
+        let (rows, cols) = session.window_size();
+        Self {
+            id: session.id().to_owned(),
+            name: session.name().map(str::to_owned),
+            created: humantime::format_rfc3339_seconds(session.created()).to_string(),
+            command: session.terminal.command().to_owned(),
+            pid: session.terminal.pid(),
+            clients: ClientCounts {
+                interactive: session.client_count().saturating_sub(view),
+                view,
+            },
+            window_size: WindowSize { rows, cols },
+            scrollback_bytes: session.scrollback_bytes(),
+            spooled_bytes: session.spooled_bytes(),
+            exited: session.has_exited(),
+        }
+    }
+}
+
+/// Settings for a new session; unset fields fall back to the server's
+/// configuration.
+#[derive(Debug, Default)]
+pub struct NewSession {
+    /// Program to start instead of the configured shell.
+    pub command: Option<String>,
+    /// Arguments for the program.
+    pub args: Vec<String>,
+    /// Working directory instead of `--pwd`.
+    pub cwd: Option<PathBuf>,
+    /// Extra environment variables.
+    pub env: Vec<(String, String)>,
+    /// Initial PTY size as `(rows, cols)`.
+    pub window_size: Option<(u16, u16)>,
+    /// Time without clients before the session is reaped, instead of the
+    /// configured one.
+    pub orphan_timeout: Option<Duration>,
+    /// Limit profile the client asks for; the credential's takes precedence,
+    /// otherwise it must be allowed for the client's user.
+    pub profile: Option<String>,
+    /// Idle timeout; can only shorten the configured one.
+    pub idle_timeout: Option<Duration>,
+    /// Unique name for the session (see [`is_valid_name`]).
+    pub name: Option<String>,
+}
+
+/// Why [`start`] refused or failed to start a session.
+#[derive(Debug)]
+pub enum StartError {
+    /// The client may not start this session (unknown unix user, or a limit
+    /// profile that is unknown or not allowed).
+    Forbidden(String),
+    /// The [session limits](crate::web::limiter) refused the session.
+    Limited(Refusal),
+    /// Another session already has the requested name.
+    NameTaken(String),
+    /// Spawning the shell failed.
+    Io(std::io::Error),
+}
+
+/// Start a session for `identity` and register it in the store.
+///
+/// The session is subject to the [session limits](crate::web::limiter) and
+/// runs as the unix account and with the limit profile chosen for the
+/// client. `identity` becomes the session's owner.
+pub fn start(
+    state: &AppState,
+    identity: &Identity,
+    peer: &Peer,
+    spec: &NewSession,
+) -> Result<Arc<Session>, StartError> {
+    if let Some(name) = &spec.name {
+        if state.sessions.get(name).is_some() {
+            return Err(StartError::NameTaken(name.clone()));
+        }
+    }
+    let _permit = state
+        .session_limiter
+        .admit(&state.sessions, peer.ip())
+        .map_err(StartError::Limited)?;
+    let user_name = identity.name();
+    let user_name = user_name.as_deref();
+    // A unix user or profile assigned by the credential (JWT claims) takes
+    // precedence over the user map and the client's choice.
+    let run_as = match identity.run_as() {
+        Some(user) => state
+            .run_as
+            .resolve_claim(user)
+            .map(Some)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => {
+                    StartError::Forbidden(e.to_string())
+                }
+                _ => StartError::Io(e),
+            })?,
+        None => state
+            .run_as
+            .resolve(user_name)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::PermissionDenied => StartError::Forbidden(e.to_string()),
+                _ => StartError::Io(e),
+            })?,
+    };
+    let profile = match (identity.profile(), spec.profile.as_deref()) {
+        (Some(assigned), _) => Some(assigned),
+        (None, Some(requested)) if !state.limits.may_select(user_name, requested) => {
+            return Err(StartError::Forbidden(format!(
+                "limit profile {requested:?} not allowed for {}",
+                user_name.unwrap_or("anonymous")
+            )));
+        }
+        (None, requested) => requested,
+    };
+    let limits = state.limits.resolve(profile).ok_or_else(|| {
+        StartError::Forbidden(format!(
+            "unknown limit profile {:?}",
+            profile.unwrap_or_default()
+        ))
+    })?;
+    let run_as_name = run_as.as_ref().map(|r| r.name.clone());
+    let opts = SpawnOptions::default()
+        .with_run_as(run_as)
+        .with_isolation(state.isolation)
+        .with_cgroup(state.cgroup.clone())
+        .with_limits(limits)
+        .with_output_filter(state.output_filter.clone())
+        .with_args(spec.args.clone())
+        .with_env(spec.env.clone());
+    let command = spec.command.as_deref().unwrap_or(&state.shell);
+    let cwd = spec.cwd.as_deref().or(state.pwd.as_deref());
+    let (terminal, output_rx) =
+        Terminal::spawn_with(command, cwd, &opts).map_err(StartError::Io)?;
+    let idle = match (state.idle, spec.idle_timeout) {
+        (Some(idle), Some(timeout)) => Some(idle.shortened(timeout)),
+        (None, Some(timeout)) => Some(IdlePolicy::new(timeout)),
+        (idle, None) => idle,
+    };
+    let orphan_timeout = spec.orphan_timeout.unwrap_or(state.orphan_timeout);
+    let options = SessionOptions::new(state.scrollback_limit, orphan_timeout)
+        .with_scrollback_compression(state.scrollback_compression)
+        .with_client_ip(peer.ip())
+        .with_audit(state.audit.clone())
+        .with_spool(state.spool.clone())
+        .with_idle(idle)
+        .with_max_lifetime(state.max_lifetime)
+        .with_name(spec.name.clone())
+        .with_owner(user_name.map(str::to_owned));
+    let session = Session::with_options(terminal, output_rx, options);
+    if let Some((rows, cols)) = spec.window_size {
+        if let Err(e) = session.terminal.resize(rows, cols) {
+            tracing::warn!("resize failed: {e}");
+        }
+        session.set_window_size(rows, cols);
+    }
+    // Checked again atomically: another client may have taken the name while
+    // the shell was starting.
+    if state.sessions.insert(session.clone()).is_err() {
+        return Err(StartError::NameTaken(spec.name.clone().unwrap_or_default()));
+    }
+    if let Some(audit) = &state.audit {
+        audit.record(&AuditEvent {
+            client: peer.addr,
+            user: user_name,
+            ..AuditEvent::new(AuditKind::Create, session.id())
+        });
+    }
+    tracing::info!(
+        user = user_name.unwrap_or("-"),
+        client = ?peer.ip(),
+        run_as = run_as_name.as_deref().unwrap_or("-"),
+        profile = profile.unwrap_or("-"),
+        command,
+        name = session.name().unwrap_or("-"),
+        "created new session {}",
+        session.id()
+    );
+    Ok(session)
+}
+
+/// Request body of `POST /api/v1/sessions`; every field is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateRequest {
+    /// Unique session name.
+    pub name: Option<String>,
+    /// Program to start; the configured shell or one allowed with
+    /// `--api-command`.
+    pub command: Option<String>,
+    /// Arguments for the program.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Absolute working directory.
+    pub cwd: Option<PathBuf>,
+    /// Environment variables allowed with `--api-env`.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Initial PTY rows (default 24).
+    pub rows: Option<u16>,
+    /// Initial PTY columns (default 80).
+    pub cols: Option<u16>,
+    /// Seconds without clients before the session is reaped.
+    pub orphan_timeout: Option<u64>,
+}
+
+/// Response body of `POST /api/v1/sessions`.
+#[derive(Debug, Serialize)]
+pub struct CreatedSession {
+    /// Session UUID.
+    pub id: String,
+    /// Key granting input.
+    pub control_key: String,
+    /// Key granting read-only access.
+    pub view_key: String,
+    /// Frontend paths that attach to the session (by name, if it has one).
+    pub urls: AttachUrls,
+}
+
+/// Frontend paths that attach to a session, relative to the server's origin.
+#[derive(Debug, Serialize)]
+pub struct AttachUrls {
+    /// Interactive attach URL.
+    pub control: String,
+    /// Read-only attach URL.
+    pub view: String,
+}
+
+impl CreatedSession {
+    /// Describe the newly created `session`, including its keys.
+    pub fn new(session: &Session) -> Self {
+        // Names are URL-safe, see `is_valid_name`.
+        let url = |key: &str| match session.name() {
+            Some(name) => format!("/?session={name}&key={key}"),
+            None => format!("/?sid={}&key={key}", session.id()),
+        };
+        Self {
+            id: session.id().to_owned(),
+            control_key: session.control_key().to_owned(),
+            view_key: session.view_key().to_owned(),
+            urls: AttachUrls {
+                control: url(session.control_key()),
+                view: url(session.view_key()) + "&view",
+            },
+        }
+    }
+}
+
+impl CreateRequest {
+    /// Check the request against the server's allow-lists.
+    fn validate(self, state: &AppState) -> Result<NewSession, (StatusCode, String)> {
+        let bad_request = |msg: String| Err((StatusCode::BAD_REQUEST, msg));
+        if let Some(name) = &self.name {
+            if !is_valid_name(name) {
+                return bad_request(format!("invalid session name {name:?}"));
+            }
+        }
+        if let Some(command) = &self.command {
+            if *command != state.shell && !state.api_commands.contains(command) {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("command {command:?} not allowed"),
+                ));
+            }
+        }
+        if let Some(name) = self.env.keys().find(|name| !state.api_env.contains(name)) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("environment variable {name:?} not allowed"),
+            ));
+        }
+        if let Some(cwd) = &self.cwd {
+            if !cwd.is_absolute() || !cwd.is_dir() {
+                return bad_request(format!("cwd {cwd:?} is not an absolute directory"));
+            }
+        }
+        let window_size = match (self.rows, self.cols) {
+            (None, None) => None,
+            (Some(0), _) | (_, Some(0)) => {
+                return bad_request("rows and cols must be positive".into());
+            }
+            (rows, cols) => Some((rows.unwrap_or(24), cols.unwrap_or(80))),
+        };
+        if self.orphan_timeout == Some(0) {
+            return bad_request("orphan_timeout must be positive".into());
+        }
+        Ok(NewSession {
+            name: self.name,
+            command: self.command,
+            args: self.args,
+            cwd: self.cwd,
+            env: self.env.into_iter().collect(),
+            window_size,
+            orphan_timeout: self.orphan_timeout.map(Duration::from_secs),
+            ..NewSession::default()
+        })
+    }
+}
+
+/// Returns `true` if the client passes the client certificate allow-list.
+fn client_cert_allowed(state: &AppState, peer: &Option<Extension<ConnectInfo<Peer>>>) -> bool {
+    match peer {
+        Some(Extension(ConnectInfo(peer))) => state.allows_client_cert(peer),
+        None => state.allows_client_cert(&Peer::default()),
+    }
+}
+
+/// Parse a signal name accepted by `DELETE`, with or without `SIG` prefix.
+fn parse_signal(name: &str) -> Option<Signal> {
+    match name.strip_prefix("SIG").unwrap_or(name) {
+        "HUP" => Some(Signal::SIGHUP),
+        "INT" => Some(Signal::SIGINT),
+        "TERM" => Some(Signal::SIGTERM),
+        "KILL" => Some(Signal::SIGKILL),
+        _ => None,
+    }
+}
+
+/// `GET /api/v1/sessions`
+///
+/// Lists the sessions the caller owns, or every session for admins.
+pub async fn list(
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<Peer>>>,
+    identity: Option<Extension<Identity>>,
+) -> Response {
+    if !client_cert_allowed(&state, &peer) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let peer = peer
+        .map(|Extension(ConnectInfo(peer))| peer)
+        .unwrap_or_default();
+    let identity = identity
+        .map_or(Identity::Anonymous, |Extension(identity)| identity)
+        .or_certificate(&peer);
+    let sessions = state
+        .sessions
+        .list()
+        .iter()
+        .filter(|session| state.can_manage(&identity, session))
+        .map(|session| SessionInfo::new(session))
+        .collect();
+    Json(SessionList { sessions }).into_response()
+}
+
+/// `POST /api/v1/sessions`
+///
+/// Starts a session without attaching to it and returns its keys and attach
+/// URLs.
+pub async fn create(
+    State(state): State<AppState>,
+    peer: Option<Extension<ConnectInfo<Peer>>>,
+    identity: Option<Extension<Identity>>,
+    Json(request): Json<CreateRequest>,
+) -> Response {
+    if !client_cert_allowed(&state, &peer) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let spec = match request.validate(&state) {
+        Ok(spec) => spec,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let peer = peer
+        .map(|Extension(ConnectInfo(peer))| peer)
+        .unwrap_or_default();
+    let identity = identity
+        .map_or(Identity::Anonymous, |Extension(identity)| identity)
+        .or_certificate(&peer);
+    match start(&state, &identity, &peer, &spec) {
+        Ok(session) => (StatusCode::CREATED, Json(CreatedSession::new(&session))).into_response(),
+        Err(StartError::Forbidden(reason)) => {
+            tracing::warn!("refused to create session: {reason}");
+            StatusCode::FORBIDDEN.into_response()
+        }
+        Err(StartError::Limited(refusal)) => {
+            tracing::warn!(client = ?peer.ip(), "refused to create session: {refusal}");
+            (StatusCode::TOO_MANY_REQUESTS, refusal.reason()).into_response()
+        }
+        Err(StartError::NameTaken(name)) => (
+            StatusCode::CONFLICT,
+            format!("session name {name:?} is already taken"),
+        )
+            .into_response(),
+        Err(StartError::Io(e)) => {
+            tracing::error!("failed to create session: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// `DELETE /api/v1/sessions/{id}?signal=HUP|INT|TERM|KILL&key=…`, where `id`
+/// may also be the session's name.
+///
+/// Sends the signal (`HUP` by default) to the shell's process group, tells
+/// attached clients that the shell exited and removes the session at once.
+/// Only the session's owner, admins and clients presenting its control key
+/// may do so.
+pub async fn kill(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    peer: Option<Extension<ConnectInfo<Peer>>>,
+    identity: Option<Extension<Identity>>,
+) -> Response {
+    if !client_cert_allowed(&state, &peer) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let signal = match params.get("signal") {
+        None => Signal::SIGHUP,
+        Some(name) => match parse_signal(name) {
+            Some(signal) => signal,
+            None => {
+                return (StatusCode::BAD_REQUEST, format!("unknown signal {name:?}"))
+                    .into_response();
+            }
+        },
+    };
+    let peer = peer
+        .map(|Extension(ConnectInfo(peer))| peer)
+        .unwrap_or_default();
+    let identity = identity
+        .map_or(Identity::Anonymous, |Extension(identity)| identity)
+        .or_certificate(&peer);
+    // Other users' sessions look like missing ones, so they cannot be probed.
+    let Some(session) = state.sessions.get(&id).filter(|session| {
+        state.can_manage(&identity, session)
+            || params
+                .get("key")
+                .is_some_and(|key| session.authorize(key) == Some(Access::Control))
+    }) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let user_name = identity.name();
+    let id = session.id();
+    if state.sessions.remove(id).is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Some(audit) = &state.audit {
+        audit.record(&AuditEvent {
+            client: peer.addr,
+            user: user_name.as_deref(),
+            ..AuditEvent::new(AuditKind::Kill, id)
+        });
+    }
+    tracing::info!(
+        user = user_name.as_deref().unwrap_or("-"),
+        client = ?peer.ip(),
+        %signal,
+        "killed session {id}"
+    );
+    if let Err(e) = session.kill(signal) {
+        tracing::warn!("failed to signal session {id}: {e}");
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{Access, DEFAULT_ORPHAN_TIMEOUT, ScrollbackEvent, SessionStore};
+    use crate::terminal::Terminal;
+
+    #[tokio::test]
+    async fn test_session_info() {
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let session = Session::new(terminal, output_rx, 1024, DEFAULT_ORPHAN_TIMEOUT);
+        session.set_window_size(40, 120);
+        let (_sb1, _rx1, _ws1) = session.attach_as(Access::Control);
+        let (_sb2, _rx2, _ws2) = session.attach_as(Access::View);
+        let (_sb3, _rx3, _ws3) = session.attach_as(Access::View);
+
+        let info = serde_json::to_value(SessionInfo::new(&session)).unwrap();
+        assert_eq!(info["id"], session.id());
+        assert_eq!(info["command"], "/bin/sh");
+        assert!(info["pid"].as_u64().is_some());
+        assert_eq!(info["clients"]["interactive"], 1);
+        assert_eq!(info["clients"]["view"], 2);
+        assert_eq!(info["window_size"]["rows"], 40);
+        assert_eq!(info["window_size"]["cols"], 120);
+        assert!(info["scrollback_bytes"].as_u64().unwrap() >= 4);
+        assert_eq!(info["spooled_bytes"], 0);
+        assert_eq!(info["exited"], false);
+        assert!(info["created"].as_str().unwrap().ends_with('Z'));
+        assert!(info["name"].is_null());
+        assert!(info.get("control_key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let options =
+            SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT).with_owner(Some("alice".to_owned()));
+        let session = Session::with_options(terminal, output_rx, options);
+        assert!(sessions.insert(session).is_ok());
+        let alice = || Some(Extension(Identity::User("alice".to_owned())));
+
+        let response = list(State(state.clone()), None, alice()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["sessions"].as_array().unwrap().len(), 1);
+
+        // Nobody owns sessions created without a user name.
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let session = Session::new(terminal, output_rx, 1024, DEFAULT_ORPHAN_TIMEOUT);
+        assert!(sessions.insert(session).is_ok());
+        let response = list(State(state.clone()), None, None).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["sessions"].as_array().unwrap().len(), 0);
+
+        let state = state.with_client_cert_allow([crate::web::peer::CertPattern::Name(
+            crate::web::peer::CertName::Cn("alice".to_owned()),
+        )]);
+        let response = list(State(state), None, alice()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_list_only_own_sessions() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_admins(["root".to_owned()]);
+        for owner in ["alice", "bob"] {
+            let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+            let options = SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT)
+                .with_owner(Some(owner.to_owned()));
+            assert!(
+                sessions
+                    .insert(Session::with_options(terminal, output_rx, options))
+                    .is_ok()
+            );
+        }
+
+        let list_as = async |user: Option<&str>| {
+            let identity = user.map(|user| Extension(Identity::User(user.to_owned())));
+            let response = list(State(state.clone()), None, identity).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["sessions"].as_array().unwrap().len()
+        };
+        assert_eq!(list_as(Some("bob")).await, 1);
+        assert_eq!(list_as(Some("carol")).await, 0);
+        assert_eq!(list_as(None).await, 0);
+        assert_eq!(list_as(Some("root")).await, 2);
+    }
+
+    fn create_request(body: serde_json::Value) -> CreateRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/bash".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_api_commands(["/bin/sh".to_owned()])
+        .with_api_env(["GREETING".to_owned()]);
+        let request = create_request(serde_json::json!({
+            "command": "/bin/sh",
+            "args": ["-c", "echo \"$GREETING from $PWD\"; sleep 5"],
+            "cwd": "/tmp",
+            "env": {"GREETING": "hello"},
+            "rows": 40,
+            "cols": 120,
+            "orphan_timeout": 600,
+        }));
+        let response = create(State(state), None, None, Json(request)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let id = body["id"].as_str().unwrap();
+        let session = sessions.get(id).expect("session is registered");
+        assert_eq!(body["control_key"], session.control_key());
+        assert_eq!(body["view_key"], session.view_key());
+        assert_eq!(
+            body["urls"]["control"],
+            format!("/?sid={id}&key={}", session.control_key())
+        );
+        assert_eq!(
+            body["urls"]["view"],
+            format!("/?sid={id}&key={}&view", session.view_key())
+        );
+        assert_eq!(session.terminal.command(), "/bin/sh");
+        assert_eq!(session.window_size(), (40, 120));
+        assert_eq!(session.client_count(), 0);
+
+        // The shell may have echoed before we attach: start from the replay.
+        let (sb, mut rx, _ws) = session.attach();
+        let mut output = Vec::new();
+        for event in sb {
+            if let ScrollbackEvent::Output(data) = event {
+                output.extend_from_slice(&data);
+            }
+        }
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !String::from_utf8_lossy(&output).contains("hello from /tmp") {
+                match rx.recv().await {
+                    Ok(data) => output.extend_from_slice(&data),
+                    Err(_) => break,
+                }
+            }
+        })
+        .await;
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("hello from /tmp"), "output: {output:?}");
+    }
+
+    #[tokio::test]
+    async fn test_start_refuses_unlisted_run_as_claim() {
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            SessionStore::new(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let identity = Identity::Jwt(Arc::new(crate::web::jwt::JwtClaims {
+            name: Some("mallory".to_owned()),
+            run_as: Some("root".to_owned()),
+            profile: None,
+            claims: serde_json::Map::new(),
+        }));
+        let result = start(&state, &identity, &Peer::default(), &NewSession::default());
+        assert!(matches!(result, Err(StartError::Forbidden(_))));
+        assert!(state.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_start_profile_selection() {
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            SessionStore::new(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_limits(crate::limits::LimitProfiles {
+            profiles: HashMap::from([("build".to_owned(), "nice=5".parse().unwrap())]),
+            users: HashMap::from([("alice".to_owned(), vec!["build".to_owned()])]),
+            ..Default::default()
+        });
+        let spec = NewSession {
+            profile: Some("build".to_owned()),
+            ..NewSession::default()
+        };
+        let start_as =
+            |identity: &Identity| start(&state, identity, &Peer::default(), &spec).map(|_| ());
+        assert!(matches!(
+            start_as(&Identity::Token),
+            Err(StartError::Forbidden(_))
+        ));
+        assert!(matches!(
+            start_as(&Identity::User("bob".to_owned())),
+            Err(StartError::Forbidden(_))
+        ));
+        assert!(start_as(&Identity::User("alice".to_owned())).is_ok());
+        assert_eq!(state.sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_api_env(["LANG".to_owned()]);
+        let cases = [
+            (
+                serde_json::json!({"command": "/bin/bash"}),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                serde_json::json!({"env": {"PATH": "/tmp"}}),
+                StatusCode::FORBIDDEN,
+            ),
+            (serde_json::json!({"cwd": "tmp"}), StatusCode::BAD_REQUEST),
+            (serde_json::json!({"rows": 0}), StatusCode::BAD_REQUEST),
+            (
+                serde_json::json!({"orphan_timeout": 0}),
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for (body, status) in cases {
+            let request = create_request(body.clone());
+            let response = create(State(state.clone()), None, None, Json(request)).await;
+            assert_eq!(response.status(), status, "{body}");
+        }
+        assert!(sessions.list().is_empty());
+        assert!(
+            serde_json::from_value::<CreateRequest>(serde_json::json!({"shell": "x"})).is_err()
+        );
+
+        let request = create_request(serde_json::json!({"env": {"LANG": "C.UTF-8"}, "rows": 50}));
+        let response = create(State(state), None, None, Json(request)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(sessions.list()[0].window_size(), (50, 80));
+    }
+
+    #[tokio::test]
+    async fn test_create_named() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let alice = || Some(Extension(Identity::User("alice".to_owned())));
+        let create_named = |name: &str| {
+            let request = create_request(serde_json::json!({"name": name}));
+            create(State(state.clone()), None, alice(), Json(request))
+        };
+        let response = create_named("build").await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["urls"]["view"],
+            format!(
+                "/?session=build&key={}&view",
+                body["view_key"].as_str().unwrap()
+            )
+        );
+        assert_eq!(create_named("build").await.status(), StatusCode::CONFLICT);
+        assert_eq!(create_named("a/b").await.status(), StatusCode::BAD_REQUEST);
+
+        let response = list(State(state.clone()), None, alice()).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["sessions"][0]["name"], "build");
+
+        let response = kill(
+            State(state.clone()),
+            Path("build".to_owned()),
+            Query(HashMap::new()),
+            None,
+            alice(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("TERM"), Some(Signal::SIGTERM));
+        assert_eq!(parse_signal("SIGKILL"), Some(Signal::SIGKILL));
+        assert_eq!(parse_signal("HUP"), Some(Signal::SIGHUP));
+        assert_eq!(parse_signal("INT"), Some(Signal::SIGINT));
+        assert_eq!(parse_signal("STOP"), None);
+        assert_eq!(parse_signal("term"), None);
+    }
+
+    #[tokio::test]
+    async fn test_kill() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let options =
+            SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT).with_owner(Some("alice".to_owned()));
+        let session = Session::with_options(terminal, output_rx, options);
+        let id = session.id().to_owned();
+        let mut closed = session.terminal.closed();
+        assert!(sessions.insert(session).is_ok());
+
+        let kill = |id: &str, signal: Option<&str>| {
+            let params = signal
+                .map(|s| HashMap::from([("signal".to_owned(), s.to_owned())]))
+                .unwrap_or_default();
+            kill(
+                State(state.clone()),
+                Path(id.to_owned()),
+                Query(params),
+                None,
+                Some(Extension(Identity::User("alice".to_owned()))),
+            )
+        };
+        assert_eq!(
+            kill(&id, Some("STOP")).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert!(sessions.get(&id).is_some(), "bad request keeps the session");
+        assert_eq!(
+            kill(&id, Some("KILL")).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(sessions.get(&id).is_none());
+        let result =
+            tokio::time::timeout(std::time::Duration::from_secs(10), closed.wait_for(|&v| v)).await;
+        assert!(result.is_ok(), "shell should be killed");
+        assert_eq!(kill(&id, None).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_kill_requires_owner() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_admins(["root".to_owned()]);
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let options =
+            SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT).with_owner(Some("alice".to_owned()));
+        let session = Session::with_options(terminal, output_rx, options);
+        let id = session.id().to_owned();
+        assert!(sessions.insert(session).is_ok());
+
+        let kill_as = |user: Option<&str>| {
+            kill(
+                State(state.clone()),
+                Path(id.clone()),
+                Query(HashMap::new()),
+                None,
+                user.map(|user| Extension(Identity::User(user.to_owned()))),
+            )
+        };
+        assert_eq!(kill_as(Some("bob")).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(kill_as(None).await.status(), StatusCode::NOT_FOUND);
+        assert!(
+            sessions.get(&id).is_some(),
+            "bob cannot kill alice's session"
+        );
+        assert_eq!(kill_as(Some("root")).await.status(), StatusCode::NO_CONTENT);
+        assert!(sessions.get(&id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_kill_admin_by_credential_kind() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        )
+        .with_admins(["root".to_owned()]);
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let options =
+            SessionOptions::new(1024, DEFAULT_ORPHAN_TIMEOUT).with_owner(Some("alice".to_owned()));
+        let session = Session::with_options(terminal, output_rx, options);
+        let id = session.id().to_owned();
+        assert!(sessions.insert(session).is_ok());
+
+        let jwt = Identity::Jwt(Arc::new(crate::web::jwt::JwtClaims {
+            name: Some("root".to_owned()),
+            run_as: None,
+            profile: None,
+            claims: serde_json::Map::new(),
+        }));
+        let cert = Identity::Certificate(Arc::new(crate::web::peer::ClientCert {
+            subject: "CN=root".to_owned(),
+            names: vec![crate::web::peer::CertName::Cn("root".to_owned())],
+        }));
+        for identity in [jwt, cert] {
+            let response = kill(
+                State(state.clone()),
+                Path(id.clone()),
+                Query(HashMap::new()),
+                None,
+                Some(Extension(identity)),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        assert!(
+            sessions.get(&id).is_some(),
+            "only the htpasswd user root is an admin"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kill_without_owner_needs_key() {
+        let sessions = SessionStore::new();
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            sessions.clone(),
+            DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let (terminal, output_rx) = Terminal::spawn("/bin/sh", None).expect("spawn");
+        let session = Session::new(terminal, output_rx, 1024, DEFAULT_ORPHAN_TIMEOUT);
+        let id = session.id().to_owned();
+        let view_key = session.view_key().to_owned();
+        let control_key = session.control_key().to_owned();
+        assert!(sessions.insert(session).is_ok());
+
+        let kill_with = |key: Option<&str>| {
+            let params = key
+                .map(|key| HashMap::from([("key".to_owned(), key.to_owned())]))
+                .unwrap_or_default();
+            kill(
+                State(state.clone()),
+                Path(id.clone()),
+                Query(params),
+                None,
+                Some(Extension(Identity::Token)),
+            )
+        };
+        assert_eq!(kill_with(None).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            kill_with(Some(&view_key)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert!(
+            sessions.get(&id).is_some(),
+            "the shared token is not enough"
+        );
+        assert_eq!(
+            kill_with(Some(&control_key)).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert!(sessions.get(&id).is_none());
+    }
+}
@@ -11,21 +11,30 @@
 //! | client → server | `0x01` | rows(u16 BE) + cols(u16 BE) | Resize |
 //! | server → client | `0x00` | raw bytes | Terminal output |
 //! | server → client | `0x10` | UUID string | Session ID |
+//! | server → client | `0x11` | control key + `:` + view key | Session keys (control clients only) |
 //! | server → client | `0x12` | — | Shell exited |
 //! | server → client | `0x13` | rows(u16 BE) + cols(u16 BE) | Window size |
 //! | server → client | `0x14` | — | Replay end |
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
+use axum::Extension;
 use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
-use axum::extract::{Query, State};
-use axum::response::IntoResponse;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
 use tokio::sync::broadcast::error::RecvError;
 
-use crate::session::{ScrollbackEvent, Session};
-use crate::terminal::Terminal;
+use crate::audit::{AuditEvent, AuditKind};
+use crate::filter::OutputFilter;
+use crate::session::{Access, ScrollbackEvent, Session, SessionNotice, is_valid_name};
 use crate::web::AppState;
+use crate::web::auth::Identity;
+use crate::web::limiter::Refusal;
+use crate::web::peer::Peer;
+use crate::web::sessions::{self, NewSession, StartError};
 
 /// Client → Server: terminal input.
 const CMD_INPUT: u8 = 0x00;
@@ -36,15 +45,31 @@ const CMD_RESIZE: u8 = 0x01;
 const CMD_OUTPUT: u8 = 0x00;
 /// Server → Client: session UUID string.
 const CMD_SESSION_ID: u8 = 0x10;
+/// Server → Client: session keys (`control_key:view_key`), sent to control clients only.
+const CMD_SESSION_KEYS: u8 = 0x11;
 /// Server → Client: shell process exited.
 const CMD_SHELL_EXIT: u8 = 0x12;
 /// Server → Client: current PTY window size (4-byte payload: rows u16 BE, cols u16 BE).
 const CMD_WINDOW_SIZE: u8 = 0x13;
 /// Server → Client: end of scrollback replay.
 const CMD_REPLAY_END: u8 = 0x14;
+/// Server → Client: idle warning (4-byte payload: seconds until the idle action, u32 BE).
+const CMD_IDLE_WARNING: u8 = 0x15;
+/// Server → Client: lifetime warning (4-byte payload: seconds until the session ends, u32 BE).
+const CMD_LIFETIME_WARNING: u8 = 0x16;
 
-/// WebSocket close code: requested session not found.
+/// WebSocket close code: missing or invalid credentials.
+const CLOSE_UNAUTHORIZED: u16 = 4401;
+/// WebSocket close code: authenticated, but not permitted to open a session.
+const CLOSE_FORBIDDEN: u16 = 4403;
+/// WebSocket close code: requested session not found (or wrong key).
 const CLOSE_SESSION_NOT_FOUND: u16 = 4404;
+/// WebSocket close code: disconnected after the session's idle timeout.
+const CLOSE_IDLE_TIMEOUT: u16 = 4408;
+/// WebSocket close code: session limit or creation rate limit reached.
+const CLOSE_TOO_MANY_SESSIONS: u16 = 4429;
+/// WebSocket close code: the session moved to a new server process.
+const CLOSE_RESTART: u16 = axum::extract::ws::close_code::RESTART;
 
 /// Send a protocol frame (command byte + payload) over the WebSocket.
 async fn send_frame(socket: &mut WebSocket, cmd: u8, payload: &[u8]) -> Result<(), ()> {
@@ -57,6 +82,33 @@ async fn send_frame(socket: &mut WebSocket, cmd: u8, payload: &[u8]) -> Result<(
         .map_err(|_| ())
 }
 
+/// Send terminal output, passing it through `filter` first if there is one.
+/// Nothing is sent if the filter removed everything.
+async fn send_output(
+    socket: &mut WebSocket,
+    filter: &mut Option<OutputFilter>,
+    data: &[u8],
+) -> Result<(), ()> {
+    let Some(filter) = filter else {
+        return send_frame(socket, CMD_OUTPUT, data).await;
+    };
+    let data = filter.filter(data);
+    if data.is_empty() {
+        return Ok(());
+    }
+    send_frame(socket, CMD_OUTPUT, &data).await
+}
+
+/// Send a close frame with an application-specific code.
+async fn close(socket: &mut WebSocket, code: u16, reason: &'static str) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
 /// Encode a window size as 4 big-endian bytes (rows, cols).
 fn encode_window_size(rows: u16, cols: u16) -> [u8; 4] {
     let r = rows.to_be_bytes();
@@ -64,38 +116,130 @@ fn encode_window_size(rows: u16, cols: u16) -> [u8; 4] {
     [r[0], r[1], c[0], c[1]]
 }
 
+/// Encode a duration as whole seconds (rounded up), 4 bytes big-endian.
+fn encode_secs(duration: Duration) -> [u8; 4] {
+    let secs = duration
+        .as_secs()
+        .saturating_add(u64::from(duration.subsec_nanos() > 0));
+    u32::try_from(secs).unwrap_or(u32::MAX).to_be_bytes()
+}
+
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
+    peer: Option<Extension<ConnectInfo<Peer>>>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    let sid = params.get("sid").cloned();
-    let readonly = params.contains_key("view");
-    ws.on_upgrade(move |socket| handle_socket(socket, state, sid, readonly))
+) -> Response {
+    if !state.origins.allows(&headers) {
+        tracing::warn!(
+            origin = ?headers.get(axum::http::header::ORIGIN),
+            "rejected cross-origin websocket"
+        );
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let identity = match state.auth.authenticate(&headers, &params).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::warn!("rejected unauthenticated websocket: {e:?}");
+            return ws.on_upgrade(|mut socket| async move {
+                close(&mut socket, CLOSE_UNAUTHORIZED, "unauthorized").await;
+            });
+        }
+    };
+    let request = SessionRequest::from_query(&params);
+    let peer = peer
+        .map(|Extension(ConnectInfo(peer))| peer)
+        .unwrap_or_default();
+    let identity = identity.or_certificate(&peer);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, identity, peer, request))
+        .into_response()
+}
+
+/// Session selection from the `/ws` query string.
+#[derive(Debug, Default)]
+struct SessionRequest {
+    /// Existing session to attach to (`sid`); a new one is created if absent.
+    sid: Option<String>,
+    /// Named session to attach to, or to create if no session has this name
+    /// (`session`).
+    name: Option<String>,
+    /// Control or view key for `sid` or `session` (`key`).
+    key: String,
+    /// Limit profile for a new session (`profile`).
+    profile: Option<String>,
+    /// Idle timeout for a new session in seconds (`idle_timeout`); can only
+    /// shorten the configured one.
+    idle_timeout: Option<Duration>,
+}
+
+impl SessionRequest {
+    fn from_query(params: &HashMap<String, String>) -> Self {
+        Self {
+            sid: params.get("sid").cloned(),
+            name: params.get("session").cloned(),
+            key: params.get("key").cloned().unwrap_or_default(),
+            profile: params.get("profile").cloned(),
+            idle_timeout: params
+                .get("idle_timeout")
+                .and_then(|secs| secs.parse().ok())
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+        }
+    }
 }
 
 enum ResolveError {
     NotFound(String),
+    Forbidden(String),
+    Limited(Refusal),
     Io(std::io::Error),
 }
 
+impl From<StartError> for ResolveError {
+    fn from(e: StartError) -> Self {
+        match e {
+            StartError::Forbidden(reason) => Self::Forbidden(reason),
+            StartError::Limited(refusal) => Self::Limited(refusal),
+            StartError::NameTaken(name) => Self::NotFound(name),
+            StartError::Io(e) => Self::Io(e),
+        }
+    }
+}
+
 async fn handle_socket(
     mut socket: WebSocket,
     state: AppState,
-    sid: Option<String>,
-    readonly: bool,
+    identity: Identity,
+    peer: Peer,
+    request: SessionRequest,
 ) {
+    if !state.allows_client_cert(&peer) {
+        tracing::warn!(
+            client = ?peer.ip(),
+            subject = peer.client_cert.as_ref().map(|cert| cert.subject.as_str()),
+            "client certificate not in allow-list"
+        );
+        close(&mut socket, CLOSE_FORBIDDEN, "forbidden").await;
+        return;
+    }
+
     // Resolve or create session
-    let session = match resolve_session(&state, sid.as_deref()) {
+    let (session, access) = match resolve_session(&state, &identity, &peer, &request) {
         Ok(result) => result,
         Err(ResolveError::NotFound(id)) => {
             tracing::warn!("session {id} not found");
-            let _ = socket
-                .send(Message::Close(Some(CloseFrame {
-                    code: CLOSE_SESSION_NOT_FOUND,
-                    reason: "session not found".into(),
-                })))
-                .await;
+            close(&mut socket, CLOSE_SESSION_NOT_FOUND, "session not found").await;
+            return;
+        }
+        Err(ResolveError::Forbidden(reason)) => {
+            tracing::warn!("refused to create session: {reason}");
+            close(&mut socket, CLOSE_FORBIDDEN, "forbidden").await;
+            return;
+        }
+        Err(ResolveError::Limited(refusal)) => {
+            tracing::warn!(client = ?peer.ip(), "refused to create session: {refusal}");
+            close(&mut socket, CLOSE_TOO_MANY_SESSIONS, refusal.reason()).await;
             return;
         }
         Err(ResolveError::Io(e)) => {
@@ -104,67 +248,144 @@ async fn handle_socket(
         }
     };
 
-    handle_session(&mut socket, &session, readonly).await;
+    let client = ClientInfo {
+        access,
+        peer,
+        identity,
+    };
+    handle_client(&mut socket, &session, &client).await;
+}
+
+/// A WebSocket client attached to a session.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    /// What the client may do.
+    pub access: Access,
+    /// Where the client connected from.
+    pub peer: Peer,
+    /// Who the client authenticated as.
+    pub identity: Identity,
+}
+
+impl ClientInfo {
+    /// An anonymous client with unknown address.
+    pub fn new(access: Access) -> Self {
+        Self {
+            access,
+            peer: Peer::default(),
+            identity: Identity::Anonymous,
+        }
+    }
+
+    /// Record an event by this client in the session's audit log, if any.
+    fn audit(&self, session: &Session, kind: AuditKind, data: Option<&[u8]>) {
+        if let Some(audit) = session.audit_log() {
+            audit.record(&AuditEvent {
+                client: self.peer.addr,
+                user: self.identity.name().as_deref(),
+                access: Some(self.access),
+                data,
+                ..AuditEvent::new(kind, session.id())
+            });
+        }
+    }
 }
 
 /// Drive the tty-web binary protocol on an already-resolved session.
 ///
-/// Performs the full handshake (session ID → window size → scrollback replay →
-/// replay-end marker), then bridges WebSocket I/O with the terminal until the
-/// client disconnects or the shell exits. Calls [`Session::attach`] /
-/// [`Session::detach`] automatically.
+/// Performs the full handshake (session ID → session keys → window size →
+/// scrollback replay → replay-end marker), then bridges WebSocket I/O with the
+/// terminal until the client disconnects or the shell exits. Calls
+/// [`Session::attach`] / [`Session::detach`] automatically.
+///
+/// `access` is enforced strictly: [`Access::View`] clients never receive the
+/// session keys and all their input is discarded.
 ///
 /// This is the main building block for embedding tty-web in other applications
-/// that manage session creation themselves.
-pub async fn handle_session(socket: &mut WebSocket, session: &Arc<Session>, readonly: bool) {
-    // Handshake: session ID → window size → replay events → replay end
+/// that manage session creation themselves. Use [`handle_client`] to include
+/// the client's address and identity in the audit log.
+pub async fn handle_session(socket: &mut WebSocket, session: &Arc<Session>, access: Access) {
+    handle_client(socket, session, &ClientInfo::new(access)).await;
+}
+
+/// Like [`handle_session`], for a known client: attach, detach and every
+/// input payload are recorded in the session's audit log.
+pub async fn handle_client(socket: &mut WebSocket, session: &Arc<Session>, client: &ClientInfo) {
+    client.audit(session, AuditKind::Attach, None);
+    run_session(socket, session, client).await;
+    client.audit(session, AuditKind::Detach, None);
+}
+
+async fn run_session(socket: &mut WebSocket, session: &Arc<Session>, client: &ClientInfo) {
+    let readonly = client.access == Access::View;
+
+    // Handshake: session ID → keys → window size → replay events → replay end
     if send_frame(socket, CMD_SESSION_ID, session.id().as_bytes())
         .await
         .is_err()
     {
         return;
     }
+    if !readonly {
+        let keys = format!("{}:{}", session.control_key(), session.view_key());
+        if send_frame(socket, CMD_SESSION_KEYS, keys.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
 
-    let (events, mut output_rx, mut window_size_rx) = session.attach();
+    let (events, mut output_rx, mut window_size_rx) = session.attach_as(client.access);
+    let mut notice_rx = session.notices();
+    let mut viewer_filter = if readonly {
+        OutputFilter::for_viewer(session.terminal.output_policy().clone())
+    } else {
+        None
+    };
 
     let (rows, cols) = *window_size_rx.borrow_and_update();
     if send_frame(socket, CMD_WINDOW_SIZE, &encode_window_size(rows, cols))
         .await
         .is_err()
     {
-        session.detach();
+        session.detach_as(client.access);
         return;
     }
 
-    // Replay scrollback events
-    for event in &events {
+    // Replay scrollback events, read from the spool off the runtime
+    let mut events = events.stream();
+    while let Some(event) = events.recv().await {
         let ok = match event {
-            ScrollbackEvent::Output(data) => send_frame(socket, CMD_OUTPUT, data).await.is_ok(),
+            ScrollbackEvent::Output(data) => {
+                send_output(socket, &mut viewer_filter, &data).await.is_ok()
+            }
             ScrollbackEvent::WindowSize(r, c) => {
-                send_frame(socket, CMD_WINDOW_SIZE, &encode_window_size(*r, *c))
+                send_frame(socket, CMD_WINDOW_SIZE, &encode_window_size(r, c))
                     .await
                     .is_ok()
             }
         };
         if !ok {
-            session.detach();
+            session.detach_as(client.access);
             return;
         }
     }
 
     if send_frame(socket, CMD_REPLAY_END, &[]).await.is_err() {
-        session.detach();
+        session.detach_as(client.access);
         return;
     }
 
     // Main loop: bridge WebSocket ↔ session
     let mut closed_rx = session.terminal.closed();
+    let mut shell_exited = false;
     loop {
         tokio::select! {
             result = output_rx.recv() => {
                 match result {
                     Ok(data) => {
-                        if send_frame(socket, CMD_OUTPUT, &data).await.is_err() {
+                        if send_output(socket, &mut viewer_filter, &data).await.is_err() {
                             break;
                         }
                     }
@@ -181,7 +402,7 @@ pub async fn handle_session(socket: &mut WebSocket, session: &Arc<Session>, read
                         if readonly || data.is_empty() {
                             continue;
                         }
-                        handle_client_message(session, &data).await;
+                        handle_client_message(session, client, &data).await;
                     }
                     Some(Ok(Message::Close(_))) | None => break,
                     _ => {}
@@ -193,40 +414,110 @@ pub async fn handle_session(socket: &mut WebSocket, session: &Arc<Session>, read
                     break;
                 }
             }
-            _ = closed_rx.changed() => {
-                // Drain buffered output before sending exit
-                while let Ok(data) = output_rx.try_recv() {
-                    if send_frame(socket, CMD_OUTPUT, &data).await.is_err() {
+            Ok(notice) = notice_rx.recv() => {
+                match notice {
+                    SessionNotice::IdleWarning(remaining) => {
+                        if send_frame(socket, CMD_IDLE_WARNING, &encode_secs(remaining)).await.is_err() {
+                            break;
+                        }
+                    }
+                    SessionNotice::LifetimeWarning(remaining) => {
+                        if send_frame(socket, CMD_LIFETIME_WARNING, &encode_secs(remaining)).await.is_err() {
+                            break;
+                        }
+                    }
+                    SessionNotice::Killed => {
+                        shell_exited = true;
+                        break;
+                    }
+                    SessionNotice::IdleDetach => {
+                        close(socket, CLOSE_IDLE_TIMEOUT, "idle timeout").await;
+                        break;
+                    }
+                    SessionNotice::HandedOff => {
+                        close(socket, CLOSE_RESTART, "server restarting").await;
                         break;
                     }
                 }
-                let _ = send_frame(socket, CMD_SHELL_EXIT, &[]).await;
+            }
+            _ = closed_rx.changed() => {
+                shell_exited = true;
                 break;
             }
         }
     }
-    session.detach();
+    if shell_exited {
+        // Drain buffered output before sending exit
+        while let Ok(data) = output_rx.try_recv() {
+            if send_output(socket, &mut viewer_filter, &data)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = send_frame(socket, CMD_SHELL_EXIT, &[]).await;
+    }
+    session.detach_as(client.access);
 }
 
-fn resolve_session(state: &AppState, sid: Option<&str>) -> Result<Arc<Session>, ResolveError> {
-    if let Some(sid) = sid {
+/// Find the session for `sid` and check `key`, or create a new session (with
+/// control access and the requested limit profile) when no `sid` is given.
+///
+/// With `session`, the named session is attached to (its owner needs no key)
+/// or created under that name. A wrong key is reported as
+/// [`ResolveError::NotFound`] so that clients cannot probe which session IDs
+/// or names exist. New sessions are subject to the
+/// [session limits](crate::web::limiter).
+fn resolve_session(
+    state: &AppState,
+    identity: &Identity,
+    peer: &Peer,
+    request: &SessionRequest,
+) -> Result<(Arc<Session>, Access), ResolveError> {
+    let user_name = identity.name();
+    let user = user_name.as_deref().unwrap_or("-");
+    if let Some(sid) = request.sid.as_deref() {
         return state
             .sessions
             .get(sid)
-            .inspect(|_| tracing::info!("reattaching to session {sid}"))
+            .and_then(|session| {
+                session
+                    .authorize(&request.key)
+                    .map(|access| (session, access))
+            })
+            .inspect(|(_, access)| tracing::info!(user, ?access, "reattaching to session {sid}"))
             .ok_or_else(|| ResolveError::NotFound(sid.to_owned()));
     }
-    let (terminal, output_rx) =
-        Terminal::spawn(&state.shell, state.pwd.as_deref()).map_err(ResolveError::Io)?;
-    let session = Session::new(
-        terminal,
-        output_rx,
-        state.scrollback_limit,
-        state.orphan_timeout,
-    );
-    tracing::info!("created new session {}", session.id());
-    state.sessions.insert(session.clone());
-    Ok(session)
+    if let Some(name) = request.name.as_deref() {
+        if let Some(session) = state.sessions.get(name) {
+            // Only a name, not a (non-secret) session ID, lets the owner in
+            // without a key.
+            let owner = session.name() == Some(name) && session.is_owned_by(user_name.as_deref());
+            let access = session
+                .authorize(&request.key)
+                .or_else(|| owner.then_some(Access::Control));
+            return access
+                .map(|access| (session, access))
+                .inspect(|(_, access)| {
+                    tracing::info!(user, ?access, "reattaching to session {name}")
+                })
+                .ok_or_else(|| ResolveError::NotFound(name.to_owned()));
+        }
+        if !is_valid_name(name) {
+            return Err(ResolveError::Forbidden(format!(
+                "invalid session name {name:?}"
+            )));
+        }
+    }
+    let spec = NewSession {
+        name: request.name.clone(),
+        profile: request.profile.clone(),
+        idle_timeout: request.idle_timeout,
+        ..NewSession::default()
+    };
+    let session = sessions::start(state, identity, peer, &spec)?;
+    Ok((session, Access::Control))
 }
 
 #[derive(Debug, PartialEq)]
@@ -250,9 +541,11 @@ fn parse_client_message(data: &[u8]) -> Option<ClientCommand<'_>> {
     }
 }
 
-async fn handle_client_message(session: &Session, data: &[u8]) {
+async fn handle_client_message(session: &Session, client: &ClientInfo, data: &[u8]) {
     match parse_client_message(data) {
         Some(ClientCommand::Input(payload)) => {
+            client.audit(session, AuditKind::Input, Some(payload));
+            session.record_input();
             if let Err(e) = session.terminal.write(payload.to_vec()).await {
                 tracing::error!("write to terminal failed: {e}");
             }
@@ -274,6 +567,105 @@ async fn handle_client_message(session: &Session, data: &[u8]) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encode_secs() {
+        assert_eq!(encode_secs(Duration::from_secs(300)), [0, 0, 1, 44]);
+        assert_eq!(encode_secs(Duration::from_millis(1500)), [0, 0, 0, 2]);
+        assert_eq!(encode_secs(Duration::ZERO), [0, 0, 0, 0]);
+        assert_eq!(encode_secs(Duration::MAX), [0xFF; 4]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_named_session() {
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            crate::session::SessionStore::new(),
+            crate::session::DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let alice = Identity::User("alice".to_owned());
+        let bob = Identity::User("bob".to_owned());
+        let named = |name: &str, key: &str| SessionRequest {
+            name: Some(name.to_owned()),
+            key: key.to_owned(),
+            ..SessionRequest::default()
+        };
+        let resolve = |identity: &Identity, request: &SessionRequest| {
+            resolve_session(&state, identity, &Peer::default(), request)
+        };
+
+        let Ok((session, Access::Control)) = resolve(&alice, &named("build", "")) else {
+            panic!("named session should be created");
+        };
+        assert_eq!(session.name(), Some("build"));
+        assert_eq!(state.sessions.len(), 1);
+
+        // The owner reattaches by name alone; others need a key.
+        let Ok((again, Access::Control)) = resolve(&alice, &named("build", "")) else {
+            panic!("owner should reattach");
+        };
+        assert_eq!(again.id(), session.id());
+        assert!(matches!(
+            resolve(&bob, &named("build", "")),
+            Err(ResolveError::NotFound(_))
+        ));
+        assert!(matches!(
+            resolve(&bob, &named("build", session.view_key())),
+            Ok((_, Access::View))
+        ));
+        // The ID is not a name: it still needs a key.
+        assert!(matches!(
+            resolve(&alice, &named(session.id(), "")),
+            Err(ResolveError::NotFound(_))
+        ));
+        // Names also work in place of `sid`.
+        let by_sid = SessionRequest {
+            sid: Some("build".to_owned()),
+            key: session.control_key().to_owned(),
+            ..SessionRequest::default()
+        };
+        assert!(matches!(resolve(&bob, &by_sid), Ok((_, Access::Control))));
+
+        assert!(matches!(
+            resolve(&alice, &named("no spaces", "")),
+            Err(ResolveError::Forbidden(_))
+        ));
+        assert_eq!(state.sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_named_session_without_owner() {
+        let state = AppState::new(
+            "/bin/sh".to_owned(),
+            None,
+            1024,
+            crate::session::SessionStore::new(),
+            crate::session::DEFAULT_ORPHAN_TIMEOUT,
+        );
+        let named = |key: &str| SessionRequest {
+            name: Some("build".to_owned()),
+            key: key.to_owned(),
+            ..SessionRequest::default()
+        };
+        let resolve = |request: &SessionRequest| {
+            resolve_session(&state, &Identity::Token, &Peer::default(), request)
+        };
+
+        // Shared-token clients have no user name, so nobody owns the session.
+        let Ok((session, Access::Control)) = resolve(&named("")) else {
+            panic!("named session should be created");
+        };
+        assert!(matches!(
+            resolve(&named("")),
+            Err(ResolveError::NotFound(_))
+        ));
+        assert!(matches!(
+            resolve(&named(session.control_key())),
+            Ok((_, Access::Control))
+        ));
+    }
+
     #[test]
     fn test_parse_input() {
         let data = [0x00, b'h', b'i'];